@@ -24,7 +24,7 @@ impl Pixel {
     fn to_renderpixel(&self) -> graphics::PrimitiveVertex {
         graphics::PrimitiveVertex {
             position: [self.x, self.y],
-            color: [self.r, self.g, self.b, self.a],
+            color: graphics::Color::srgb(self.r, self.g, self.b, self.a),
         }
     }
 
@@ -124,18 +124,17 @@ fn run() {
                         .collect();
 
                     let scene = graphics::Scene {
-                        rectangles: Vec::new(),
-                        sprites: Vec::new(),
                         pixels: renderpixels,
-                        linestrips: Vec::new(),
                         circles: vec![graphics::Circle {
                             offset: [24, 24],
                             diameter: 13,
-                            color: [1.0, 1.0, 1.0, 1.0],
+                            color: graphics::Color::WHITE,
+                            blend: graphics::BlendMode::Alpha,
                         }],
+                        ..Default::default()
                     };
                     renderer
-                        .render(&sprite_sheet, &scene)
+                        .render(&[&sprite_sheet], &scene)
                         .expect("draw to screen");
                     window.request_redraw();
                 }
@@ -0,0 +1,153 @@
+use std::{cell::OnceCell, ops::Range, rc::Rc};
+
+use wgpu::{BlendComponent, BlendFactor, BlendOperation, BlendState};
+
+/// How an item's colors combine with what's already on the canvas.
+///
+/// Sprites, rectangles, circles, linestrips, tilemaps and material draws each have a mode.
+/// Pixels and text are always alpha blended.
+/// Modes other than [BlendMode::Alpha] and [BlendMode::Multiply] leave the canvas alpha as is.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, bytemuck::NoUninit)]
+pub enum BlendMode {
+    /// Cover the canvas by the item's alpha.
+    #[default]
+    Alpha = 0,
+    /// Add the item's color, for glows, fire and light.
+    Additive = 1,
+    /// Multiply the canvas by the item's color, for shadows and darkening overlays.
+    /// Transparent parts of the item leave the canvas unchanged.
+    Multiply = 2,
+    /// Brighten the canvas, like additive blending that never exceeds white.
+    Screen = 3,
+    /// Subtract the item's color from the canvas.
+    Subtract = 4,
+}
+
+impl BlendMode {
+    const COUNT: usize = 5;
+
+    /// Blend state for premultiplied colors.
+    fn state(self) -> BlendState {
+        let component = |src_factor, dst_factor, operation| BlendComponent {
+            src_factor,
+            dst_factor,
+            operation,
+        };
+        let keep_alpha = component(BlendFactor::Zero, BlendFactor::One, BlendOperation::Add);
+        match self {
+            BlendMode::Alpha => BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+            BlendMode::Additive => BlendState {
+                color: component(BlendFactor::One, BlendFactor::One, BlendOperation::Add),
+                alpha: keep_alpha,
+            },
+            BlendMode::Multiply => BlendState {
+                color: component(
+                    BlendFactor::Dst,
+                    BlendFactor::OneMinusSrcAlpha,
+                    BlendOperation::Add,
+                ),
+                alpha: BlendState::PREMULTIPLIED_ALPHA_BLENDING.alpha,
+            },
+            BlendMode::Screen => BlendState {
+                color: component(
+                    BlendFactor::OneMinusDst,
+                    BlendFactor::One,
+                    BlendOperation::Add,
+                ),
+                alpha: keep_alpha,
+            },
+            BlendMode::Subtract => BlendState {
+                color: component(
+                    BlendFactor::One,
+                    BlendFactor::One,
+                    BlendOperation::ReverseSubtract,
+                ),
+                alpha: keep_alpha,
+            },
+        }
+    }
+
+    /// Blend a premultiplied linear color onto a canvas color, as the GPU does.
+    pub(crate) fn apply(self, src: [f32; 4], dst: [f32; 4]) -> [f32; 4] {
+        let keep = 1.0 - src[3];
+        let color = |c: usize| match self {
+            BlendMode::Alpha => src[c] + dst[c] * keep,
+            BlendMode::Additive => src[c] + dst[c],
+            BlendMode::Multiply => src[c] * dst[c] + dst[c] * keep,
+            BlendMode::Screen => src[c] * (1.0 - dst[c]) + dst[c],
+            BlendMode::Subtract => dst[c] - src[c],
+        };
+        let alpha = match self {
+            BlendMode::Alpha | BlendMode::Multiply => src[3] + dst[3] * keep,
+            _ => dst[3],
+        };
+        [color(0), color(1), color(2), alpha].map(|c| c.clamp(0.0, 1.0))
+    }
+}
+
+/// Creates a pipeline from shaders, with a blend state.
+type CreatePipeline = dyn Fn(&wgpu::ShaderModule, BlendState) -> wgpu::RenderPipeline;
+
+/// A pipeline for each blend mode, all from the same shaders.
+/// Only the alpha blending pipeline is created up front, the others when first used.
+pub(crate) struct Pipelines {
+    module: Rc<wgpu::ShaderModule>,
+    create: Rc<CreatePipeline>,
+    variants: [OnceCell<wgpu::RenderPipeline>; BlendMode::COUNT],
+}
+
+impl Pipelines {
+    pub(crate) fn new(
+        module: Rc<wgpu::ShaderModule>,
+        create: impl Fn(&wgpu::ShaderModule, BlendState) -> wgpu::RenderPipeline + 'static,
+    ) -> Self {
+        let pipelines = Self {
+            module,
+            create: Rc::new(create),
+            variants: Default::default(),
+        };
+        pipelines.get(BlendMode::Alpha);
+        pipelines
+    }
+
+    pub(crate) fn get(&self, mode: BlendMode) -> &wgpu::RenderPipeline {
+        self.variants[mode as usize].get_or_init(|| (self.create)(&self.module, mode.state()))
+    }
+
+    /// The same pipelines from other shaders.
+    /// Variants already in use are created right away, so that errors show up immediately.
+    #[cfg(feature = "hot-reload")]
+    pub(crate) fn with_module(&self, module: Rc<wgpu::ShaderModule>) -> Self {
+        let pipelines = Self {
+            module,
+            create: self.create.clone(),
+            variants: Default::default(),
+        };
+        use BlendMode::*;
+        for mode in [Alpha, Additive, Multiply, Screen, Subtract] {
+            if self.variants[mode as usize].get().is_some() {
+                pipelines.get(mode);
+            }
+        }
+        pipelines
+    }
+}
+
+/// Split items into runs with the same blend mode, so each run is one draw call.
+pub(crate) fn runs<'a, T>(
+    items: &'a [T],
+    mode: impl Fn(&T) -> BlendMode + 'a,
+) -> impl Iterator<Item = (BlendMode, Range<u32>)> + 'a {
+    let mut start = 0;
+    std::iter::from_fn(move || {
+        let first = mode(items.get(start)?);
+        let len = items[start..]
+            .iter()
+            .take_while(|item| mode(item) == first)
+            .count();
+        let run = start as u32..(start + len) as u32;
+        start += len;
+        Some((first, run))
+    })
+}
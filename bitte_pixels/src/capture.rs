@@ -0,0 +1,221 @@
+//! Screenshots and short recordings of rendered frames.
+//!
+//! [crate::Renderer::capture_frame] copies a frame back from the GPU,
+//! either the canvas at its native resolution or the upscaled image as shown in the window.
+//! [Frame::save_png] encodes it on a background thread, so that saving doesn't stall rendering.
+//! A [Recorder] keeps the canvas frames of the last few seconds,
+//! which can be saved as an animated PNG for bug reports or sharing.
+//!
+//! Threads aren't available on the web, where frames can be encoded with [Frame::write_png]
+//! and [Recorder::write_apng] instead.
+//!
+//! ```ignore
+//! let mut recorder = Recorder::new(10.0, 30.0);
+//! // Every frame, after rendering:
+//! recorder.record(&renderer, time)?;
+//! if screenshot_pressed {
+//!     renderer.capture_frame(Capture::Upscaled)?.save_png("screenshot.png");
+//! }
+//! if clip_pressed {
+//!     recorder.save_apng("clip.png");
+//! }
+//! ```
+
+use std::{collections::VecDeque, io::Write, path::PathBuf, sync::Arc, thread::JoinHandle};
+
+use super::{RenderError, Renderer, Size};
+
+/// Which image [crate::Renderer::capture_frame] copies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Capture {
+    /// The canvas at the game resolution.
+    #[default]
+    Canvas,
+    /// The canvas upscaled to the size of the window,
+    /// or to the output size of a headless renderer.
+    Upscaled,
+}
+
+/// A captured image, in the same format as [crate::Renderer::read_canvas].
+#[derive(Clone)]
+pub struct Frame {
+    pub size: Size,
+    /// RGBA pixels in sRGB, top-to-bottom left-to-right.
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Io(PathBuf, std::io::Error),
+    Encoding(png::EncodingError),
+    /// A recording was saved before any frame was recorded.
+    NoFrames,
+}
+
+impl From<png::EncodingError> for CaptureError {
+    fn from(err: png::EncodingError) -> Self {
+        CaptureError::Encoding(err)
+    }
+}
+
+impl std::fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptureError::Io(path, err) => write!(f, "couldn't write {path:?}: {err}"),
+            CaptureError::Encoding(err) => write!(f, "couldn't encode PNG: {err}"),
+            CaptureError::NoFrames => f.write_str("nothing was recorded"),
+        }
+    }
+}
+
+impl Frame {
+    /// Encode the frame as a PNG image.
+    pub fn write_png<W: Write>(&self, writer: W) -> Result<(), CaptureError> {
+        let mut writer = encoder(writer, self.size).write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Save the frame as a PNG file on a background thread.
+    pub fn save_png(self, path: impl Into<PathBuf>) -> JoinHandle<Result<(), CaptureError>> {
+        let path = path.into();
+        std::thread::spawn(move || save(&path, |file| self.write_png(file)))
+    }
+}
+
+/// Keeps the canvas frames of the last few seconds.
+///
+/// Frames are stored uncompressed, so long recordings of large canvases take a lot of memory:
+/// 10 seconds of a 320x180 canvas at 30 frames per second take about 70 MB.
+pub struct Recorder {
+    duration: f32,
+    /// Shortest time between recorded frames.
+    interval: f32,
+    /// Frames with the time they were recorded at, oldest first.
+    frames: VecDeque<(f32, Arc<Frame>)>,
+}
+
+impl Recorder {
+    /// Record up to `duration` seconds, at up to `frame_rate` frames per second.
+    pub fn new(duration: f32, frame_rate: f32) -> Self {
+        Self {
+            duration,
+            interval: 1.0 / frame_rate,
+            frames: VecDeque::new(),
+        }
+    }
+
+    /// Capture the canvas as drawn by the last call to [crate::Renderer::render],
+    /// unless a frame was recorded too recently.
+    /// `time` is in seconds, and must not go backwards.
+    pub fn record(&mut self, renderer: &Renderer, time: f32) -> Result<(), RenderError> {
+        if self.wants_frame(time) {
+            self.push(time, renderer.capture_frame(Capture::Canvas)?);
+        }
+        Ok(())
+    }
+
+    /// Whether a frame recorded at `time` would be kept,
+    /// which avoids reading back frames that [Recorder::push] would drop.
+    pub fn wants_frame(&self, time: f32) -> bool {
+        // Frames arriving slightly early are kept, so that jitter doesn't halve the frame rate.
+        self.frames
+            .back()
+            .is_none_or(|(last, _)| time - last >= self.interval * 0.9)
+    }
+
+    /// Add a frame captured some other way, such as from [crate::software::Renderer::canvas].
+    /// A frame of a different size than the previous ones starts a new recording.
+    pub fn push(&mut self, time: f32, frame: Frame) {
+        if !self.wants_frame(time) {
+            return;
+        }
+        if let Some((_, last)) = self.frames.back() {
+            if (last.size.width, last.size.height) != (frame.size.width, frame.size.height) {
+                self.frames.clear();
+            }
+        }
+        self.frames.push_back((time, Arc::new(frame)));
+        while let Some((first, _)) = self.frames.front() {
+            if time - first < self.duration {
+                break;
+            }
+            self.frames.pop_front();
+        }
+    }
+
+    /// Number of frames recorded.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Forget every recorded frame.
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Encode the recorded frames as an animated PNG that loops forever.
+    /// Each frame is shown for as long as it was on screen.
+    pub fn write_apng<W: Write>(&self, writer: W) -> Result<(), CaptureError> {
+        let frames: Vec<_> = self.frames.iter().cloned().collect();
+        write_apng(&frames, self.interval, writer)
+    }
+
+    /// Save the recorded frames as an animated PNG file on a background thread.
+    /// Recording can continue meanwhile, as frames are shared rather than copied.
+    pub fn save_apng(&self, path: impl Into<PathBuf>) -> JoinHandle<Result<(), CaptureError>> {
+        let path = path.into();
+        let frames: Vec<_> = self.frames.iter().cloned().collect();
+        let interval = self.interval;
+        std::thread::spawn(move || save(&path, |file| write_apng(&frames, interval, file)))
+    }
+}
+
+fn write_apng<W: Write>(
+    frames: &[(f32, Arc<Frame>)],
+    interval: f32,
+    writer: W,
+) -> Result<(), CaptureError> {
+    let Some((_, first)) = frames.first() else {
+        return Err(CaptureError::NoFrames);
+    };
+    let mut encoder = encoder(writer, first.size);
+    encoder.set_animated(frames.len() as u32, 0)?;
+    let mut writer = encoder.write_header()?;
+    for (i, (time, frame)) in frames.iter().enumerate() {
+        let delay = frames.get(i + 1).map_or(interval, |(next, _)| next - time);
+        // Delays are stored as fractions, here in milliseconds.
+        let milliseconds = (delay * 1000.0).round().clamp(1.0, u16::MAX as f32) as u16;
+        writer.set_frame_delay(milliseconds, 1000)?;
+        writer.write_image_data(&frame.pixels)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+fn encoder<W: Write>(writer: W, size: Size) -> png::Encoder<'static, W> {
+    let mut encoder = png::Encoder::new(writer, size.width, size.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    encoder
+}
+
+/// Create a file at `path` and write to it, reporting I/O errors with the path.
+fn save(
+    path: &PathBuf,
+    write: impl FnOnce(std::io::BufWriter<std::fs::File>) -> Result<(), CaptureError>,
+) -> Result<(), CaptureError> {
+    let file = std::fs::File::create(path).map_err(|err| CaptureError::Io(path.clone(), err))?;
+    match write(std::io::BufWriter::new(file)) {
+        Err(CaptureError::Encoding(png::EncodingError::IoError(err))) => {
+            Err(CaptureError::Io(path.clone(), err))
+        }
+        result => result,
+    }
+}
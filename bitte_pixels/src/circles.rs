@@ -1,35 +1,39 @@
-use bytemuck::{cast_slice, Pod, Zeroable};
+use bytemuck::{cast_slice, NoUninit};
 use std::{mem::size_of, rc::Rc};
 use wgpu::{
     vertex_attr_array, BlendState, Buffer, BufferAddress, BufferDescriptor, BufferUsages,
-    ColorTargetState, ColorWrites, FragmentState, MultisampleState, PipelineLayoutDescriptor,
-    PrimitiveState, PrimitiveTopology, RenderPass, RenderPipeline, RenderPipelineDescriptor,
-    VertexBufferLayout, VertexState, VertexStepMode,
+    ColorTargetState, ColorWrites, FragmentState, MultisampleState, PipelineLayout,
+    PipelineLayoutDescriptor, PrimitiveState, PrimitiveTopology, RenderPass, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModule, VertexBufferLayout, VertexState, VertexStepMode,
 };
 
+use super::{blend, BlendMode, Color};
+
 /// Sent to the shader for rendering.
 #[repr(C)]
-#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+#[derive(Copy, Clone, Debug, Default, NoUninit)]
 pub struct Circle {
     /// Position on the canvas
     pub offset: [i32; 2],
     /// Width and height in pixels.
     pub diameter: u32,
-    /// Zero-indexed position in the sheet.
-    pub color: [f32; 4],
+    /// Color of the outline.
+    pub color: Color,
+    /// How the circle blends with the canvas.
+    pub blend: BlendMode,
 }
 
 impl Circle {
     const LAYOUT: VertexBufferLayout<'static> = VertexBufferLayout {
         array_stride: size_of::<Self>() as BufferAddress,
         step_mode: VertexStepMode::Instance,
-        attributes: &vertex_attr_array![1 => Sint32x2, 2 => Uint32x2, 3 => Float32x4],
+        attributes: &vertex_attr_array![1 => Sint32x2, 2 => Uint32, 3 => Float32x4],
     };
 }
 
 pub(crate) struct Renderer {
     ctx: Rc<super::Context>,
-    pipeline: RenderPipeline,
+    pub(crate) pipelines: blend::Pipelines,
     instance_buffer: Buffer,
 }
 
@@ -47,24 +51,57 @@ impl Renderer {
 
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("circle"),
-            bind_group_layouts: &[&ctx.canvas.dimensions_layout],
+            bind_group_layouts: &[&ctx.dimensions_layout],
             push_constant_ranges: &[],
         });
 
-        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+        let pipelines = blend::Pipelines::new(ctx.shaders.clone(), {
+            let ctx = ctx.clone();
+            move |module, blend| create_pipeline(&ctx, &pipeline_layout, module, blend)
+        });
+
+        Self {
+            ctx,
+            pipelines,
+            instance_buffer,
+        }
+    }
+
+    pub fn render<'a>(&'a mut self, render_pass: &mut RenderPass<'a>, circles: &[Circle]) {
+        self.ctx
+            .queue
+            .write_buffer(&self.instance_buffer, 0, cast_slice(circles));
+
+        render_pass.set_vertex_buffer(0, self.ctx.quad_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        for (mode, instances) in blend::runs(circles, |circle| circle.blend) {
+            render_pass.set_pipeline(self.pipelines.get(mode));
+            render_pass.draw(0..super::QUAD_VERTICES.len() as u32, instances);
+        }
+    }
+}
+
+fn create_pipeline(
+    ctx: &super::Context,
+    layout: &PipelineLayout,
+    module: &ShaderModule,
+    blend: BlendState,
+) -> RenderPipeline {
+    ctx.device
+        .create_render_pipeline(&RenderPipelineDescriptor {
             label: Some("circle"),
-            layout: Some(&pipeline_layout),
+            layout: Some(layout),
             vertex: VertexState {
-                module: &ctx.shaders,
+                module,
                 entry_point: "circle_v",
                 buffers: &[super::QUAD_LAYOUT, Circle::LAYOUT],
             },
             fragment: Some(FragmentState {
-                module: &ctx.shaders,
+                module,
                 entry_point: "circle_f",
                 targets: &[Some(ColorTargetState {
-                    format: ctx.canvas.color_format,
-                    blend: Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    format: ctx.color_format,
+                    blend: Some(blend),
                     write_mask: ColorWrites::all(),
                 })],
             }),
@@ -75,26 +112,5 @@ impl Renderer {
             multisample: MultisampleState::default(),
             depth_stencil: None,
             multiview: None,
-        });
-
-        Self {
-            ctx,
-            pipeline,
-            instance_buffer,
-        }
-    }
-
-    pub fn render<'a>(&'a mut self, render_pass: &mut RenderPass<'a>, circles: &[Circle]) {
-        self.ctx
-            .queue
-            .write_buffer(&self.instance_buffer, 0, cast_slice(circles));
-
-        render_pass.set_pipeline(&self.pipeline);
-        render_pass.set_vertex_buffer(0, self.ctx.quad_buffer.slice(..));
-        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-        render_pass.draw(
-            0..super::QUAD_VERTICES.len() as u32,
-            0..circles.len() as u32,
-        );
-    }
+        })
 }
@@ -0,0 +1,121 @@
+/// A color in linear space with premultiplied alpha, which is how the pipelines blend.
+///
+/// Colors are created from straight alpha with [Color::srgb], [Color::srgb8] or [Color::linear],
+/// or from components that are already premultiplied with [Color::premultiplied],
+/// so a translucent color can't end up brighter than intended.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Color([f32; 4]);
+
+impl Color {
+    pub const TRANSPARENT: Color = Color([0.0; 4]);
+    pub const BLACK: Color = Color([0.0, 0.0, 0.0, 1.0]);
+    pub const WHITE: Color = Color([1.0; 4]);
+
+    /// A color from linear components that are already multiplied by alpha.
+    pub const fn premultiplied(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Color([r, g, b, a])
+    }
+
+    /// A color from linear components and straight alpha.
+    pub const fn linear(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Color([r * a, g * a, b * a, a])
+    }
+
+    /// A color from sRGB components, as picked in an image editor, and straight alpha.
+    pub fn srgb(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self::linear(srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a)
+    }
+
+    /// A color from sRGB bytes and straight alpha.
+    pub fn srgb8(r: u8, g: u8, b: u8, a: u8) -> Self {
+        let [r, g, b, a] = [r, g, b, a].map(|c| c as f32 / 255.0);
+        Self::srgb(r, g, b, a)
+    }
+
+    /// Parse a `#rrggbb` or `#rrggbbaa` sRGB color with straight alpha.
+    /// The `#` is optional.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+        if !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        let [r, g, b, a] = match hex.len() {
+            6 => (value << 8 | 0xff).to_be_bytes(),
+            8 => value.to_be_bytes(),
+            _ => return None,
+        };
+        Some(Self::srgb8(r, g, b, a))
+    }
+
+    /// Linear components multiplied by alpha, as sent to the GPU.
+    pub fn to_premultiplied(self) -> [f32; 4] {
+        self.0
+    }
+
+    /// Linear components and straight alpha.
+    /// Fully transparent colors are transparent black.
+    pub fn to_linear(self) -> [f32; 4] {
+        let [r, g, b, a] = self.0;
+        if a == 0.0 {
+            return [0.0; 4];
+        }
+        [r / a, g / a, b / a, a]
+    }
+
+    /// sRGB components and straight alpha.
+    pub fn to_srgb(self) -> [f32; 4] {
+        let [r, g, b, a] = self.to_linear();
+        [linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b), a]
+    }
+
+    /// sRGB bytes and straight alpha.
+    pub fn to_srgb8(self) -> [u8; 4] {
+        self.to_srgb().map(to_byte)
+    }
+
+    /// Premultiplied components with sRGB encoding, as stored in sprites and on the canvas.
+    pub(crate) fn to_premultiplied_srgb8(self) -> [u8; 4] {
+        let [r, g, b, a] = self.0;
+        [
+            to_byte(linear_to_srgb(r)),
+            to_byte(linear_to_srgb(g)),
+            to_byte(linear_to_srgb(b)),
+            to_byte(a),
+        ]
+    }
+
+    /// The same color, with its opacity multiplied by `factor`.
+    pub fn fade(self, factor: f32) -> Self {
+        Color(self.0.map(|c| c * factor))
+    }
+}
+
+impl From<Color> for wgpu::Color {
+    /// A clear color for render passes, which takes premultiplied linear components.
+    fn from(color: Color) -> Self {
+        let [r, g, b, a] = color.0.map(f64::from);
+        wgpu::Color { r, g, b, a }
+    }
+}
+
+pub(crate) fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub(crate) fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub(crate) fn to_byte(c: f32) -> u8 {
+    (c.clamp(0.0, 1.0) * 255.0).round() as u8
+}
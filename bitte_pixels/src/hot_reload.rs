@@ -0,0 +1,159 @@
+//! Reloading shaders from disk during development, behind the `hot-reload` feature.
+//!
+//! The crate's own shaders are read from `src/shaders` in its source directory,
+//! so that editing them takes effect without recompiling.
+//! Materials and post-processing passes can be loaded from files with
+//! [crate::Renderer::load_material] and [crate::Renderer::load_post_shader],
+//! and are recompiled whenever their file changes.
+//! Shaders are also recompiled when a prelude they're appended to changes.
+//!
+//! Files are checked for changes a few times per second, when a frame is rendered.
+//! If a changed shader fails to validate, the error is logged and the previous version stays in use.
+//!
+//! The web has no file system, so this feature only works on native platforms.
+
+use std::{
+    path::{Path, PathBuf},
+    rc::Weak,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Where the crate's own shaders are read from.
+const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders");
+
+/// Shortest time between checking files for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The source of a shader, and when its file was last read.
+pub(crate) struct ShaderFile {
+    path: PathBuf,
+    /// Modification time and length of the file when it was last read.
+    /// File systems with coarse timestamps can miss quick edits, unless they change the length.
+    stamp: Option<(SystemTime, u64)>,
+    pub(crate) source: String,
+}
+
+impl ShaderFile {
+    /// Read a shader from a file.
+    pub(crate) fn load(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            path: path.to_owned(),
+            stamp: stamp(path).ok(),
+            source: std::fs::read_to_string(path)?,
+        })
+    }
+
+    /// A shader included in the crate, read from its file when it's first polled.
+    fn builtin(name: &str, source: &str) -> Self {
+        Self {
+            path: Path::new(SHADER_DIR).join(name),
+            stamp: None,
+            source: source.to_owned(),
+        }
+    }
+
+    /// Read the file again if it was modified or resized since it was last read.
+    /// Returns whether the source changed.
+    pub(crate) fn poll(&mut self) -> bool {
+        let Ok(stamp) = stamp(&self.path) else {
+            return false;
+        };
+        if self.stamp == Some(stamp) {
+            return false;
+        }
+        self.stamp = Some(stamp);
+        match std::fs::read_to_string(&self.path) {
+            Ok(source) if source != self.source => {
+                self.source = source;
+                true
+            }
+            Ok(_) => false,
+            Err(err) => {
+                error!("couldn't read shader {:?}: {err}", self.path);
+                false
+            }
+        }
+    }
+}
+
+fn stamp(path: &Path) -> std::io::Result<(SystemTime, u64)> {
+    let meta = std::fs::metadata(path)?;
+    Ok((meta.modified()?, meta.len()))
+}
+
+/// A material or post-processing pass, with what it's compiled from.
+/// Dropped items are forgotten on the next reload.
+pub(crate) struct Tracked<T> {
+    pub(crate) item: Weak<T>,
+    pub(crate) label: String,
+    pub(crate) source: TrackedSource,
+}
+
+pub(crate) enum TrackedSource {
+    Text(String),
+    File(ShaderFile),
+}
+
+impl TrackedSource {
+    pub(crate) fn get(&self) -> &str {
+        match self {
+            TrackedSource::Text(source) => source,
+            TrackedSource::File(file) => &file.source,
+        }
+    }
+
+    /// Whether the source was reloaded from its file.
+    pub(crate) fn poll(&mut self) -> bool {
+        match self {
+            TrackedSource::Text(_) => false,
+            TrackedSource::File(file) => file.poll(),
+        }
+    }
+}
+
+/// The crate's own shaders.
+pub(crate) struct CrateShaders {
+    last_poll: Option<Instant>,
+    pub(crate) prelude: ShaderFile,
+    pub(crate) main: ShaderFile,
+    pub(crate) material: ShaderFile,
+    pub(crate) post_prelude: ShaderFile,
+    pub(crate) postprocess: ShaderFile,
+    pub(crate) upscale: ShaderFile,
+}
+
+impl CrateShaders {
+    pub(crate) fn new() -> Self {
+        if !Path::new(SHADER_DIR).is_dir() {
+            warn!("{SHADER_DIR:?} is missing, so built-in shaders won't be reloaded");
+        }
+        Self {
+            last_poll: None,
+            prelude: ShaderFile::builtin("prelude.wgsl", include_str!("shaders/prelude.wgsl")),
+            main: ShaderFile::builtin("main.wgsl", include_str!("shaders/main.wgsl")),
+            material: ShaderFile::builtin("material.wgsl", include_str!("shaders/material.wgsl")),
+            post_prelude: ShaderFile::builtin(
+                "post_prelude.wgsl",
+                include_str!("shaders/post_prelude.wgsl"),
+            ),
+            postprocess: ShaderFile::builtin(
+                "postprocess.wgsl",
+                include_str!("shaders/postprocess.wgsl"),
+            ),
+            upscale: ShaderFile::builtin("upscale.wgsl", include_str!("shaders/upscale.wgsl")),
+        }
+    }
+
+    /// Whether it's time to check files for changes again.
+    pub(crate) fn due(&mut self) -> bool {
+        let now = Instant::now();
+        if self
+            .last_poll
+            .is_some_and(|last| now.duration_since(last) < POLL_INTERVAL)
+        {
+            return false;
+        }
+        self.last_poll = Some(now);
+        true
+    }
+}
@@ -3,16 +3,47 @@ use wgpu::{util::DeviceExt, StoreOp};
 #[macro_use]
 extern crate log;
 
+mod blend;
+#[cfg(feature = "capture")]
+pub mod capture;
 mod circles;
+mod color;
+#[cfg(feature = "hot-reload")]
+mod hot_reload;
+pub mod material;
+pub mod pixel_font;
+pub mod postprocess;
 mod primitives;
 mod rect;
+pub mod rich_text;
+pub mod software;
 pub mod sprite;
+mod target;
+pub mod text;
+#[cfg(feature = "tiled")]
+pub mod tiled;
+pub mod tilemap;
+#[cfg(feature = "truetype")]
+pub mod truetype;
 mod upscale;
 
+pub use blend::BlendMode;
 pub use circles::Circle;
+pub use color::Color;
+use material::{Material, MaterialDescriptor, MaterialDraw};
+use postprocess::{PostEffect, PostShader};
 pub use primitives::{LineStrip, PrimitiveVertex};
 pub use rect::Rectangle;
-use sprite::{SpriteInstance, SpriteSheet, SpriteSheetBuilder};
+use rich_text::RichTextInstance;
+use sprite::{
+    HandleError, InvalidSpritePolicy, PackedSpriteSheet, SpriteInstance, SpriteSheet,
+    SpriteSheetBuilder,
+};
+use target::Canvas;
+pub use target::RenderTarget;
+use text::Text;
+use tilemap::{Tilemap, TilemapInstance};
+pub use upscale::{Letterbox, UpscaleMode};
 
 // Buffer element types and constants
 #[repr(C)]
@@ -41,12 +72,40 @@ const QUAD_VERTICES: &[Vertex; 4] = &[
 ];
 
 // Renderer components
-pub struct Scene {
+pub struct Scene<'a> {
+    /// Color of the canvas before anything is drawn.
+    pub background: Color,
+    /// Drawn first, below everything else.
+    pub tilemaps: Vec<TilemapInstance<'a>>,
     pub pixels: Vec<PrimitiveVertex>,
     pub linestrips: Vec<LineStrip>,
     pub circles: Vec<Circle>,
     pub rectangles: Vec<Rectangle>,
+    /// Sprites and rectangles with custom shaders, drawn above the other sprites and rectangles.
+    pub material_draws: Vec<MaterialDraw<'a>>,
     pub sprites: Vec<SpriteInstance>,
+    /// Drawn last, above everything else.
+    pub texts: Vec<Text<'a>>,
+    /// Drawn along with texts.
+    pub rich_texts: Vec<RichTextInstance<'a>>,
+}
+
+impl Default for Scene<'_> {
+    /// An empty scene on a black background.
+    fn default() -> Self {
+        Self {
+            background: Color::BLACK,
+            tilemaps: Vec::new(),
+            pixels: Vec::new(),
+            linestrips: Vec::new(),
+            circles: Vec::new(),
+            rectangles: Vec::new(),
+            material_draws: Vec::new(),
+            sprites: Vec::new(),
+            texts: Vec::new(),
+            rich_texts: Vec::new(),
+        }
+    }
 }
 
 #[derive(std::fmt::Debug, Clone, Copy)]
@@ -61,6 +120,11 @@ pub enum RenderError {
     AcquireAdapter,
     AcquireDevice,
     SurfaceTexture,
+    /// A texture couldn't be copied back to the CPU.
+    ReadBack,
+    /// The renderer has no output texture to read back.
+    NoOutput,
+    InvalidSprite(HandleError),
     Other(String),
 }
 
@@ -71,6 +135,9 @@ impl std::fmt::Display for RenderError {
             RenderError::AcquireAdapter => "adapter request failed",
             RenderError::AcquireDevice => "device request failed",
             RenderError::SurfaceTexture => "couldn't acquire surface texture",
+            RenderError::ReadBack => "couldn't map readback buffer",
+            RenderError::NoOutput => "renderer has no output texture",
+            RenderError::InvalidSprite(err) => return write!(f, "invalid sprite: {err}"),
             RenderError::Other(err) => err,
         };
         f.write_str(err)
@@ -79,13 +146,26 @@ impl std::fmt::Display for RenderError {
 
 pub struct Renderer<'w> {
     ctx: std::rc::Rc<Context>,
-    surface: wgpu::Surface<'w>,
+    /// Missing for headless renderers.
+    surface: Option<wgpu::Surface<'w>>,
+    /// Size and format of the upscaled output, whether to a surface or not.
     surface_config: wgpu::SurfaceConfiguration,
+    /// Off-screen target that headless renderers upscale to, if any.
+    output: Option<OutputTexture>,
+    /// The scene is drawn here at the game resolution, then upscaled.
+    canvas: Canvas,
     rect_renderer: rect::Renderer,
     sprite_renderer: sprite::Renderer,
+    material_renderer: material::Renderer,
+    tilemap_renderer: tilemap::Renderer,
+    text_renderer: text::Renderer,
     upscale_renderer: upscale::Renderer,
+    post_renderer: postprocess::Renderer,
+    post_effects: Vec<PostEffect>,
     primitives_renderer: primitives::Renderer,
     circle_renderer: circles::Renderer,
+    #[cfg(feature = "hot-reload")]
+    crate_shaders: hot_reload::CrateShaders,
 }
 
 impl<'w> Renderer<'w> {
@@ -101,17 +181,57 @@ impl<'w> Renderer<'w> {
         let surface = instance
             .create_surface(window)
             .map_err(|_| RenderError::CreateSurface)?;
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .ok_or(RenderError::AcquireAdapter)?;
+        Self::with_surface(instance, Some(surface), window_size, game_resolution).await
+    }
+
+    /// Create a renderer that draws off-screen, without a window.
+    /// If `output_size` is given, the canvas is also upscaled to an off-screen texture of that size,
+    /// which can be read back with [Renderer::read_output].
+    /// Falls back to software adapters such as llvmpipe or lavapipe when there is no GPU.
+    pub async fn new_headless(
+        game_resolution: Size,
+        output_size: Option<Size>,
+    ) -> Result<Renderer<'static>, RenderError> {
+        let instance = wgpu::Instance::default();
+        let mut renderer = Renderer::with_surface(
+            instance,
+            None,
+            output_size.unwrap_or(game_resolution),
+            game_resolution,
+        )
+        .await?;
+        if let Some(size) = output_size {
+            let ctx = &renderer.ctx;
+            renderer.output = Some(OutputTexture::new(&ctx.device, size, ctx.color_format));
+            renderer
+                .upscale_renderer
+                .renew_active_quad(&ctx.queue, size);
+        }
+        Ok(renderer)
+    }
 
-        let mut limits = wgpu::Limits::downlevel_webgl2_defaults();
-        limits.max_texture_dimension_2d = 8192;
+    async fn with_surface(
+        instance: wgpu::Instance,
+        surface: Option<wgpu::Surface<'w>>,
+        output_size: Size,
+        game_resolution: Size,
+    ) -> Result<Self, RenderError> {
+        let mut adapter = None;
+        for force_fallback_adapter in [false, true] {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    compatible_surface: surface.as_ref(),
+                    force_fallback_adapter,
+                })
+                .await;
+            if adapter.is_some() {
+                break;
+            }
+        }
+        let adapter = adapter.ok_or(RenderError::AcquireAdapter)?;
+
+        let limits = limits();
 
         let (device, queue) = adapter
             .request_device(
@@ -124,24 +244,25 @@ impl<'w> Renderer<'w> {
             .await
             .map_err(|_| RenderError::AcquireDevice)?;
 
-        let screen_color_format = *surface
-            .get_capabilities(&adapter)
-            .formats
-            .get(0)
-            .unwrap_or(&wgpu::TextureFormat::Rgba8UnormSrgb);
+        let screen_color_format = surface
+            .as_ref()
+            .and_then(|surface| surface.get_capabilities(&adapter).formats.first().copied())
+            .unwrap_or(wgpu::TextureFormat::Rgba8UnormSrgb);
 
         let surface_config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: screen_color_format,
-            width: window_size.width,
-            height: window_size.height,
+            width: output_size.width,
+            height: output_size.height,
             present_mode: wgpu::PresentMode::Fifo,
             alpha_mode: wgpu::CompositeAlphaMode::Auto,
             view_formats: vec![screen_color_format],
             desired_maximum_frame_latency: 1,
         };
 
-        surface.configure(&device, &surface_config);
+        if let Some(surface) = &surface {
+            surface.configure(&device, &surface_config);
+        }
 
         let quad_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("quad buffer"),
@@ -149,35 +270,75 @@ impl<'w> Renderer<'w> {
             usage: wgpu::BufferUsages::VERTEX,
         });
 
-        let canvas = Canvas::new(&device, "final image", game_resolution, screen_color_format);
+        let dimensions_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("canvas dimensions"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    // minimum size has to be 16 rather than 8 due to WebGL2 layout rules
+                    min_binding_size: Some(std::num::NonZeroU64::new(16).unwrap()),
+                },
+                count: None,
+            }],
+        });
 
-        let shaders = device.create_shader_module(wgpu::include_wgsl!("shaders/main.wgsl"));
+        let shaders = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shaders"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("shaders/prelude.wgsl"),
+                    include_str!("shaders/main.wgsl")
+                )
+                .into(),
+            ),
+        });
 
         let ctx = std::rc::Rc::new(Context {
             device,
             queue,
             limits,
-            shaders,
-            canvas,
+            shaders: std::rc::Rc::new(shaders),
+            color_format: screen_color_format,
+            dimensions_layout,
             quad_buffer,
         });
 
+        let canvas = Canvas::new(&ctx, "final image", game_resolution);
+
         let primitives_renderer = primitives::Renderer::new(ctx.clone());
         let circle_renderer = circles::Renderer::new(ctx.clone());
         let rect_renderer = rect::Renderer::new(ctx.clone());
         let sprite_renderer = sprite::Renderer::new(ctx.clone());
-        let upscale_renderer = upscale::Renderer::new(ctx.clone());
+        let material_renderer =
+            material::Renderer::new(ctx.clone(), sprite_renderer.sprite_sheet_layout());
+        let tilemap_renderer =
+            tilemap::Renderer::new(ctx.clone(), sprite_renderer.sprite_sheet_layout());
+        let text_renderer = text::Renderer::new(ctx.clone(), sprite_renderer.sprite_sheet_layout());
+        let upscale_renderer = upscale::Renderer::new(ctx.clone(), &canvas);
+        let post_renderer = postprocess::Renderer::new(ctx.clone());
 
         Ok(Self {
             ctx,
             surface,
             surface_config,
+            output: None,
+            canvas,
 
             primitives_renderer,
             circle_renderer,
             rect_renderer,
             sprite_renderer,
+            material_renderer,
+            tilemap_renderer,
+            text_renderer,
             upscale_renderer,
+            post_renderer,
+            post_effects: Vec::new(),
+            #[cfg(feature = "hot-reload")]
+            crate_shaders: hot_reload::CrateShaders::new(),
         })
     }
 
@@ -185,11 +346,260 @@ impl<'w> Renderer<'w> {
         self.sprite_renderer.create_sprite_sheet_builder(name)
     }
 
+    /// Upload a sheet that was packed with [SpriteSheetBuilder::pack].
+    pub fn load_sprite_sheet(
+        &self,
+        name: &str,
+        packed: PackedSpriteSheet,
+    ) -> Result<SpriteSheet, RenderError> {
+        self.sprite_renderer.load_sprite_sheet(name, packed)
+    }
+
+    /// Create an empty tilemap.
+    /// `size` is given in tiles, and `tile_size` in pixels.
+    pub fn create_tilemap(
+        &self,
+        size: (u32, u32),
+        tile_size: (u32, u32),
+        layer_count: usize,
+    ) -> Tilemap {
+        Tilemap::new(Some(self.ctx.clone()), size, tile_size, layer_count)
+    }
+
+    /// Width and height of the canvas in pixels.
+    pub fn canvas_size(&self) -> Size {
+        self.canvas.size
+    }
+
+    /// Change the resolution of the canvas, such as for a resolution option or a zoomed-out scene.
+    /// The canvas is created again, so it's cleared until the next call to [Renderer::render].
+    pub fn set_canvas_size(&mut self, size: Size) -> Result<(), RenderError> {
+        target::check_size("canvas", size, &self.ctx.limits)?;
+        self.canvas = Canvas::new(&self.ctx, "final image", size);
+        let surface_size = self.surface_size();
+        self.upscale_renderer.set_canvas(&self.canvas, surface_size);
+        Ok(())
+    }
+
+    /// Choose how sprites with unresolvable handles are drawn.
+    pub fn set_invalid_sprite_policy(&mut self, policy: InvalidSpritePolicy) {
+        self.sprite_renderer.invalid_sprite_policy = policy;
+    }
+
+    /// Choose how the canvas is scaled to the window, or to the output of a headless renderer.
+    pub fn set_upscale_mode(&mut self, mode: UpscaleMode) {
+        self.upscale_renderer.mode = mode;
+        self.upscale_renderer
+            .renew_active_quad(&self.ctx.queue, self.surface_size());
+    }
+
+    pub fn upscale_mode(&self) -> UpscaleMode {
+        self.upscale_renderer.mode
+    }
+
+    /// Map a position in physical window pixels, such as a cursor position, to canvas pixels.
+    /// Canvas positions are fractional, and outside the canvas for positions in the letterbox.
+    /// The Y-axis of the canvas points up, unlike that of the window.
+    ///
+    /// Returns `None` if the canvas isn't visible, as in a minimized window,
+    /// or one smaller than the canvas with [UpscaleMode::Integer].
+    pub fn window_to_canvas(&self, position: [f32; 2]) -> Option<[f32; 2]> {
+        self.upscale_renderer
+            .surface_to_canvas(self.surface_size(), position)
+    }
+
+    /// Map a position in canvas pixels to physical window pixels, from the top left.
+    /// This is the inverse of [Renderer::window_to_canvas], and likewise returns `None`
+    /// if the canvas isn't visible.
+    pub fn canvas_to_window(&self, position: [f32; 2]) -> Option<[f32; 2]> {
+        self.upscale_renderer
+            .canvas_to_surface(self.surface_size(), position)
+    }
+
+    /// Whether a position in physical window pixels is on the upscaled canvas,
+    /// rather than in the letterbox.
+    pub fn is_on_canvas(&self, position: [f32; 2]) -> bool {
+        let [x, y, width, height] = self.upscale_renderer.upscaled_rect(self.surface_size());
+        (x..x + width).contains(&position[0]) && (y..y + height).contains(&position[1])
+    }
+
+    /// Choose what fills the window around the upscaled canvas.
+    /// Fails if a letterbox image is larger than the device supports,
+    /// keeping the previous letterbox.
+    pub fn set_letterbox(&mut self, letterbox: Letterbox) -> Result<(), RenderError> {
+        let surface_size = self.surface_size();
+        self.upscale_renderer.set_letterbox(letterbox, surface_size)
+    }
+
+    /// The chain of post-processing effects applied while upscaling, in order.
+    /// Effects can be added, removed, reordered and tweaked between frames.
+    pub fn post_effects_mut(&mut self) -> &mut Vec<PostEffect> {
+        &mut self.post_effects
+    }
+
+    /// Compile a custom post-processing pass from WGSL, as described in [postprocess].
+    pub fn create_post_shader(&self, label: &str, source: &str) -> Result<PostShader, RenderError> {
+        self.post_renderer
+            .create_shader(label, source)
+            .map_err(|err| RenderError::Other(format!("invalid shader {label:?}: {err}")))
+    }
+
+    /// Compile a material for sprites and rectangles from WGSL, as described in [material].
+    pub fn create_material(&self, descriptor: MaterialDescriptor) -> Result<Material, RenderError> {
+        self.material_renderer
+            .create_material(&descriptor)
+            .map_err(|err| {
+                RenderError::Other(format!("invalid material {:?}: {err}", descriptor.label))
+            })
+    }
+
+    /// Compile a material from a WGSL file, and recompile it whenever the file changes.
+    #[cfg(feature = "hot-reload")]
+    pub fn load_material(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        uniforms: &[u8],
+    ) -> Result<Material, RenderError> {
+        self.material_renderer
+            .load_material(path.as_ref(), uniforms)
+            .map_err(|err| {
+                RenderError::Other(format!("invalid material {:?}: {err}", path.as_ref()))
+            })
+    }
+
+    /// Compile a custom post-processing pass from a WGSL file,
+    /// and recompile it whenever the file changes.
+    #[cfg(feature = "hot-reload")]
+    pub fn load_post_shader(
+        &self,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<PostShader, RenderError> {
+        self.post_renderer
+            .load_shader(path.as_ref())
+            .map_err(|err| RenderError::Other(format!("invalid shader {:?}: {err}", path.as_ref())))
+    }
+
+    /// Recompile shaders whose files changed, as described in the `hot_reload` module.
+    #[cfg(feature = "hot-reload")]
+    fn reload_shaders(&mut self) {
+        let shaders = &mut self.crate_shaders;
+        if !shaders.due() {
+            return;
+        }
+        // Every file is polled, so that none of them is reloaded twice.
+        let prelude = shaders.prelude.poll();
+        let main = shaders.main.poll();
+        let material = shaders.material.poll();
+        let post_prelude = shaders.post_prelude.poll();
+        let postprocess = shaders.postprocess.poll();
+        let upscale = shaders.upscale.poll();
+
+        let scene =
+            (prelude || main).then(|| format!("{}{}", shaders.prelude.source, shaders.main.source));
+        let material_prelude = (prelude || material)
+            .then(|| format!("{}{}", shaders.prelude.source, shaders.material.source));
+        let post = (post_prelude || postprocess).then(|| {
+            (
+                shaders.post_prelude.source.clone(),
+                shaders.postprocess.source.clone(),
+            )
+        });
+        let upscale = upscale.then(|| shaders.upscale.source.clone());
+
+        if let Some(source) = scene {
+            self.reload_scene_shaders(source);
+        }
+        if let Some(source) = upscale {
+            self.reload_upscale_shaders(source);
+        }
+        self.material_renderer.reload(material_prelude);
+        self.post_renderer.reload(
+            post.as_ref()
+                .map(|(prelude, effects)| (prelude.as_str(), effects.as_str())),
+        );
+    }
+
+    /// Replace the pipelines drawing scenes, if `source` validates.
+    #[cfg(feature = "hot-reload")]
+    fn reload_scene_shaders(&mut self, source: String) {
+        let device = &self.ctx.device;
+        let result = validate(device, || {
+            let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("shaders"),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+            let module = std::rc::Rc::new(module);
+            (
+                self.primitives_renderer
+                    .pixel_pipelines
+                    .with_module(module.clone()),
+                self.primitives_renderer
+                    .linestrip_pipelines
+                    .with_module(module.clone()),
+                self.circle_renderer.pipelines.with_module(module.clone()),
+                self.rect_renderer.pipelines.with_module(module.clone()),
+                self.sprite_renderer.pipelines.with_module(module.clone()),
+                self.tilemap_renderer.pipelines.with_module(module.clone()),
+                self.text_renderer.pipelines.with_module(module),
+            )
+        });
+        match result {
+            Ok((pixel, linestrip, circle, rect, sprite, tilemap, text)) => {
+                self.primitives_renderer.pixel_pipelines = pixel;
+                self.primitives_renderer.linestrip_pipelines = linestrip;
+                self.circle_renderer.pipelines = circle;
+                self.rect_renderer.pipelines = rect;
+                self.sprite_renderer.pipelines = sprite;
+                self.tilemap_renderer.pipelines = tilemap;
+                self.text_renderer.pipelines = text;
+                info!("reloaded main.wgsl");
+            }
+            Err(err) => error!("couldn't reload main.wgsl: {err}"),
+        }
+    }
+
+    /// Replace the pipelines upscaling the canvas, if `source` validates.
+    #[cfg(feature = "hot-reload")]
+    fn reload_upscale_shaders(&mut self, source: String) {
+        let device = &self.ctx.device;
+        let result = validate(device, || {
+            let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("upscale"),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+            self.upscale_renderer.create_pipelines(&module)
+        });
+        match result {
+            Ok(pipelines) => {
+                self.upscale_renderer.pipelines = pipelines;
+                info!("reloaded upscale.wgsl");
+            }
+            Err(err) => error!("couldn't reload upscale.wgsl: {err}"),
+        }
+    }
+
+    /// Size of the window surface, or of the off-screen output of a headless renderer.
+    fn surface_size(&self) -> Size {
+        Size {
+            width: self.surface_config.width,
+            height: self.surface_config.height,
+        }
+    }
+
+    /// Resize the window surface, or the off-screen output of a headless renderer.
     pub fn resize_surface(&mut self, size: Size) {
         self.surface_config.width = size.width;
         self.surface_config.height = size.height;
-        self.surface
-            .configure(&self.ctx.device, &self.surface_config);
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.ctx.device, &self.surface_config);
+        }
+        if self.output.is_some() {
+            self.output = Some(OutputTexture::new(
+                &self.ctx.device,
+                size,
+                self.ctx.color_format,
+            ));
+        }
         self.upscale_renderer
             .renew_active_quad(&self.ctx.queue, size);
     }
@@ -197,21 +607,32 @@ impl<'w> Renderer<'w> {
     /// Acquire the next swap chain frame.
     /// If the swap chain has been lost,
     /// this function will recreate it.
-    fn get_surface_texture(&mut self) -> Result<wgpu::SurfaceTexture, RenderError> {
-        match self.surface.get_current_texture() {
+    fn get_surface_texture(
+        &self,
+        surface: &wgpu::Surface,
+    ) -> Result<wgpu::SurfaceTexture, RenderError> {
+        match surface.get_current_texture() {
             Ok(frame) => Ok(frame),
             _ => {
                 info!("Couldn't get swapchain surface texture, reconfiguring.");
-                self.surface
-                    .configure(&self.ctx.device, &self.surface_config);
-                self.surface
+                surface.configure(&self.ctx.device, &self.surface_config);
+                surface
                     .get_current_texture()
                     .map_err(|_| RenderError::SurfaceTexture)
             }
         }
     }
 
-    pub fn render(&mut self, sprite_sheet: &SpriteSheet, scene: &Scene) -> Result<(), RenderError> {
+    /// Render a scene to the window, or off-screen for headless renderers.
+    /// Sprites are looked up in whichever of `sprite_sheets` their handle belongs to.
+    pub fn render(
+        &mut self,
+        sprite_sheets: &[&SpriteSheet],
+        scene: &Scene,
+    ) -> Result<(), RenderError> {
+        #[cfg(feature = "hot-reload")]
+        self.reload_shaders();
+
         // Create a command encoder
         let mut encoder = self
             .ctx
@@ -219,15 +640,50 @@ impl<'w> Renderer<'w> {
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("full pass encoder"),
             });
+        self.draw_scene(&mut encoder, None, sprite_sheets, scene)?;
+
+        let Some(surface) = &self.surface else {
+            if let Some(output) = &self.output {
+                self.upscale(&mut encoder, &output.view);
+            }
+            self.ctx.queue.submit(Some(encoder.finish()));
+            return Ok(());
+        };
+
+        // Draw canvas to surface
+        let surface_texture = self.get_surface_texture(surface)?;
+        let surface_view = &surface_texture
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.upscale(&mut encoder, surface_view);
+
+        // Finish and present surface
+        self.ctx.queue.submit(Some(encoder.finish()));
+        surface_texture.present();
+        Ok(())
+    }
+
+    /// Draw a scene to the canvas, or to a render target.
+    fn draw_scene(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: Option<&Canvas>,
+        sprite_sheets: &[&SpriteSheet],
+        scene: &Scene,
+    ) -> Result<(), RenderError> {
+        let canvas = target.unwrap_or(&self.canvas);
+
+        // Materials may be recompiled between frames, so the pipelines of this frame are kept here.
+        let material_pipelines = material::frame_pipelines(&scene.material_draws);
 
         // Draw items to canvas
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("fill background"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &self.ctx.canvas.view,
+                view: &canvas.view,
                 resolve_target: None,
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    load: wgpu::LoadOp::Clear(scene.background.into()),
                     store: StoreOp::Store,
                 },
             })],
@@ -235,11 +691,27 @@ impl<'w> Renderer<'w> {
             timestamp_writes: None,
             occlusion_query_set: None, // TODO: Check this
         });
-        render_pass.set_bind_group(0, &self.ctx.canvas.dimensions_bind_group, &[]);
+        render_pass.set_bind_group(0, &canvas.dimensions_bind_group, &[]);
+        self.tilemap_renderer.render(
+            &mut render_pass,
+            sprite_sheets,
+            scene.tilemaps.as_slice(),
+            canvas.size,
+        );
         self.sprite_renderer
-            .render(&mut render_pass, sprite_sheet, scene.sprites.as_slice());
+            .render(&mut render_pass, sprite_sheets, scene.sprites.as_slice())
+            .map_err(RenderError::InvalidSprite)?;
         self.rect_renderer
             .render(&mut render_pass, scene.rectangles.as_slice());
+        self.material_renderer
+            .render(
+                &mut render_pass,
+                &self.sprite_renderer,
+                sprite_sheets,
+                scene.material_draws.as_slice(),
+                &material_pipelines,
+            )
+            .map_err(RenderError::InvalidSprite)?;
         self.primitives_renderer.render(
             &mut render_pass,
             scene.pixels.as_slice(),
@@ -247,85 +719,221 @@ impl<'w> Renderer<'w> {
         );
         self.circle_renderer
             .render(&mut render_pass, scene.circles.as_slice());
-        drop(render_pass);
+        self.text_renderer.render(
+            &mut render_pass,
+            sprite_sheets,
+            scene.texts.as_slice(),
+            scene.rich_texts.as_slice(),
+        );
+        Ok(())
+    }
 
-        // Draw canvas to surface
-        let surface_texture = self.get_surface_texture()?;
-        let surface_view = &surface_texture
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-        self.upscale_renderer.render(&mut encoder, surface_view);
+    /// Create an off-screen canvas to render scenes to, and draw as a sprite in other scenes.
+    pub fn create_render_target(
+        &self,
+        name: &str,
+        size: Size,
+    ) -> Result<RenderTarget, RenderError> {
+        RenderTarget::new(Some((&self.ctx, self.sprite_renderer.gpu())), name, size)
+    }
 
-        // Finish and present surface
+    /// Render a scene to a target, replacing what was drawn to it before.
+    /// The target is finished by the time this returns,
+    /// so scenes rendered afterwards can draw it.
+    /// Fails if `sprite_sheets` includes the sheet of the target itself.
+    pub fn render_to_target(
+        &mut self,
+        target: &mut RenderTarget,
+        sprite_sheets: &[&SpriteSheet],
+        scene: &Scene,
+    ) -> Result<(), RenderError> {
+        let Some(canvas) = &target.canvas else {
+            return Err(RenderError::Other(
+                "render target was created by the software renderer".to_owned(),
+            ));
+        };
+        canvas.check_unread(sprite_sheets)?;
+        let mut encoder = self
+            .ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("render target encoder"),
+            });
+        self.draw_scene(&mut encoder, Some(canvas), sprite_sheets, scene)?;
+        // Buffers written while drawing are shared with other scenes, so they're submitted now.
         self.ctx.queue.submit(Some(encoder.finish()));
-        surface_texture.present();
         Ok(())
     }
+
+    /// Upscale the canvas to a view the size of the surface, through the post-processing chain.
+    fn upscale(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        self.post_renderer.render(
+            encoder,
+            &self.upscale_renderer,
+            &self.post_effects,
+            view,
+            self.surface_size(),
+        );
+    }
+
+    /// Copy the canvas, as drawn by the last call to [Renderer::render], back to the CPU.
+    /// Pixels are returned in `Rgba8UnormSrgb`, top-to-bottom left-to-right.
+    pub fn read_canvas(&self) -> Result<Vec<u8>, RenderError> {
+        let canvas = &self.canvas;
+        read_texture(&self.ctx, &canvas.texture, canvas.size)
+    }
+
+    /// Copy the canvas or the upscaled image back to the CPU, as drawn by the last call to
+    /// [Renderer::render].
+    /// The upscaled image is drawn again off-screen, as window surfaces can't be read back.
+    #[cfg(feature = "capture")]
+    pub fn capture_frame(&self, capture: capture::Capture) -> Result<capture::Frame, RenderError> {
+        let (size, pixels) = match capture {
+            capture::Capture::Canvas => (self.canvas.size, self.read_canvas()?),
+            capture::Capture::Upscaled => {
+                let size = self.surface_size();
+                let target = OutputTexture::new(&self.ctx.device, size, self.ctx.color_format);
+                let mut encoder =
+                    self.ctx
+                        .device
+                        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                            label: Some("capture encoder"),
+                        });
+                self.upscale(&mut encoder, &target.view);
+                self.ctx.queue.submit(Some(encoder.finish()));
+                (size, read_texture(&self.ctx, &target.texture, size)?)
+            }
+        };
+        Ok(capture::Frame { size, pixels })
+    }
+
+    /// Copy the upscaled output of a headless renderer back to the CPU, in the same format as
+    /// [Renderer::read_canvas].
+    /// Fails with [RenderError::NoOutput] if the renderer wasn't created with an output size.
+    pub fn read_output(&self) -> Result<Vec<u8>, RenderError> {
+        let output = self.output.as_ref().ok_or(RenderError::NoOutput)?;
+        read_texture(&self.ctx, &output.texture, output.size)
+    }
+}
+
+/// Limits that devices are requested with.
+fn limits() -> wgpu::Limits {
+    let mut limits = wgpu::Limits::downlevel_webgl2_defaults();
+    limits.max_texture_dimension_2d = 8192;
+    limits
+}
+
+/// Create GPU objects from user input, such as shaders,
+/// reporting validation errors instead of panicking.
+fn validate<T>(device: &wgpu::Device, create: impl FnOnce() -> T) -> Result<T, String> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let created = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(err) => Err(err.to_string()),
+        None => Ok(created),
+    }
+}
+
+/// Copy a texture in the canvas format to the CPU, converting it to RGBA.
+fn read_texture(
+    ctx: &Context,
+    texture: &wgpu::Texture,
+    size: Size,
+) -> Result<Vec<u8>, RenderError> {
+    // Rows of the copy must be aligned, so padding is removed afterwards.
+    let row_bytes = size.width * 4;
+    let padded_row_bytes = row_bytes.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback"),
+        size: (padded_row_bytes * size.height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = ctx
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("readback encoder"),
+        });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_bytes),
+                rows_per_image: None,
+            },
+        },
+        wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        },
+    );
+    ctx.queue.submit(Some(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        // The receiver outlives the wait below, so sending can't fail.
+        let _ = sender.send(result);
+    });
+    ctx.device.poll(wgpu::Maintain::Wait);
+    match receiver.try_recv() {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => {
+            error!("Couldn't map readback buffer: {err}");
+            return Err(RenderError::ReadBack);
+        }
+        Err(_) => {
+            error!("Readback buffer wasn't mapped after waiting");
+            return Err(RenderError::ReadBack);
+        }
+    }
+
+    let mut pixels = Vec::with_capacity((row_bytes * size.height) as usize);
+    for row in slice.get_mapped_range().chunks(padded_row_bytes as usize) {
+        pixels.extend_from_slice(&row[..row_bytes as usize]);
+    }
+    buffer.unmap();
+
+    let bgra = matches!(
+        ctx.color_format,
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+    );
+    if bgra {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+    Ok(pixels)
 }
 
 struct Context {
     device: wgpu::Device,
     queue: wgpu::Queue,
     limits: wgpu::Limits,
-    shaders: wgpu::ShaderModule,
-    canvas: Canvas,
+    shaders: std::rc::Rc<wgpu::ShaderModule>,
+    /// Format of the canvas and of the surface.
+    color_format: wgpu::TextureFormat,
+    /// Layout of the canvas dimensions that scene pipelines read.
+    dimensions_layout: wgpu::BindGroupLayout,
     quad_buffer: wgpu::Buffer,
 }
 
-/// A texture that can both be a target and source
-struct Canvas {
-    _texture: wgpu::Texture,
+/// Off-screen texture the size of the output,
+/// that headless renderers upscale the canvas to and post-processing passes draw to.
+struct OutputTexture {
+    texture: wgpu::Texture,
     view: wgpu::TextureView,
-    color_format: wgpu::TextureFormat,
     size: Size,
-    _dimensions_buffer: wgpu::Buffer,
-    dimensions_layout: wgpu::BindGroupLayout,
-    dimensions_bind_group: wgpu::BindGroup,
 }
 
-impl Canvas {
-    fn new(
-        device: &wgpu::Device,
-        name: &str,
-        size: Size,
-        color_format: wgpu::TextureFormat,
-    ) -> Self {
-        let dimensions_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(name),
-            contents: bytemuck::cast_slice(&[size.width, size.height, 0, 0]),
-            usage: wgpu::BufferUsages::UNIFORM,
-        });
-
-        let dimensions_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some(name),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    // minimum size has to be 16 rather than 8 due to WebGL2 layout rules
-                    min_binding_size: Some(std::num::NonZeroU64::new(16).unwrap()),
-                },
-                count: None,
-            }],
-        });
-
-        let dimensions_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some(name),
-            layout: &dimensions_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: &dimensions_buffer,
-                    offset: 0,
-                    size: None,
-                }),
-            }],
-        });
-
+impl OutputTexture {
+    fn new(device: &wgpu::Device, size: Size, color_format: wgpu::TextureFormat) -> Self {
         let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some(name),
+            label: Some("output"),
             size: wgpu::Extent3d {
                 width: size.width,
                 height: size.height,
@@ -335,20 +943,16 @@ impl Canvas {
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: color_format,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[color_format],
         });
-
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-
         Self {
-            _texture: texture,
+            texture,
             view,
-            color_format,
             size,
-            _dimensions_buffer: dimensions_buffer,
-            dimensions_layout,
-            dimensions_bind_group,
         }
     }
 }
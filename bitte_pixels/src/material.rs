@@ -0,0 +1,495 @@
+//! Custom shaders for sprites and rectangles, for effects like water ripples or hit flashes.
+//!
+//! A material is WGSL source defining a fragment function `material_f`,
+//! which takes a `MaterialInter` and returns a premultiplied color.
+//! The source is appended to a prelude that provides:
+//!
+//! - `canvas_info` and `pixel_to_ndc`, as used by the built-in shaders.
+//! - `MaterialInter`, the fragment input: the `local` position within the item in pixels
+//!   from its bottom left corner, its `size`, and the tint or rectangle `color`.
+//! - `sprite_texel(in, pixel)`, a pixel of the sprite being drawn,
+//!   and `sprite_color(in)`, the color the sprite would have without a material.
+//! - `material`, the uniform block of the material, if it has one.
+//!   Its type is a `MaterialUniforms` struct, which the source must define.
+//!
+//! The source may also define `material_vertex`, which takes a `MaterialVertex` with a corner
+//! of the item and returns a `MaterialInter`, to move corners around.
+//! Otherwise `default_material_vertex` is used.
+//!
+//! Sprites and rectangles keep their own blend mode when drawn with a material.
+//!
+//! ```ignore
+//! let flash = renderer.create_material(MaterialDescriptor {
+//!     label: "hit flash",
+//!     source: "
+//!         struct MaterialUniforms { amount: f32 }
+//!         @fragment fn material_f(in: MaterialInter) -> @location(0) vec4<f32> {
+//!             let color = sprite_color(in);
+//!             return vec4<f32>(mix(color.rgb, vec3<f32>(color.a), material.amount), color.a);
+//!         }
+//!     ",
+//!     uniforms: bytemuck::bytes_of(&0.0f32),
+//! })?;
+//! flash.set_uniforms(bytemuck::bytes_of(&1.0f32));
+//! scene.material_draws.push(MaterialDraw {
+//!     material: &flash,
+//!     sprites: vec![enemy],
+//!     rectangles: Vec::new(),
+//! });
+//! ```
+
+use std::{cell::RefCell, ops::Range, rc::Rc};
+
+#[cfg(feature = "hot-reload")]
+use super::hot_reload::{ShaderFile, Tracked, TrackedSource};
+use super::{
+    blend,
+    rect::{self, Rectangle},
+    sprite::{self, HandleError, InstanceData, SpriteInstance, SpriteSheet},
+    BlendMode,
+};
+
+const PRELUDE: &str = concat!(
+    include_str!("shaders/prelude.wgsl"),
+    include_str!("shaders/material.wgsl")
+);
+
+/// Maximum amount of sprites, and of rectangles, drawn with materials in a single frame.
+const MAX_INSTANCES: u64 = 2048;
+
+/// What a material is created from, with [crate::Renderer::create_material].
+#[derive(Clone, Copy, Debug)]
+pub struct MaterialDescriptor<'a> {
+    pub label: &'a str,
+    /// WGSL source, as described in the [module documentation](self).
+    pub source: &'a str,
+    /// Initial contents of the uniform block, or nothing if the material has none.
+    pub uniforms: &'a [u8],
+}
+
+/// Sprites and rectangles to draw with a material.
+#[derive(Clone)]
+pub struct MaterialDraw<'a> {
+    pub material: &'a Material,
+    pub sprites: Vec<SpriteInstance>,
+    /// Rectangles are filled, unlike those drawn without a material,
+    /// so that the material can shade their whole area.
+    pub rectangles: Vec<Rectangle>,
+}
+
+/// A custom shader, with its uniform block.
+/// Cloning a material is cheap, and clones share their uniforms.
+#[derive(Clone)]
+pub struct Material {
+    inner: Rc<MaterialInner>,
+}
+
+struct MaterialInner {
+    ctx: Rc<super::Context>,
+    /// Replaced when the material is reloaded.
+    pipelines: RefCell<Rc<Pipelines>>,
+    /// Size of the uniform block in bytes, before padding.
+    uniform_size: usize,
+    uniforms: Option<(wgpu::Buffer, wgpu::BindGroup)>,
+}
+
+impl Material {
+    /// Replace the contents of the uniform block, from the next frame rendered on.
+    ///
+    /// # Panics
+    ///
+    /// If the size differs from the uniforms the material was created with.
+    pub fn set_uniforms(&self, uniforms: &[u8]) {
+        let inner = &self.inner;
+        assert_eq!(
+            uniforms.len(),
+            inner.uniform_size,
+            "uniforms of a material can't change size"
+        );
+        if let Some((buffer, _)) = &inner.uniforms {
+            inner.ctx.queue.write_buffer(buffer, 0, uniforms);
+        }
+    }
+}
+
+/// What a material draws sprites and rectangles with.
+pub(crate) struct Pipelines {
+    sprite: blend::Pipelines,
+    rect: blend::Pipelines,
+}
+
+/// The pipelines of each draw, kept alive while a frame is drawn,
+/// as materials may be reloaded between frames.
+pub(crate) fn frame_pipelines(draws: &[MaterialDraw]) -> Vec<Rc<Pipelines>> {
+    draws
+        .iter()
+        .map(|draw| draw.material.inner.pipelines.borrow().clone())
+        .collect()
+}
+
+pub(crate) struct Renderer {
+    ctx: Rc<super::Context>,
+    /// Shared by every material, and followed by its source.
+    prelude: String,
+    #[cfg(feature = "hot-reload")]
+    tracked: RefCell<Vec<Tracked<MaterialInner>>>,
+    uniform_layout: wgpu::BindGroupLayout,
+    /// For materials without uniforms.
+    pipeline_layout: Rc<wgpu::PipelineLayout>,
+    uniform_pipeline_layout: Rc<wgpu::PipelineLayout>,
+    sprite_instances: wgpu::Buffer,
+    rect_instances: wgpu::Buffer,
+}
+
+impl Renderer {
+    pub(crate) fn new(
+        ctx: Rc<super::Context>,
+        sprite_sheet_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let device = &ctx.device;
+
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("material uniforms"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("material"),
+            bind_group_layouts: &[&ctx.dimensions_layout, sprite_sheet_layout],
+            push_constant_ranges: &[],
+        });
+
+        let uniform_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("material with uniforms"),
+                bind_group_layouts: &[&ctx.dimensions_layout, sprite_sheet_layout, &uniform_layout],
+                push_constant_ranges: &[],
+            });
+
+        let sprite_instances = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("material sprite instances"),
+            size: MAX_INSTANCES * std::mem::size_of::<InstanceData>() as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let rect_instances = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("material rect instances"),
+            size: MAX_INSTANCES * std::mem::size_of::<Rectangle>() as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            ctx,
+            prelude: PRELUDE.to_owned(),
+            #[cfg(feature = "hot-reload")]
+            tracked: RefCell::new(Vec::new()),
+            uniform_layout,
+            pipeline_layout: Rc::new(pipeline_layout),
+            uniform_pipeline_layout: Rc::new(uniform_pipeline_layout),
+            sprite_instances,
+            rect_instances,
+        }
+    }
+
+    /// Compile a material, reporting WGSL errors instead of panicking.
+    pub(crate) fn create_material(
+        &self,
+        descriptor: &MaterialDescriptor,
+    ) -> Result<Material, String> {
+        let material = self.compile(descriptor)?;
+        #[cfg(feature = "hot-reload")]
+        self.tracked.borrow_mut().push(Tracked {
+            item: Rc::downgrade(&material.inner),
+            label: descriptor.label.to_owned(),
+            source: TrackedSource::Text(descriptor.source.to_owned()),
+        });
+        Ok(material)
+    }
+
+    /// Compile a material from a file, and recompile it whenever the file changes.
+    #[cfg(feature = "hot-reload")]
+    pub(crate) fn load_material(
+        &self,
+        path: &std::path::Path,
+        uniforms: &[u8],
+    ) -> Result<Material, String> {
+        let file =
+            ShaderFile::load(path).map_err(|err| format!("couldn't read {path:?}: {err}"))?;
+        let label = path.display().to_string();
+        let material = self.compile(&MaterialDescriptor {
+            label: &label,
+            source: &file.source,
+            uniforms,
+        })?;
+        self.tracked.borrow_mut().push(Tracked {
+            item: Rc::downgrade(&material.inner),
+            label,
+            source: TrackedSource::File(file),
+        });
+        Ok(material)
+    }
+
+    /// Recompile materials whose file changed, or every material if the prelude changed.
+    /// Materials that fail to compile keep their previous pipelines.
+    #[cfg(feature = "hot-reload")]
+    pub(crate) fn reload(&mut self, prelude: Option<String>) {
+        let prelude_changed = prelude.is_some();
+        if let Some(prelude) = prelude {
+            self.prelude = prelude;
+        }
+        self.tracked.borrow_mut().retain_mut(|tracked| {
+            let Some(material) = tracked.item.upgrade() else {
+                return false;
+            };
+            if tracked.source.poll() | prelude_changed {
+                let has_uniforms = material.uniforms.is_some();
+                match self.create_pipelines(&tracked.label, tracked.source.get(), has_uniforms) {
+                    Ok(pipelines) => {
+                        *material.pipelines.borrow_mut() = Rc::new(pipelines);
+                        info!("reloaded material {:?}", tracked.label);
+                    }
+                    Err(err) => error!("couldn't reload material {:?}: {err}", tracked.label),
+                }
+            }
+            true
+        });
+    }
+
+    fn compile(&self, descriptor: &MaterialDescriptor) -> Result<Material, String> {
+        let device = &self.ctx.device;
+        let has_uniforms = !descriptor.uniforms.is_empty();
+        let pipelines = self.create_pipelines(descriptor.label, descriptor.source, has_uniforms)?;
+
+        let uniforms = has_uniforms.then(|| {
+            // Uniform buffers are padded to a multiple of 16 bytes for WebGL.
+            let size = (descriptor.uniforms.len() as u64).next_multiple_of(16);
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(descriptor.label),
+                size,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: true,
+            });
+            buffer.slice(..).get_mapped_range_mut()[..descriptor.uniforms.len()]
+                .copy_from_slice(descriptor.uniforms);
+            buffer.unmap();
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(descriptor.label),
+                layout: &self.uniform_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+            });
+            (buffer, bind_group)
+        });
+
+        Ok(Material {
+            inner: Rc::new(MaterialInner {
+                ctx: self.ctx.clone(),
+                pipelines: RefCell::new(Rc::new(pipelines)),
+                uniform_size: descriptor.uniforms.len(),
+                uniforms,
+            }),
+        })
+    }
+
+    fn create_pipelines(
+        &self,
+        label: &str,
+        source: &str,
+        has_uniforms: bool,
+    ) -> Result<Pipelines, String> {
+        let device = &self.ctx.device;
+        let mut full_source = format!("{}\n{source}\n", self.prelude);
+        if has_uniforms {
+            full_source
+                .push_str("@group(2) @binding(0)\nvar<uniform> material: MaterialUniforms;\n");
+        }
+        if !source.contains("fn material_vertex") {
+            full_source.push_str(
+                "fn material_vertex(v: MaterialVertex) -> MaterialInter {\n    \
+                 return default_material_vertex(v);\n}\n",
+            );
+        }
+
+        let layout = if has_uniforms {
+            &self.uniform_pipeline_layout
+        } else {
+            &self.pipeline_layout
+        };
+
+        super::validate(device, || {
+            let module = Rc::new(device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(label),
+                source: wgpu::ShaderSource::Wgsl(full_source.into()),
+            }));
+            let pipelines =
+                |vertex_entry_point, instance_layout: wgpu::VertexBufferLayout<'static>| {
+                    let ctx = self.ctx.clone();
+                    let label = label.to_owned();
+                    let layout = layout.clone();
+                    blend::Pipelines::new(module.clone(), move |module, blend| {
+                        create_pipeline(
+                            &ctx,
+                            &label,
+                            &layout,
+                            module,
+                            vertex_entry_point,
+                            instance_layout.clone(),
+                            blend,
+                        )
+                    })
+                };
+            Pipelines {
+                sprite: pipelines("material_sprite_v", sprite::INSTANCE_LAYOUT),
+                rect: pipelines("material_rect_v", rect::RECTANGLE_LAYOUT),
+            }
+        })
+    }
+
+    pub(crate) fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        sprite_renderer: &'a sprite::Renderer,
+        sprite_sheets: &[&'a SpriteSheet],
+        draws: &'a [MaterialDraw<'a>],
+        pipelines: &'a [Rc<Pipelines>],
+    ) -> Result<(), HandleError> {
+        // Sprites of every draw share one instance buffer, as do rectangles.
+        // Sprites are batched by page and blend mode like in the sprite renderer,
+        // but never across draws.
+        let mut sprite_batches: Vec<(usize, &'a sprite::SheetPage, BlendMode, Range<u32>)> =
+            Vec::new();
+        let mut sprites: Vec<InstanceData> = Vec::new();
+        let mut rect_ranges: Vec<Range<u32>> = Vec::new();
+        let mut rectangles: Vec<Rectangle> = Vec::new();
+        for (index, draw) in draws.iter().enumerate() {
+            for sprite in &draw.sprites {
+                let Some((page, entry)) = sprite_renderer.resolve(sprite_sheets, sprite.sprite)?
+                else {
+                    continue;
+                };
+                let start = sprites.len() as u32;
+                match sprite_batches.last_mut() {
+                    Some((last_index, last, mode, range))
+                        if *last_index == index
+                            && std::ptr::eq(*last, page)
+                            && *mode == sprite.blend =>
+                    {
+                        range.end += 1
+                    }
+                    _ => sprite_batches.push((index, page, sprite.blend, start..start + 1)),
+                }
+                sprites.push(InstanceData::new(entry, sprite.position));
+            }
+            let start = rectangles.len() as u32;
+            rectangles.extend_from_slice(&draw.rectangles);
+            rect_ranges.push(start..rectangles.len() as u32);
+        }
+        if sprites.len() as u64 > MAX_INSTANCES {
+            warn!("Only the first {MAX_INSTANCES} sprites with materials are drawn");
+            sprites.truncate(MAX_INSTANCES as usize);
+        }
+        if rectangles.len() as u64 > MAX_INSTANCES {
+            warn!("Only the first {MAX_INSTANCES} rectangles with materials are drawn");
+            rectangles.truncate(MAX_INSTANCES as usize);
+        }
+        let clamp = |range: Range<u32>| {
+            range.start.min(MAX_INSTANCES as u32)..range.end.min(MAX_INSTANCES as u32)
+        };
+        self.ctx.queue.write_buffer(
+            &self.sprite_instances,
+            0,
+            bytemuck::cast_slice(sprites.as_slice()),
+        );
+        self.ctx.queue.write_buffer(
+            &self.rect_instances,
+            0,
+            bytemuck::cast_slice(rectangles.as_slice()),
+        );
+
+        render_pass.set_vertex_buffer(0, self.ctx.quad_buffer.slice(..));
+        let mut batches = sprite_batches.into_iter().peekable();
+        for (index, (draw, pipelines)) in draws.iter().zip(pipelines).enumerate() {
+            let material = &draw.material.inner;
+            if let Some((_, bind_group)) = &material.uniforms {
+                render_pass.set_bind_group(2, bind_group, &[]);
+            }
+
+            render_pass.set_vertex_buffer(1, self.sprite_instances.slice(..));
+            while let Some((_, page, mode, range)) = batches.next_if(|(i, _, _, _)| *i == index) {
+                let range = clamp(range);
+                let Some(bind_group) = page.bind_group() else {
+                    continue;
+                };
+                render_pass.set_pipeline(pipelines.sprite.get(mode));
+                render_pass.set_bind_group(1, bind_group, &[]);
+                render_pass.draw(0..super::QUAD_VERTICES.len() as u32, range);
+            }
+
+            let range = rect_ranges[index].clone();
+            if !range.is_empty() {
+                // Rectangles don't read from a sprite sheet, but the layout expects one.
+                let Some(bind_group) = sprite_renderer.placeholder_page().bind_group() else {
+                    continue;
+                };
+                render_pass.set_bind_group(1, bind_group, &[]);
+                render_pass.set_vertex_buffer(1, self.rect_instances.slice(..));
+                for (mode, run) in blend::runs(&draw.rectangles, |rect| rect.blend) {
+                    render_pass.set_pipeline(pipelines.rect.get(mode));
+                    let run = clamp(range.start + run.start..range.start + run.end);
+                    render_pass.draw(0..super::QUAD_VERTICES.len() as u32, run);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn create_pipeline(
+    ctx: &super::Context,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    vertex_entry_point: &str,
+    instance_layout: wgpu::VertexBufferLayout,
+    blend: wgpu::BlendState,
+) -> wgpu::RenderPipeline {
+    ctx.device
+        .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module,
+                entry_point: vertex_entry_point,
+                buffers: &[super::QUAD_LAYOUT, instance_layout],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module,
+                entry_point: "material_f",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: ctx.color_format,
+                    blend: Some(blend),
+                    write_mask: wgpu::ColorWrites::all(),
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            multisample: wgpu::MultisampleState::default(),
+            depth_stencil: None,
+            multiview: None,
+        })
+}
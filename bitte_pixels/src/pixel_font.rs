@@ -0,0 +1,464 @@
+//! Loading of BDF and PCF bitmap fonts, as used by X11 and most free pixel fonts.
+//!
+//! Files are parsed into a [PixelFont], which holds one-bit glyph bitmaps and their metrics.
+//! [PixelFont::to_font] adds the glyphs to a sprite sheet and creates a [Font] for drawing text.
+//!
+//! Character encodings are read as Unicode code points,
+//! which also covers fonts encoded in ISO 8859-1.
+//! Compressed `.pcf.gz` files must be decompressed first.
+
+use std::{collections::HashMap, path::Path};
+
+use super::{
+    sprite::{SpriteData, SpriteSheetBuilder},
+    text::{Font, Glyph},
+};
+
+/// Metrics and bitmap of a single glyph.
+#[derive(Clone, Debug)]
+pub struct GlyphBitmap {
+    pub char: char,
+    /// Width and height of the bitmap in pixels.
+    pub dimensions: (u32, u32),
+    /// Position of the bitmap's bottom-left corner relative to the glyph origin on the baseline.
+    pub offset: (i32, i32),
+    /// Distance to the next glyph origin in pixels.
+    pub advance: i32,
+    /// Set pixels, row by row from the top.
+    pub bits: Vec<bool>,
+}
+
+/// A bitmap font read from a BDF or PCF file.
+#[derive(Clone, Debug)]
+pub struct PixelFont {
+    pub glyphs: Vec<GlyphBitmap>,
+    /// Pixels above the baseline.
+    pub ascent: i32,
+    /// Pixels below the baseline.
+    pub descent: i32,
+    /// Character to draw in place of characters the font doesn't have.
+    pub default_char: Option<char>,
+}
+
+#[derive(Debug)]
+pub enum FontFileError {
+    Io(std::io::Error),
+    Format,
+    Corrupt(&'static str),
+}
+
+impl From<std::io::Error> for FontFileError {
+    fn from(err: std::io::Error) -> Self {
+        FontFileError::Io(err)
+    }
+}
+
+impl std::fmt::Display for FontFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FontFileError::Io(err) => write!(f, "couldn't read font: {err}"),
+            FontFileError::Format => f.write_str("not a BDF or PCF font file"),
+            FontFileError::Corrupt(err) => write!(f, "corrupt font: {err}"),
+        }
+    }
+}
+
+impl PixelFont {
+    /// Load a `.bdf` or `.pcf` file, detecting the format from its contents.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, FontFileError> {
+        let data = std::fs::read(path)?;
+        if data.starts_with(PCF_MAGIC) {
+            Self::parse_pcf(&data)
+        } else {
+            let source = std::str::from_utf8(&data).map_err(|_| FontFileError::Format)?;
+            Self::parse_bdf(source)
+        }
+    }
+
+    /// Add the glyphs to a sprite sheet as white pixels, to be tinted when drawn.
+    pub fn to_font(&self, builder: &mut SpriteSheetBuilder) -> Font {
+        let mut font = Font::new((self.ascent + self.descent).max(0) as u32);
+        for glyph in &self.glyphs {
+            let data = glyph
+                .bits
+                .iter()
+                .flat_map(|&set| if set { [255; 4] } else { [0; 4] })
+                .collect();
+            // Glyph sprites are placed relative to the bottom of the line, not the baseline.
+            let offset = (glyph.offset.0, glyph.offset.1 + self.descent);
+            let sprite = match glyph.bits.contains(&true) {
+                true => SpriteData::new(glyph.dimensions, offset, data).map(|s| builder.add(s)),
+                false => None,
+            };
+            let advance = glyph.advance;
+            font.add_glyph(glyph.char, Glyph { sprite, advance });
+        }
+        font.set_fallback(self.default_char);
+        font
+    }
+
+    /// Parse the text-based Glyph Bitmap Distribution Format.
+    pub fn parse_bdf(source: &str) -> Result<Self, FontFileError> {
+        let mut lines = source.lines().map(str::trim);
+        if !lines
+            .next()
+            .is_some_and(|line| line.starts_with("STARTFONT"))
+        {
+            return Err(FontFileError::Format);
+        }
+
+        let mut properties = HashMap::new();
+        let mut bounding_box = None;
+        let mut glyphs = Vec::new();
+        while let Some(line) = lines.next() {
+            let (keyword, args) = line.split_once(' ').unwrap_or((line, ""));
+            match keyword {
+                "FONTBOUNDINGBOX" => bounding_box = Some(numbers::<4>(args)?),
+                "STARTPROPERTIES" => {
+                    for line in lines.by_ref() {
+                        if line == "ENDPROPERTIES" {
+                            break;
+                        }
+                        if let Some((name, value)) = line.split_once(' ') {
+                            properties.insert(name, value.trim().trim_matches('"'));
+                        }
+                    }
+                }
+                "STARTCHAR" => {
+                    if let Some(glyph) = parse_bdf_char(&mut lines)? {
+                        glyphs.push(glyph);
+                    }
+                }
+                "ENDFONT" => break,
+                _ => {}
+            }
+        }
+
+        let property = |name: &str| properties.get(name).and_then(|v| v.parse::<i32>().ok());
+        let [_, height, _, y_offset] = bounding_box.unwrap_or_default();
+        Ok(PixelFont {
+            glyphs,
+            ascent: property("FONT_ASCENT").unwrap_or(height + y_offset),
+            descent: property("FONT_DESCENT").unwrap_or(-y_offset),
+            default_char: property("DEFAULT_CHAR").and_then(|c| char::from_u32(c as u32)),
+        })
+    }
+
+    /// Parse the binary Portable Compiled Format.
+    pub fn parse_pcf(data: &[u8]) -> Result<Self, FontFileError> {
+        if !data.starts_with(PCF_MAGIC) {
+            return Err(FontFileError::Format);
+        }
+        let header = Reader::new(data, 4, false);
+        let table_count = header.u32(0)? as usize;
+        let mut tables = HashMap::new();
+        for i in 0..table_count {
+            let entry = 4 + i * 16;
+            let kind = header.u32(entry)?;
+            let size = header.u32(entry + 8)? as usize;
+            let offset = header.u32(entry + 12)? as usize;
+            let table = data
+                .get(offset..offset + size)
+                .ok_or(FontFileError::Corrupt("table outside of file"))?;
+            tables.insert(kind, table);
+        }
+        let table = |kind| {
+            tables
+                .get(&kind)
+                .copied()
+                .ok_or(FontFileError::Corrupt("missing table"))
+        };
+
+        let metrics = parse_pcf_metrics(table(PCF_METRICS)?)?;
+        let bitmaps = PcfBitmaps::new(table(PCF_BITMAPS)?, metrics.len())?;
+
+        let accelerators = match tables.get(&PCF_BDF_ACCELERATORS) {
+            Some(table) => table,
+            None => table(PCF_ACCELERATORS)?,
+        };
+        let accelerators = Reader::table(accelerators)?;
+        let ascent = accelerators.i32(8)?;
+        let descent = accelerators.i32(12)?;
+
+        let encodings = Reader::table(table(PCF_BDF_ENCODINGS)?)?;
+        let min_byte2 = encodings.u16(0)? as u32;
+        let max_byte2 = encodings.u16(2)? as u32;
+        let min_byte1 = encodings.u16(4)? as u32;
+        let max_byte1 = encodings.u16(6)? as u32;
+        let default_char = encodings.u16(8)? as u32;
+        let row_length = (max_byte2 + 1).saturating_sub(min_byte2);
+
+        let mut glyphs = Vec::new();
+        for byte1 in min_byte1..=max_byte1 {
+            for byte2 in min_byte2..=max_byte2 {
+                let entry = (byte1 - min_byte1) * row_length + byte2 - min_byte2;
+                let index = encodings.u16(10 + entry as usize * 2)? as usize;
+                let Some(metric) = metrics.get(index) else {
+                    continue;
+                };
+                let Some(char) = char::from_u32(byte1 << 8 | byte2) else {
+                    continue;
+                };
+                glyphs.push(GlyphBitmap {
+                    char,
+                    dimensions: metric.dimensions(),
+                    offset: (metric.left_bearing, -metric.descent),
+                    advance: metric.width,
+                    bits: bitmaps.glyph(index, metric.dimensions())?,
+                });
+            }
+        }
+
+        Ok(PixelFont {
+            glyphs,
+            ascent,
+            descent,
+            default_char: char::from_u32(default_char),
+        })
+    }
+}
+
+/// Parse whitespace-separated integers.
+fn numbers<const N: usize>(args: &str) -> Result<[i32; N], FontFileError> {
+    let mut numbers = [0; N];
+    let mut args = args.split_whitespace();
+    for number in &mut numbers {
+        *number = args
+            .next()
+            .and_then(|arg| arg.parse().ok())
+            .ok_or(FontFileError::Corrupt("bad number"))?;
+    }
+    Ok(numbers)
+}
+
+/// Parse a BDF glyph, after its `STARTCHAR` line.
+/// Glyphs without a standard encoding are skipped.
+fn parse_bdf_char<'a>(
+    lines: &mut impl Iterator<Item = &'a str>,
+) -> Result<Option<GlyphBitmap>, FontFileError> {
+    let mut encoding = None;
+    let mut advance = 0;
+    let mut bbx = [0; 4];
+    let mut bits = Vec::new();
+    while let Some(line) = lines.next() {
+        let (keyword, args) = line.split_once(' ').unwrap_or((line, ""));
+        match keyword {
+            "ENCODING" => encoding = Some(numbers::<1>(args)?[0]),
+            "DWIDTH" => advance = numbers::<1>(args)?[0],
+            "BBX" => bbx = numbers::<4>(args)?,
+            "BITMAP" => {
+                let [width, height, ..] = bbx;
+                for _ in 0..height {
+                    let row = lines
+                        .next()
+                        .ok_or(FontFileError::Corrupt("bitmap ended early"))?;
+                    for x in 0..width.max(0) as usize {
+                        let byte = row
+                            .get(x / 8 * 2..x / 8 * 2 + 2)
+                            .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                            .ok_or(FontFileError::Corrupt("bad bitmap row"))?;
+                        bits.push(byte >> (7 - x % 8) & 1 == 1);
+                    }
+                }
+            }
+            "ENDCHAR" => break,
+            _ => {}
+        }
+    }
+
+    let [width, height, x_offset, y_offset] = bbx;
+    if width < 0 || height < 0 || bits.len() != (width * height) as usize {
+        return Err(FontFileError::Corrupt("bitmap doesn't match bounding box"));
+    }
+    let char = encoding
+        .filter(|&e| e >= 0)
+        .and_then(|e| char::from_u32(e as u32));
+    Ok(char.map(|char| GlyphBitmap {
+        char,
+        dimensions: (width as u32, height as u32),
+        offset: (x_offset, y_offset),
+        advance,
+        bits,
+    }))
+}
+
+const PCF_MAGIC: &[u8; 4] = b"\x01fcp";
+const PCF_ACCELERATORS: u32 = 1 << 1;
+const PCF_METRICS: u32 = 1 << 2;
+const PCF_BITMAPS: u32 = 1 << 3;
+const PCF_BDF_ENCODINGS: u32 = 1 << 5;
+const PCF_BDF_ACCELERATORS: u32 = 1 << 8;
+
+const PCF_BYTE_MASK: u32 = 1 << 2;
+const PCF_BIT_MASK: u32 = 1 << 3;
+const PCF_COMPRESSED_METRICS: u32 = 0x100;
+
+/// Reads integers from a PCF table in the table's byte order.
+struct Reader<'a> {
+    data: &'a [u8],
+    start: usize,
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], start: usize, big_endian: bool) -> Self {
+        Self {
+            data,
+            start,
+            big_endian,
+        }
+    }
+
+    /// Reader for the contents of a table, after its format field.
+    fn table(data: &'a [u8]) -> Result<Self, FontFileError> {
+        let format = Reader::new(data, 0, false).u32(0)?;
+        Ok(Self::new(data, 4, format & PCF_BYTE_MASK != 0))
+    }
+
+    fn bytes<const N: usize>(&self, offset: usize) -> Result<[u8; N], FontFileError> {
+        let start = self.start + offset;
+        let bytes = self
+            .data
+            .get(start..start + N)
+            .ok_or(FontFileError::Corrupt("table ended early"))?;
+        let mut bytes: [u8; N] = bytes.try_into().unwrap();
+        if self.big_endian {
+            bytes.reverse();
+        }
+        Ok(bytes)
+    }
+
+    fn u8(&self, offset: usize) -> Result<u8, FontFileError> {
+        Ok(self.bytes::<1>(offset)?[0])
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, FontFileError> {
+        Ok(u16::from_le_bytes(self.bytes(offset)?))
+    }
+
+    fn i16(&self, offset: usize) -> Result<i16, FontFileError> {
+        Ok(i16::from_le_bytes(self.bytes(offset)?))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, FontFileError> {
+        Ok(u32::from_le_bytes(self.bytes(offset)?))
+    }
+
+    fn i32(&self, offset: usize) -> Result<i32, FontFileError> {
+        Ok(i32::from_le_bytes(self.bytes(offset)?))
+    }
+}
+
+struct PcfMetric {
+    left_bearing: i32,
+    right_bearing: i32,
+    width: i32,
+    ascent: i32,
+    descent: i32,
+}
+
+impl PcfMetric {
+    fn dimensions(&self) -> (u32, u32) {
+        (
+            (self.right_bearing - self.left_bearing).max(0) as u32,
+            (self.ascent + self.descent).max(0) as u32,
+        )
+    }
+}
+
+fn parse_pcf_metrics(table: &[u8]) -> Result<Vec<PcfMetric>, FontFileError> {
+    let format = Reader::new(table, 0, false).u32(0)?;
+    let reader = Reader::table(table)?;
+    let mut metrics = Vec::new();
+    if format & PCF_COMPRESSED_METRICS != 0 {
+        let value = |offset| Ok::<_, FontFileError>(reader.u8(offset)? as i32 - 0x80);
+        for i in 0..reader.u16(0)? as usize {
+            let entry = 2 + i * 5;
+            metrics.push(PcfMetric {
+                left_bearing: value(entry)?,
+                right_bearing: value(entry + 1)?,
+                width: value(entry + 2)?,
+                ascent: value(entry + 3)?,
+                descent: value(entry + 4)?,
+            });
+        }
+    } else {
+        let value = |offset| Ok::<_, FontFileError>(reader.i16(offset)? as i32);
+        for i in 0..reader.u32(0)? as usize {
+            let entry = 4 + i * 12;
+            metrics.push(PcfMetric {
+                left_bearing: value(entry)?,
+                right_bearing: value(entry + 2)?,
+                width: value(entry + 4)?,
+                ascent: value(entry + 6)?,
+                descent: value(entry + 8)?,
+            });
+        }
+    }
+    Ok(metrics)
+}
+
+/// The bitmap table, with rows padded and bits ordered as given by its format.
+struct PcfBitmaps<'a> {
+    offsets: Reader<'a>,
+    data: &'a [u8],
+    /// Row alignment in bytes.
+    row_padding: usize,
+    /// Size of the units that bytes are swapped in, when byte and bit order differ.
+    scan_unit: usize,
+    swap_bytes: bool,
+    msb_first: bool,
+}
+
+impl<'a> PcfBitmaps<'a> {
+    fn new(table: &'a [u8], glyph_count: usize) -> Result<Self, FontFileError> {
+        let format = Reader::new(table, 0, false).u32(0)?;
+        let reader = Reader::table(table)?;
+        if reader.u32(0)? as usize != glyph_count {
+            return Err(FontFileError::Corrupt("metrics and bitmaps don't match"));
+        }
+        let padding = (format & 3) as usize;
+        let size = reader.u32(4 + glyph_count * 4 + padding * 4)? as usize;
+        let start = 4 + 4 + glyph_count * 4 + 16;
+        let data = table
+            .get(start..start + size)
+            .ok_or(FontFileError::Corrupt("bitmaps outside of table"))?;
+        let big_endian = format & PCF_BYTE_MASK != 0;
+        let msb_first = format & PCF_BIT_MASK != 0;
+        Ok(Self {
+            offsets: Reader::new(table, 8, big_endian),
+            data,
+            row_padding: 1 << padding,
+            scan_unit: 1 << ((format >> 4) & 3),
+            swap_bytes: big_endian != msb_first,
+            msb_first,
+        })
+    }
+
+    fn glyph(&self, index: usize, dimensions: (u32, u32)) -> Result<Vec<bool>, FontFileError> {
+        let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+        let start = self.offsets.u32(index * 4)? as usize;
+        let row_bytes = width.div_ceil(8).next_multiple_of(self.row_padding);
+        let mut bits = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut byte = x / 8;
+                if self.swap_bytes && self.scan_unit > 1 {
+                    byte = byte / self.scan_unit * self.scan_unit + self.scan_unit
+                        - 1
+                        - byte % self.scan_unit;
+                }
+                let value = *self
+                    .data
+                    .get(start + y * row_bytes + byte)
+                    .ok_or(FontFileError::Corrupt("glyph outside of bitmaps"))?;
+                let bit = match self.msb_first {
+                    true => 7 - x % 8,
+                    false => x % 8,
+                };
+                bits.push(value >> bit & 1 == 1);
+            }
+        }
+        Ok(bits)
+    }
+}
@@ -0,0 +1,495 @@
+//! Post-processing effects, applied while the canvas is upscaled to the window.
+//!
+//! With any effects in the chain, the canvas is first upscaled to an off-screen texture,
+//! then each [PostEffect] in turn reads the image drawn so far and draws the next one,
+//! the last of them to the window.
+//! Effects run at the resolution of the window, so they can add detail finer than a canvas pixel,
+//! like scanlines between the rows of the canvas.
+//!
+//! Custom passes are written in WGSL, as a fragment entry point named `post_f`.
+//! The source is appended to a prelude that declares:
+//!
+//! - `source` and `source_sampler`, the image drawn so far and a bilinear sampler for it.
+//! - `post.output_size` and `post.canvas_size`, in pixels.
+//! - `post.canvas_rect`, where the upscaled canvas is, in output pixels from the top left.
+//! - `post.params`, the parameters of the effect.
+//! - `PostInter`, the fragment input with the `position` in output pixels,
+//!   and `uv` from the top left to the bottom right of the output.
+//!
+//! ```ignore
+//! let grayscale = renderer.create_post_shader("grayscale", r"
+//!     @fragment
+//!     fn post_f(in: PostInter) -> @location(0) vec4<f32> {
+//!         let color = textureSample(source, source_sampler, in.uv);
+//!         let gray = dot(color.rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+//!         return vec4<f32>(mix(color.rgb, vec3<f32>(gray), post.params.x), color.a);
+//!     }
+//! ")?;
+//! renderer.post_effects_mut().extend([
+//!     PostEffect::Scanlines { intensity: 0.3 },
+//!     PostEffect::Custom { shader: grayscale, params: [1.0, 0.0, 0.0, 0.0] },
+//! ]);
+//! ```
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+#[cfg(feature = "hot-reload")]
+use super::hot_reload::{ShaderFile, Tracked, TrackedSource};
+use super::{upscale, OutputTexture, Size};
+
+/// Maximum amount of effects in the chain, further ones are skipped.
+const MAX_POST_EFFECTS: u64 = 16;
+
+const PRELUDE: &str = include_str!("shaders/post_prelude.wgsl");
+
+/// A post-processing pass, with its parameters.
+#[derive(Clone, Debug)]
+pub enum PostEffect {
+    /// Bulge the image like a curved CRT screen, cutting off the corners.
+    /// An amount around 0.1 is subtle.
+    Curvature { amount: f32 },
+    /// Darken the edges between rows of canvas pixels, by up to `intensity` from 0 to 1.
+    Scanlines { intensity: f32 },
+    /// Dim the other channels in alternating red, green and blue columns of output pixels,
+    /// like an aperture grille, by `intensity` from 0 to 1.
+    ShadowMask { intensity: f32 },
+    /// Make the part of colors above `threshold` glow up to `radius` output pixels around them.
+    Bloom {
+        threshold: f32,
+        intensity: f32,
+        radius: f32,
+    },
+    /// Shift red and blue apart towards the edges of the output, up to `offset` output pixels.
+    ChromaticAberration { offset: f32 },
+    /// Darken the corners, by `intensity` from 0 to 1.
+    Vignette { intensity: f32 },
+    /// A pass created with [crate::Renderer::create_post_shader].
+    /// The shader reads the parameters from `post.params`.
+    Custom {
+        shader: PostShader,
+        params: [f32; 4],
+    },
+}
+
+impl PostEffect {
+    fn params(&self) -> [f32; 4] {
+        match *self {
+            PostEffect::Curvature { amount: x }
+            | PostEffect::Scanlines { intensity: x }
+            | PostEffect::ShadowMask { intensity: x }
+            | PostEffect::ChromaticAberration { offset: x }
+            | PostEffect::Vignette { intensity: x } => [x, 0.0, 0.0, 0.0],
+            PostEffect::Bloom {
+                threshold,
+                intensity,
+                radius,
+            } => [threshold, intensity, radius, 0.0],
+            PostEffect::Custom { params, .. } => params,
+        }
+    }
+}
+
+/// A custom post-processing pass, compiled from WGSL.
+#[derive(Clone, Debug)]
+pub struct PostShader {
+    /// Replaced when the shader is reloaded.
+    pipeline: Rc<RefCell<Rc<wgpu::RenderPipeline>>>,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct Uniforms {
+    output_size: [f32; 2],
+    canvas_size: [f32; 2],
+    canvas_rect: [f32; 4],
+    params: [f32; 4],
+}
+
+/// Two textures the size of the output, which passes take turns reading from and drawing to.
+struct Targets {
+    size: Size,
+    textures: [OutputTexture; 2],
+    /// Bind groups reading from each texture.
+    bind_groups: [wgpu::BindGroup; 2],
+}
+
+pub(crate) struct Renderer {
+    ctx: Rc<super::Context>,
+    source_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    /// Shared by every pass, and followed by its source.
+    prelude: String,
+    effects_module: wgpu::ShaderModule,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    /// Distance between the uniforms of consecutive passes in the uniform buffer.
+    uniform_stride: u64,
+    /// Pipelines of built-in effects by entry point, created when first used.
+    pipelines: RefCell<HashMap<&'static str, Rc<wgpu::RenderPipeline>>>,
+    /// Created when first needed, and recreated when the output is resized.
+    targets: RefCell<Option<Targets>>,
+    #[cfg(feature = "hot-reload")]
+    tracked: RefCell<Vec<Tracked<RefCell<Rc<wgpu::RenderPipeline>>>>>,
+}
+
+impl Renderer {
+    pub(crate) fn new(ctx: Rc<super::Context>) -> Self {
+        let device = &ctx.device;
+
+        let source_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("post-processing source"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        // Each pass gets a slot for its uniforms, selected with a dynamic offset.
+        let uniform_size = std::mem::size_of::<Uniforms>() as u64;
+        let uniform_stride =
+            (ctx.limits.min_uniform_buffer_offset_alignment as u64).max(uniform_size);
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("post-processing uniforms"),
+            size: MAX_POST_EFFECTS * uniform_stride,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("post-processing uniforms"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: std::num::NonZeroU64::new(uniform_size),
+                },
+                count: None,
+            }],
+        });
+
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("post-processing uniforms"),
+            layout: &uniform_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &uniform_buffer,
+                    offset: 0,
+                    size: std::num::NonZeroU64::new(uniform_size),
+                }),
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("post-processing"),
+            bind_group_layouts: &[&source_layout, &uniform_layout],
+            push_constant_ranges: &[],
+        });
+
+        let effects_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("post-processing effects"),
+            source: wgpu::ShaderSource::Wgsl(
+                format!("{PRELUDE}\n{}", include_str!("shaders/postprocess.wgsl")).into(),
+            ),
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("post-processing sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            ctx,
+            source_layout,
+            pipeline_layout,
+            prelude: PRELUDE.to_owned(),
+            effects_module,
+            sampler,
+            uniform_buffer,
+            uniform_bind_group,
+            uniform_stride,
+            pipelines: RefCell::new(HashMap::new()),
+            targets: RefCell::new(None),
+            #[cfg(feature = "hot-reload")]
+            tracked: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn create_pipeline(
+        &self,
+        label: &str,
+        module: &wgpu::ShaderModule,
+        entry_point: &str,
+    ) -> wgpu::RenderPipeline {
+        self.ctx
+            .device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&self.pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module,
+                    entry_point: "post_v",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: self.ctx.color_format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::all(),
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                multisample: wgpu::MultisampleState::default(),
+                depth_stencil: None,
+                multiview: None,
+            })
+    }
+
+    /// Compile a custom pass, reporting WGSL errors instead of panicking.
+    pub(crate) fn create_shader(&self, label: &str, source: &str) -> Result<PostShader, String> {
+        let shader = PostShader {
+            pipeline: Rc::new(RefCell::new(self.compile(label, source)?)),
+        };
+        #[cfg(feature = "hot-reload")]
+        self.tracked.borrow_mut().push(Tracked {
+            item: Rc::downgrade(&shader.pipeline),
+            label: label.to_owned(),
+            source: TrackedSource::Text(source.to_owned()),
+        });
+        Ok(shader)
+    }
+
+    /// Compile a custom pass from a file, and recompile it whenever the file changes.
+    #[cfg(feature = "hot-reload")]
+    pub(crate) fn load_shader(&self, path: &std::path::Path) -> Result<PostShader, String> {
+        let file =
+            ShaderFile::load(path).map_err(|err| format!("couldn't read {path:?}: {err}"))?;
+        let label = path.display().to_string();
+        let shader = PostShader {
+            pipeline: Rc::new(RefCell::new(self.compile(&label, &file.source)?)),
+        };
+        self.tracked.borrow_mut().push(Tracked {
+            item: Rc::downgrade(&shader.pipeline),
+            label,
+            source: TrackedSource::File(file),
+        });
+        Ok(shader)
+    }
+
+    /// Recompile custom passes whose file changed.
+    /// If the prelude or the built-in effects changed, `changed` holds both their sources,
+    /// and every pass is recompiled.
+    /// Passes that fail to compile keep their previous pipelines.
+    #[cfg(feature = "hot-reload")]
+    pub(crate) fn reload(&mut self, changed: Option<(&str, &str)>) {
+        let prelude_changed = changed.is_some_and(|(prelude, _)| prelude != self.prelude);
+        if let Some((prelude, effects)) = changed {
+            self.prelude = prelude.to_owned();
+            self.reload_effects(effects);
+        }
+        self.tracked.borrow_mut().retain_mut(|tracked| {
+            let Some(pipeline) = tracked.item.upgrade() else {
+                return false;
+            };
+            if tracked.source.poll() | prelude_changed {
+                match self.compile(&tracked.label, tracked.source.get()) {
+                    Ok(new) => {
+                        *pipeline.borrow_mut() = new;
+                        info!("reloaded post-processing shader {:?}", tracked.label);
+                    }
+                    Err(err) => error!(
+                        "couldn't reload post-processing shader {:?}: {err}",
+                        tracked.label
+                    ),
+                }
+            }
+            true
+        });
+    }
+
+    /// Replace the built-in effects, and the pipelines already created for them.
+    #[cfg(feature = "hot-reload")]
+    fn reload_effects(&mut self, effects: &str) {
+        let device = &self.ctx.device;
+        let entry_points: Vec<_> = self.pipelines.get_mut().keys().copied().collect();
+        let result = super::validate(device, || {
+            let module = self.create_effects_module(effects);
+            let pipelines: HashMap<_, _> = entry_points
+                .into_iter()
+                .map(|entry_point| {
+                    let pipeline = self.create_pipeline(entry_point, &module, entry_point);
+                    (entry_point, Rc::new(pipeline))
+                })
+                .collect();
+            (module, pipelines)
+        });
+        match result {
+            Ok((module, pipelines)) => {
+                self.effects_module = module;
+                *self.pipelines.get_mut() = pipelines;
+                info!("reloaded post-processing effects");
+            }
+            Err(err) => error!("couldn't reload post-processing effects: {err}"),
+        }
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn create_effects_module(&self, effects: &str) -> wgpu::ShaderModule {
+        self.ctx
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("post-processing effects"),
+                source: wgpu::ShaderSource::Wgsl(format!("{}\n{effects}", self.prelude).into()),
+            })
+    }
+
+    fn compile(&self, label: &str, source: &str) -> Result<Rc<wgpu::RenderPipeline>, String> {
+        super::validate(&self.ctx.device, || {
+            let module = self
+                .ctx
+                .device
+                .create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some(label),
+                    source: wgpu::ShaderSource::Wgsl(format!("{}\n{source}", self.prelude).into()),
+                });
+            Rc::new(self.create_pipeline(label, &module, "post_f"))
+        })
+    }
+
+    fn pipeline(&self, effect: &PostEffect) -> Rc<wgpu::RenderPipeline> {
+        let entry_point = match effect {
+            PostEffect::Curvature { .. } => "curvature_f",
+            PostEffect::Scanlines { .. } => "scanlines_f",
+            PostEffect::ShadowMask { .. } => "shadow_mask_f",
+            PostEffect::Bloom { .. } => "bloom_f",
+            PostEffect::ChromaticAberration { .. } => "chromatic_aberration_f",
+            PostEffect::Vignette { .. } => "vignette_f",
+            PostEffect::Custom { shader, .. } => return shader.pipeline.borrow().clone(),
+        };
+        self.pipelines
+            .borrow_mut()
+            .entry(entry_point)
+            .or_insert_with(|| {
+                Rc::new(self.create_pipeline(entry_point, &self.effects_module, entry_point))
+            })
+            .clone()
+    }
+
+    fn create_targets(&self, size: Size) -> Targets {
+        let device = &self.ctx.device;
+        let textures = [0, 1].map(|_| OutputTexture::new(device, size, self.ctx.color_format));
+        let bind_groups = [0, 1].map(|i| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("post-processing source"),
+                layout: &self.source_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&textures[i].view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+            })
+        });
+        Targets {
+            size,
+            textures,
+            bind_groups,
+        }
+    }
+
+    /// Upscale the canvas to `output` through the chain of effects.
+    pub(crate) fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        upscale_renderer: &upscale::Renderer,
+        effects: &[PostEffect],
+        output: &wgpu::TextureView,
+        output_size: Size,
+    ) {
+        if effects.len() as u64 > MAX_POST_EFFECTS {
+            warn!("Only the first {MAX_POST_EFFECTS} post-processing effects are applied");
+        }
+        let effects = &effects[..effects.len().min(MAX_POST_EFFECTS as usize)];
+        if effects.is_empty() {
+            upscale_renderer.render(encoder, output);
+            return;
+        }
+
+        let mut targets = self.targets.borrow_mut();
+        let outdated = targets.as_ref().is_none_or(|targets| {
+            (targets.size.width, targets.size.height) != (output_size.width, output_size.height)
+        });
+        if outdated {
+            *targets = Some(self.create_targets(output_size));
+        }
+        let targets = targets.as_ref().unwrap();
+        upscale_renderer.render(encoder, &targets.textures[0].view);
+
+        let canvas_size = upscale_renderer.canvas_size;
+        for (slot, effect) in effects.iter().enumerate() {
+            let uniforms = Uniforms {
+                output_size: [output_size.width as f32, output_size.height as f32],
+                canvas_size: [canvas_size.width as f32, canvas_size.height as f32],
+                canvas_rect: upscale_renderer.upscaled_rect(output_size),
+                params: effect.params(),
+            };
+            let offset = slot as u64 * self.uniform_stride;
+            self.ctx.queue.write_buffer(
+                &self.uniform_buffer,
+                offset,
+                bytemuck::bytes_of(&uniforms),
+            );
+
+            let pipeline = self.pipeline(effect);
+            let source = slot % 2;
+            let target = if slot + 1 == effects.len() {
+                output
+            } else {
+                &targets.textures[1 - source].view
+            };
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("post-processing render pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(&pipeline);
+            render_pass.set_bind_group(0, &targets.bind_groups[source], &[]);
+            render_pass.set_bind_group(1, &self.uniform_bind_group, &[offset as u32]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use std::rc::Rc;
 use wgpu::util::DeviceExt;
 
-use super::Vertex;
+use super::{blend, BlendMode, Color, Vertex};
 
 const LINEBOX_VERTICES: &[Vertex; 5] = &[
     Vertex { x: 0.0, y: 0.0 },
@@ -18,14 +18,15 @@ const LINEBOX_LAYOUT: wgpu::VertexBufferLayout = wgpu::VertexBufferLayout {
 };
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::NoUninit)]
 pub struct Rectangle {
     pub position: [i32; 2],
     pub dimensions: [u32; 2],
-    pub color: [f32; 4],
+    pub color: Color,
+    pub blend: BlendMode,
 }
 
-const RECTANGLE_LAYOUT: wgpu::VertexBufferLayout = wgpu::VertexBufferLayout {
+pub(crate) const RECTANGLE_LAYOUT: wgpu::VertexBufferLayout = wgpu::VertexBufferLayout {
     array_stride: std::mem::size_of::<Rectangle>() as wgpu::BufferAddress,
     step_mode: wgpu::VertexStepMode::Instance,
     attributes: &wgpu::vertex_attr_array![1 => Sint32x2, 2 => Uint32x2, 3 => Float32x4],
@@ -33,7 +34,7 @@ const RECTANGLE_LAYOUT: wgpu::VertexBufferLayout = wgpu::VertexBufferLayout {
 
 pub(crate) struct Renderer {
     ctx: Rc<super::Context>,
-    pipeline: wgpu::RenderPipeline,
+    pub(crate) pipelines: blend::Pipelines,
     linebox_buffer: wgpu::Buffer,
     instance_buffer: wgpu::Buffer,
 }
@@ -58,39 +59,18 @@ impl Renderer {
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("rect"),
-            bind_group_layouts: &[&ctx.canvas.dimensions_layout],
+            bind_group_layouts: &[&ctx.dimensions_layout],
             push_constant_ranges: &[],
         });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("rect"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &ctx.shaders,
-                entry_point: "rect_v",
-                buffers: &[LINEBOX_LAYOUT, RECTANGLE_LAYOUT],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &ctx.shaders,
-                entry_point: "rect_f",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: ctx.canvas.color_format,
-                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::LineStrip,
-                ..Default::default()
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
+        let pipelines = blend::Pipelines::new(ctx.shaders.clone(), {
+            let ctx = ctx.clone();
+            move |module, blend| create_pipeline(&ctx, &pipeline_layout, module, blend)
         });
 
         Self {
             ctx,
-            pipeline,
+            pipelines,
             linebox_buffer,
             instance_buffer,
         }
@@ -103,9 +83,45 @@ impl Renderer {
         rectangles: &[Rectangle],
     ) {
         self.ctx.queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(rectangles));
-        render_pass.set_pipeline(&self.pipeline);
         render_pass.set_vertex_buffer(0, self.linebox_buffer.slice(..));
         render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-        render_pass.draw(0..LINEBOX_VERTICES.len() as u32, 0..rectangles.len() as u32);
+        for (mode, instances) in blend::runs(rectangles, |rect| rect.blend) {
+            render_pass.set_pipeline(self.pipelines.get(mode));
+            render_pass.draw(0..LINEBOX_VERTICES.len() as u32, instances);
+        }
     }
 }
+
+fn create_pipeline(
+    ctx: &super::Context,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    blend: wgpu::BlendState,
+) -> wgpu::RenderPipeline {
+    ctx.device
+        .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("rect"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module,
+                entry_point: "rect_v",
+                buffers: &[LINEBOX_LAYOUT, RECTANGLE_LAYOUT],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module,
+                entry_point: "rect_f",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: ctx.color_format,
+                    blend: Some(blend),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineStrip,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+}
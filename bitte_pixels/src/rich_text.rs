@@ -0,0 +1,452 @@
+//! Text with inline markup, for dialog boxes and the like.
+//!
+//! Markup uses square-bracket tags, which can be nested:
+//!
+//! - `[color=#rrggbb]...[/color]` or `[color=#rrggbbaa]...[/color]` changes the text color,
+//!   given in sRGB with straight alpha.
+//! - `[wave]...[/wave]` makes letters bob up and down, `[wave=3]` with an amplitude of 3 pixels.
+//! - `[shake]...[/shake]` makes letters jitter, `[shake=2]` up to 2 pixels away.
+//!   Wave and shake amplitudes are capped at 256 pixels.
+//! - `[icon=name]` inserts a named sprite from the icon sheet, as if it was a letter.
+//! - `[[` is a literal `[`.
+//!
+//! Icons take the place of characters in the private use planes 15 and 16,
+//! which markup can't contain itself.
+//!
+//! [Markup::parse] reads the tags, and [Markup::layout] positions every glyph.
+//! Effects and typewriter reveals are applied every frame, when a [RichTextInstance] is drawn.
+
+use std::collections::HashMap;
+
+use super::{
+    sprite::{SpriteHandle, SpriteSheet},
+    text::{Align, Font, Glyph},
+    Color,
+};
+
+const WAVE_FREQUENCY: f32 = 1.5;
+/// Phase difference between consecutive letters, in radians.
+const WAVE_PHASE: f32 = 0.6;
+/// Times per second that shaking letters move.
+const SHAKE_RATE: f32 = 20.0;
+/// Largest effect amplitude in pixels, so that offsets can't overflow.
+const MAX_AMPLITUDE: u32 = 256;
+
+/// Rich text to submit for drawing.
+#[derive(Clone)]
+pub struct RichTextInstance<'a> {
+    pub text: &'a RichText,
+    /// Position of the top of the first line, as for [crate::text::Text].
+    pub position: [i32; 2],
+    /// Time in seconds, driving the wave and shake effects.
+    pub time: f32,
+    /// How many characters are revealed, for a typewriter effect.
+    /// Every character is shown if `None`.
+    pub reveal: Option<usize>,
+}
+
+impl RichTextInstance<'_> {
+    /// Sprites, positions, and colors of the glyphs to draw this frame.
+    pub fn glyphs(&self) -> impl Iterator<Item = (SpriteHandle, [i32; 2], Color)> + '_ {
+        let reveal = self.reveal.unwrap_or(usize::MAX);
+        self.text
+            .glyphs
+            .iter()
+            .filter(move |glyph| glyph.index < reveal)
+            .map(|glyph| {
+                let offset = glyph.effect.offset(glyph.index, self.time);
+                let position = [
+                    self.position[0] + glyph.position[0] + offset[0],
+                    self.position[1] + glyph.position[1] + offset[1],
+                ];
+                (glyph.sprite, position, glyph.color)
+            })
+    }
+}
+
+/// An animated effect applied to individual letters.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Effect {
+    #[default]
+    None,
+    /// Bob up and down along a sine wave, with an amplitude in pixels.
+    Wave(u32),
+    /// Jump around randomly, up to a distance in pixels.
+    Shake(u32),
+}
+
+impl Effect {
+    /// Offset of the `index`th character at a point in time.
+    fn offset(self, index: usize, time: f32) -> [i32; 2] {
+        match self {
+            Effect::None => [0, 0],
+            Effect::Wave(amplitude) => {
+                let angle =
+                    time * WAVE_FREQUENCY * std::f32::consts::TAU - index as f32 * WAVE_PHASE;
+                [0, (amplitude as f32 * angle.sin()).round() as i32]
+            }
+            Effect::Shake(amplitude) => {
+                let frame = (time * SHAKE_RATE) as u32;
+                let hash = hash(index as u32 ^ frame.wrapping_mul(0x9e37_79b9));
+                let range = 2 * amplitude + 1;
+                let x = (hash & 0xffff) % range;
+                let y = (hash >> 16) % range;
+                [x as i32 - amplitude as i32, y as i32 - amplitude as i32]
+            }
+        }
+    }
+}
+
+/// Cheap integer hash for shaking letters.
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^ (x >> 16)
+}
+
+#[derive(Clone, Copy)]
+struct Style {
+    color: Color,
+    effect: Effect,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            effect: Effect::None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MarkupError {
+    /// A tag that isn't recognized, or is missing its argument.
+    UnknownTag(String),
+    /// A closing tag that doesn't match the innermost open tag.
+    UnexpectedClose(String),
+    /// A tag that is never closed.
+    Unclosed(String),
+    /// A `[` without a matching `]`.
+    UnterminatedTag,
+    BadColor(String),
+    BadNumber(String),
+    UnknownIcon(String),
+    /// A character from the range that icons are mapped to.
+    ReservedChar(char),
+}
+
+impl std::fmt::Display for MarkupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MarkupError::UnknownTag(tag) => write!(f, "unknown tag [{tag}]"),
+            MarkupError::UnexpectedClose(tag) => write!(f, "unexpected closing tag [/{tag}]"),
+            MarkupError::Unclosed(tag) => write!(f, "tag [{tag}] is never closed"),
+            MarkupError::UnterminatedTag => f.write_str("tag is missing a closing bracket"),
+            MarkupError::BadColor(color) => write!(f, "bad color {color:?}"),
+            MarkupError::BadNumber(number) => write!(f, "bad number {number:?}"),
+            MarkupError::UnknownIcon(name) => write!(f, "no icon named {name:?}"),
+            MarkupError::ReservedChar(c) => write!(f, "character {c:?} is reserved for icons"),
+        }
+    }
+}
+
+/// Text with its markup parsed, ready to be laid out.
+pub struct Markup {
+    /// Text without tags, with icons replaced by private use characters.
+    text: String,
+    /// Style of every character in `text`.
+    styles: Vec<Style>,
+    icons: HashMap<char, Glyph>,
+}
+
+impl Markup {
+    /// Parse markup, looking up icons by name in `icons`.
+    pub fn parse(markup: &str, icons: Option<&SpriteSheet>) -> Result<Self, MarkupError> {
+        let mut parsed = Markup {
+            text: String::new(),
+            styles: Vec::new(),
+            icons: HashMap::new(),
+        };
+        let mut stack: Vec<(&str, Style)> = Vec::new();
+        let mut rest = markup;
+        while let Some(c) = rest.chars().next() {
+            let style = stack.last().map_or(Style::default(), |(_, style)| *style);
+            if c as u32 >= ICON_CHARS {
+                return Err(MarkupError::ReservedChar(c));
+            }
+            if c != '[' || rest.starts_with("[[") {
+                parsed.text.push(c);
+                parsed.styles.push(style);
+                rest = &rest[if c == '[' { 2 } else { c.len_utf8() }..];
+                continue;
+            }
+
+            let end = rest.find(']').ok_or(MarkupError::UnterminatedTag)?;
+            let tag = &rest[1..end];
+            rest = &rest[end + 1..];
+            if let Some(name) = tag.strip_prefix('/') {
+                match stack.pop() {
+                    Some((open, _)) if open == name => continue,
+                    _ => return Err(MarkupError::UnexpectedClose(name.to_owned())),
+                }
+            }
+
+            let (name, arg) = match tag.split_once('=') {
+                Some((name, arg)) => (name, Some(arg)),
+                None => (tag, None),
+            };
+            let amplitude = |default| {
+                let value = arg.map_or(Ok(default), |arg| {
+                    arg.parse::<u32>()
+                        .map_err(|_| MarkupError::BadNumber(arg.to_owned()))
+                })?;
+                Ok(value.min(MAX_AMPLITUDE))
+            };
+            let style = match (name, arg) {
+                ("color", Some(arg)) => Style {
+                    color: Color::from_hex(arg)
+                        .ok_or_else(|| MarkupError::BadColor(arg.to_owned()))?,
+                    ..style
+                },
+                ("wave", _) => Style {
+                    effect: Effect::Wave(amplitude(2)?),
+                    ..style
+                },
+                ("shake", _) => Style {
+                    effect: Effect::Shake(amplitude(1)?),
+                    ..style
+                },
+                ("icon", Some(arg)) => {
+                    let unknown = || MarkupError::UnknownIcon(arg.to_owned());
+                    let sheet = icons.ok_or_else(unknown)?;
+                    let sprite = sheet.sprite(arg).ok_or_else(unknown)?;
+                    let (width, _) = sheet.dimensions(sprite).map_err(|_| unknown())?;
+                    let c = char::from_u32(ICON_CHARS + parsed.icons.len() as u32).unwrap();
+                    parsed.icons.insert(
+                        c,
+                        Glyph {
+                            sprite: Some(sprite),
+                            advance: width as i32 + 1,
+                        },
+                    );
+                    parsed.text.push(c);
+                    parsed.styles.push(style);
+                    continue;
+                }
+                _ => return Err(MarkupError::UnknownTag(tag.to_owned())),
+            };
+            stack.push((name, style));
+        }
+
+        match stack.pop() {
+            Some((name, _)) => Err(MarkupError::Unclosed(name.to_owned())),
+            None => Ok(parsed),
+        }
+    }
+
+    /// The text without markup.
+    /// Icons are represented by characters from a private use area.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Position every glyph, aligned and wrapped like [crate::text::Text].
+    pub fn layout(&self, font: &Font, align: Align, max_width: Option<u32>) -> RichText {
+        let glyph = |c| {
+            self.icons
+                .get(&c)
+                .copied()
+                .or_else(|| font.resolve(c).copied())
+        };
+        let char_starts: Vec<usize> = self.text.char_indices().map(|(i, _)| i).collect();
+        let mut glyphs = Vec::new();
+        font.layout_with(
+            &self.text,
+            [0, 0],
+            align,
+            max_width,
+            &glyph,
+            |byte, glyph, position| {
+                let index = char_starts.binary_search(&byte).unwrap();
+                let style = self.styles[index];
+                if let Some(sprite) = glyph.sprite {
+                    glyphs.push(RichGlyph {
+                        sprite,
+                        position,
+                        color: style.color,
+                        effect: style.effect,
+                        index,
+                    });
+                }
+            },
+        );
+
+        let lines = font.lines_with(&self.text, max_width, &glyph);
+        let width = lines.iter().map(|line| line.width).max().unwrap_or(0);
+        RichText {
+            glyphs,
+            len: self.styles.len(),
+            size: (width, lines.len() as u32 * font.line_height()),
+        }
+    }
+}
+
+/// First character of the private use area that icons are mapped to.
+const ICON_CHARS: u32 = 0xf0000;
+
+/// A single glyph of laid out rich text.
+#[derive(Clone, Copy)]
+struct RichGlyph {
+    sprite: SpriteHandle,
+    /// Position relative to the text position, before effects.
+    position: [i32; 2],
+    color: Color,
+    effect: Effect,
+    /// Index of the character in the text, counting icons but not tags.
+    index: usize,
+}
+
+/// Rich text laid out with a font, created with [Markup::layout].
+pub struct RichText {
+    glyphs: Vec<RichGlyph>,
+    len: usize,
+    size: (u32, u32),
+}
+
+impl RichText {
+    /// Number of characters, which a typewriter reveal counts up to.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Width and height in pixels, before effects.
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{software, sprite::SpriteData, text::Glyph, Size};
+
+    fn effects(markup: &Markup) -> Vec<Effect> {
+        markup.styles.iter().map(|style| style.effect).collect()
+    }
+
+    #[test]
+    fn nested_tags_inherit_the_outer_style() {
+        let markup = Markup::parse("a[color=#ff0000]b[wave]c[/wave]d[/color]e", None).unwrap();
+        assert_eq!(markup.text(), "abcde");
+        let red = Color::srgb8(255, 0, 0, 255);
+        let colors: Vec<_> = markup.styles.iter().map(|style| style.color).collect();
+        assert_eq!(colors, [Color::WHITE, red, red, red, Color::WHITE]);
+        let none = Effect::None;
+        assert_eq!(effects(&markup), [none, none, Effect::Wave(2), none, none]);
+    }
+
+    #[test]
+    fn doubled_brackets_are_literal() {
+        let markup = Markup::parse("[[x] [shake][[[/shake]", None).unwrap();
+        assert_eq!(markup.text(), "[x] [");
+        assert_eq!(markup.styles.len(), 5);
+        assert_eq!(effects(&markup)[4], Effect::Shake(1));
+    }
+
+    #[test]
+    fn amplitudes_are_capped() {
+        let markup = Markup::parse("[shake=2147483648]a[/shake][wave=4]b[/wave]", None).unwrap();
+        let shake = Effect::Shake(MAX_AMPLITUDE);
+        assert_eq!(effects(&markup), [shake, Effect::Wave(4)]);
+        let offset = shake.offset(0, 1.0);
+        assert!(offset.iter().all(|x| x.unsigned_abs() <= MAX_AMPLITUDE));
+    }
+
+    #[test]
+    fn malformed_markup_is_rejected() {
+        let parse = |markup| Markup::parse(markup, None).err();
+        use MarkupError::*;
+        assert_eq!(parse("[bold]a[/bold]"), Some(UnknownTag("bold".into())));
+        assert_eq!(parse("[color]a[/color]"), Some(UnknownTag("color".into())));
+        assert_eq!(parse("[wave]a"), Some(Unclosed("wave".into())));
+        assert_eq!(
+            parse("[wave]a[/shake]"),
+            Some(UnexpectedClose("shake".into()))
+        );
+        assert_eq!(parse("a[/wave]"), Some(UnexpectedClose("wave".into())));
+        assert_eq!(parse("[wave"), Some(UnterminatedTag));
+        assert_eq!(parse("[color=red]a[/color]"), Some(BadColor("red".into())));
+        assert_eq!(parse("[shake=-1]a[/shake]"), Some(BadNumber("-1".into())));
+        assert_eq!(parse("[icon=coin]"), Some(UnknownIcon("coin".into())));
+        assert_eq!(parse("a\u{f0000}"), Some(ReservedChar('\u{f0000}')));
+        assert_eq!(parse("\u{10fffd}"), Some(ReservedChar('\u{10fffd}')));
+    }
+
+    #[test]
+    fn icons_are_looked_up_and_revealed_like_letters() {
+        let renderer = software::Renderer::new(Size {
+            width: 1,
+            height: 1,
+        });
+        let mut builder = renderer.create_sprite_sheet_builder("icons");
+        builder.add_named(
+            "coin",
+            SpriteData::new((3, 2), (0, 0), vec![255; 24]).unwrap(),
+        );
+        let letter = builder.add(SpriteData::new((1, 1), (0, 0), vec![255; 4]).unwrap());
+        let icons = builder.build();
+
+        assert_eq!(
+            Markup::parse("[icon=gem]", Some(&icons)).err(),
+            Some(MarkupError::UnknownIcon("gem".into()))
+        );
+        let markup = Markup::parse("a[icon=coin] a", Some(&icons)).unwrap();
+        assert_eq!(markup.text().chars().count(), 4);
+        let icon = markup.text().chars().nth(1).unwrap();
+        assert!(markup.icons[&icon].sprite.is_some());
+        assert_eq!(markup.icons[&icon].advance, 4);
+
+        let mut font = Font::new(2);
+        font.add_glyph(
+            'a',
+            Glyph {
+                sprite: Some(letter),
+                advance: 2,
+            },
+        );
+        font.add_glyph(
+            ' ',
+            Glyph {
+                sprite: None,
+                advance: 1,
+            },
+        );
+        let text = markup.layout(&font, Align::Left, None);
+        // Spaces count towards the reveal, but aren't drawn.
+        assert_eq!(text.len(), 4);
+        assert_eq!(text.size(), (9, 2));
+        let revealed = |reveal| {
+            let instance = RichTextInstance {
+                text: &text,
+                position: [0, 0],
+                time: 0.0,
+                reveal,
+            };
+            instance
+                .glyphs()
+                .map(|(_, position, _)| position[0])
+                .collect::<Vec<_>>()
+        };
+        assert!(revealed(Some(0)).is_empty());
+        assert_eq!(revealed(Some(2)), [0, 2]);
+        assert_eq!(revealed(Some(3)), [0, 2]);
+        assert_eq!(revealed(None), [0, 2, 7]);
+    }
+}
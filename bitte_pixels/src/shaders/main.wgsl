@@ -1,24 +1,12 @@
-// Pixel dimensions of target canvas
-struct CanvasInfo {
-    @align(16) dimensions: vec2<u32>,
-};
-
-@group(0) @binding(0)
-var<uniform> canvas_info: CanvasInfo;
+// Built-in shaders, appended to prelude.wgsl.
 
-@group(1) @binding(0)
-var sprite_sheet: texture_2d<f32>;
-
-// Utility functions
+// Position of a tilemap on the canvas
+struct TilemapInfo {
+    @align(16) offset: vec2<i32>,
+};
 
-// Convert a pixel coordinate to a normalized device coordinate.
-// The result will point to the center of the corresponding pixel on the canvas.
-// We're currently assuming that (-1, -1) refers to the bottom left corner of the bottom left pixel.
-// Not to the center of the bottom left pixel.
-fn pixel_to_ndc(coord: vec2<f32>) -> vec4<f32> {
-    let result = (0.5 + coord) / vec2<f32>(canvas_info.dimensions) * 2.0 - 1.0;
-    return vec4<f32>(result, 0.0, 1.0);
-}
+@group(2) @binding(0)
+var<uniform> tilemap_info: TilemapInfo;
 
 // A super-simple shader for use in drawing primitives.
 struct PrimitiveInter {
@@ -73,6 +61,8 @@ struct SpriteInter {
     @location(1) width: u32,
     // Texture address
     @location(2) address: u32,
+    // Tint multiplied with the texture
+    @location(3) color: vec4<f32>,
 };
 
 @vertex fn sprite_v(
@@ -83,6 +73,29 @@ struct SpriteInter {
     @location(2) dimensions: vec2<u32>,
     // Address of sprite in 1D sprite sheet
     @location(3) address: u32,
+    // Tint of sprite
+    @location(4) color: vec4<f32>,
+) -> SpriteInter {
+    return sprite_vertex(position, sprite_position, dimensions, address, color);
+}
+
+// Tiles are sprites positioned relative to their tilemap.
+@vertex fn tile_v(
+    @location(0) position: vec2<f32>,
+    @location(1) tile_position: vec2<i32>,
+    @location(2) dimensions: vec2<u32>,
+    @location(3) address: u32,
+    @location(4) color: vec4<f32>,
+) -> SpriteInter {
+    return sprite_vertex(position, tile_position + tilemap_info.offset, dimensions, address, color);
+}
+
+fn sprite_vertex(
+    position: vec2<f32>,
+    sprite_position: vec2<i32>,
+    dimensions: vec2<u32>,
+    address: u32,
+    color: vec4<f32>,
 ) -> SpriteInter {
     let pos = vec2<f32>(sprite_position) + vec2<f32>(dimensions) * position - 0.5;
     let ndc = pixel_to_ndc(pos);
@@ -97,6 +110,7 @@ struct SpriteInter {
         texture_coord,
         dimensions.x,
         address,
+        color,
     );
 }
 
@@ -121,7 +135,7 @@ struct SpriteInter {
     // TODO: Remove cast to i32 once https://github.com/gfx-rs/naga/issues/1997 is resolved
     let result: vec4<f32> = textureLoad(sprite_sheet, vec2<i32>(sheet_coord), 0);
 
-    return result;
+    return result * in.color;
 }
 
 // Circle renderer
@@ -0,0 +1,66 @@
+// Materials, appended to prelude.wgsl and followed by the source of the material.
+
+// A corner of a sprite or rectangle, passed to `material_vertex`.
+struct MaterialVertex {
+    // Corner of the quad, from (0, 0) in the bottom left to (1, 1) in the top right.
+    corner: vec2<f32>,
+    // Position of the bottom left corner on the canvas.
+    position: vec2<i32>,
+    // Width and height in pixels.
+    size: vec2<u32>,
+    // Address of a sprite in its sheet, zero for rectangles.
+    address: u32,
+    // Tint of a sprite, or color of a rectangle.
+    color: vec4<f32>,
+};
+
+struct MaterialInter {
+    @builtin(position) position: vec4<f32>,
+    // Position within the sprite or rectangle in pixels, from its bottom left corner.
+    @location(0) local: vec2<f32>,
+    @location(1) size: vec2<u32>,
+    @location(2) address: u32,
+    @location(3) color: vec4<f32>,
+};
+
+// Places the quad like a sprite or rectangle without a material.
+fn default_material_vertex(v: MaterialVertex) -> MaterialInter {
+    let local = vec2<f32>(v.size) * v.corner;
+    let ndc = pixel_to_ndc(vec2<f32>(v.position) + local - 0.5);
+    return MaterialInter(ndc, local, v.size, v.address, v.color);
+}
+
+@vertex fn material_sprite_v(
+    @location(0) corner: vec2<f32>,
+    @location(1) position: vec2<i32>,
+    @location(2) size: vec2<u32>,
+    @location(3) address: u32,
+    @location(4) color: vec4<f32>,
+) -> MaterialInter {
+    return material_vertex(MaterialVertex(corner, position, size, address, color));
+}
+
+@vertex fn material_rect_v(
+    @location(0) corner: vec2<f32>,
+    @location(1) position: vec2<i32>,
+    @location(2) size: vec2<u32>,
+    @location(3) color: vec4<f32>,
+) -> MaterialInter {
+    return material_vertex(MaterialVertex(corner, position, size, 0u, color));
+}
+
+// Color of a pixel of the sprite being drawn, counted from its bottom left corner.
+// Pixels outside of the sprite are clamped to its edges.
+fn sprite_texel(in: MaterialInter, pixel: vec2<i32>) -> vec4<f32> {
+    let clamped = vec2<u32>(clamp(pixel, vec2<i32>(0), vec2<i32>(in.size) - 1));
+    // Sprites are stored top-to-bottom, see sprite_f.
+    let address = in.address + clamped.x + (in.size.y - 1u - clamped.y) * in.size.x;
+    let sheet_width = u32(textureDimensions(sprite_sheet).x);
+    let sheet_coord = vec2<u32>(address % sheet_width, address / sheet_width);
+    return textureLoad(sprite_sheet, vec2<i32>(sheet_coord), 0);
+}
+
+// The color that the sprite would have without a material.
+fn sprite_color(in: MaterialInter) -> vec4<f32> {
+    return sprite_texel(in, vec2<i32>(floor(in.local))) * in.color;
+}
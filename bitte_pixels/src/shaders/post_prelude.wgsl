@@ -0,0 +1,38 @@
+// Shared by every post-processing pass, including custom ones.
+
+// The image drawn by the previous pass, at the size of the output.
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+
+@group(0) @binding(1)
+var source_sampler: sampler;
+
+struct PostUniforms {
+    // Size of the output in pixels.
+    output_size: vec2<f32>,
+    canvas_size: vec2<f32>,
+    // Position and size of the upscaled canvas in output pixels, from the top left.
+    canvas_rect: vec4<f32>,
+    // Parameters of the effect.
+    params: vec4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> post: PostUniforms;
+
+struct PostInter {
+    // In output pixels, from the top left.
+    @builtin(position) position: vec4<f32>,
+    // From (0, 0) in the top left to (1, 1) in the bottom right.
+    @location(0) uv: vec2<f32>,
+};
+
+// A triangle covering the whole output.
+@vertex
+fn post_v(@builtin(vertex_index) index: u32) -> PostInter {
+    let uv = vec2<f32>(f32(index & 1u) * 2.0, f32(index >> 1u) * 2.0);
+    return PostInter(
+        vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0),
+        uv,
+    );
+}
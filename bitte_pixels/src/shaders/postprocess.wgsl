@@ -0,0 +1,69 @@
+// Built-in post-processing effects, appended to post_prelude.wgsl.
+
+const PI: f32 = 3.14159265;
+
+// params.x: amount of bulge.
+@fragment
+fn curvature_f(in: PostInter) -> @location(0) vec4<f32> {
+    let centered = in.uv * 2.0 - 1.0;
+    let warped = centered * (1.0 + centered.yx * centered.yx * post.params.x);
+    let uv = warped * 0.5 + 0.5;
+    let color = textureSample(source, source_sampler, uv);
+    let inside = all(uv >= vec2<f32>(0.0)) && all(uv <= vec2<f32>(1.0));
+    return select(vec4<f32>(0.0, 0.0, 0.0, 1.0), color, inside);
+}
+
+// params.x: intensity.
+@fragment
+fn scanlines_f(in: PostInter) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+    // Position within the canvas row, from 0 at its top edge to 1 at its bottom edge.
+    let row = fract((in.position.y - post.canvas_rect.y) / post.canvas_rect.w * post.canvas_size.y);
+    let shade = 1.0 - post.params.x * (1.0 - sin(row * PI));
+    return vec4<f32>(color.rgb * shade, color.a);
+}
+
+// params.x: intensity.
+@fragment
+fn shadow_mask_f(in: PostInter) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+    let column = u32(in.position.x) % 3u;
+    var mask = vec3<f32>(1.0 - post.params.x);
+    mask[column] = 1.0;
+    return vec4<f32>(color.rgb * mask, color.a);
+}
+
+// params.x: threshold, params.y: intensity, params.z: radius in output pixels.
+@fragment
+fn bloom_f(in: PostInter) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+    let step = post.params.z / 2.0 / post.output_size;
+    var glow = vec3<f32>(0.0);
+    for (var y = -2; y <= 2; y++) {
+        for (var x = -2; x <= 2; x++) {
+            let uv = in.uv + vec2<f32>(f32(x), f32(y)) * step;
+            let sample = textureSample(source, source_sampler, uv).rgb;
+            glow += max(sample - post.params.x, vec3<f32>(0.0));
+        }
+    }
+    return vec4<f32>(color.rgb + glow / 25.0 * post.params.y, color.a);
+}
+
+// params.x: offset at the edges in output pixels.
+@fragment
+fn chromatic_aberration_f(in: PostInter) -> @location(0) vec4<f32> {
+    let shift = (in.uv * 2.0 - 1.0) * post.params.x / post.output_size;
+    let color = textureSample(source, source_sampler, in.uv);
+    let red = textureSample(source, source_sampler, in.uv + shift).r;
+    let blue = textureSample(source, source_sampler, in.uv - shift).b;
+    return vec4<f32>(red, color.g, blue, color.a);
+}
+
+// params.x: intensity.
+@fragment
+fn vignette_f(in: PostInter) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+    let centered = in.uv * 2.0 - 1.0;
+    let shade = clamp(1.0 - post.params.x * dot(centered, centered) * 0.5, 0.0, 1.0);
+    return vec4<f32>(color.rgb * shade, color.a);
+}
@@ -0,0 +1,23 @@
+// Shared by the built-in shaders and materials.
+
+// Pixel dimensions of target canvas
+struct CanvasInfo {
+    @align(16) dimensions: vec2<u32>,
+};
+
+@group(0) @binding(0)
+var<uniform> canvas_info: CanvasInfo;
+
+@group(1) @binding(0)
+var sprite_sheet: texture_2d<f32>;
+
+// Utility functions
+
+// Convert a pixel coordinate to a normalized device coordinate.
+// The result will point to the center of the corresponding pixel on the canvas.
+// We're currently assuming that (-1, -1) refers to the bottom left corner of the bottom left pixel.
+// Not to the center of the bottom left pixel.
+fn pixel_to_ndc(coord: vec2<f32>) -> vec4<f32> {
+    let result = (0.5 + coord) / vec2<f32>(canvas_info.dimensions) * 2.0 - 1.0;
+    return vec4<f32>(result, 0.0, 1.0);
+}
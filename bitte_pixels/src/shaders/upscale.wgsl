@@ -1,4 +1,5 @@
-// The only thing this shader does is to propagate a position and sample a texture.
+// Propagates a position and samples the canvas, either directly or with sharp bilinear filtering,
+// or blurred for the letterbox.
 
 @group(0) @binding(0)
 var texture: texture_2d<f32>;
@@ -6,6 +7,9 @@ var texture: texture_2d<f32>;
 @group(0) @binding(1)
 var sampler_diffuse: sampler;
 
+@group(0) @binding(2)
+var sampler_linear: sampler;
+
 struct UpscaleInter {
     @builtin(position) position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
@@ -28,3 +32,35 @@ fn upscale_f(
 ) -> @location(0) vec4<f32> {
     return textureSample(texture, sampler_diffuse, in.tex_coords);
 }
+
+// Sharp bilinear filtering: texels are scaled up by a whole number with nearest sampling,
+// and the remainder with bilinear filtering, so only the edges between texels are blended.
+@fragment
+fn upscale_sharp_f(
+    in: UpscaleInter,
+) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(texture));
+    let texel = in.tex_coords * size;
+    // Screen pixels per texel, at least 1 so that downscaling falls back to bilinear filtering.
+    let scale = max(1.0 / fwidth(texel), vec2<f32>(1.0));
+    let region = 0.5 - 0.5 / scale;
+    let distance = fract(texel) - 0.5;
+    let offset = (distance - clamp(distance, -region, region)) * scale + 0.5;
+    return textureSample(texture, sampler_linear, (floor(texel) + offset) / size);
+}
+
+// A blurred copy of the canvas, averaging bilinear samples two texels apart.
+@fragment
+fn upscale_blur_f(
+    in: UpscaleInter,
+) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(texture));
+    var sum = vec4<f32>(0.0);
+    for (var y = -2; y <= 2; y += 1) {
+        for (var x = -2; x <= 2; x += 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * 2.0 * texel;
+            sum += textureSample(texture, sampler_linear, in.tex_coords + offset);
+        }
+    }
+    return sum / 25.0;
+}
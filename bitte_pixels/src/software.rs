@@ -0,0 +1,410 @@
+//! A software rasterizer with the same pixel semantics as the wgpu pipeline.
+//!
+//! [Renderer] draws a [Scene] into memory without any GPU,
+//! which gives deterministic reference output for tests, and works on machines without a driver.
+//! Sprites are read from the copy that every sheet page keeps in memory,
+//! so sheets created by either renderer can be drawn.
+//! Sheets and tilemaps created by this renderer have no GPU resources,
+//! and are left out when passed to [crate::Renderer].
+//!
+//! Colors are blended in linear space and stored as sRGB after every draw, as on the GPU.
+//! The one exception to exact agreement is lines.
+//! Drivers disagree on lines that pass exactly between pixel centers,
+//! so lines follow the diamond-exit rule of OpenGL, with ties broken towards the left and top.
+//!
+//! [Material](crate::material::Material)s can't run without a GPU,
+//! so their sprites are drawn as usual and their rectangles filled with their color.
+
+use super::{
+    color::{linear_to_srgb, srgb_to_linear, to_byte},
+    sprite::{
+        self, InstanceData, InvalidSpritePolicy, PackedSpriteSheet, SheetPage, SpriteHandle,
+        SpriteInstance, SpriteSheet, SpriteSheetBuilder,
+    },
+    target::{self, RenderTarget},
+    tilemap::Tilemap,
+    BlendMode, Circle, Color, LineStrip, PrimitiveVertex, Rectangle, RenderError, Scene, Size,
+};
+
+/// How far line endpoints are nudged, so that ties in the diamond-exit rule are broken consistently.
+/// Lines move left by much more than they move up, so the vertical nudge only matters for lines
+/// that run exactly along the corners of the diamonds.
+const NUDGE: [f64; 2] = [-1.0 / (1 << 20) as f64, 1.0 / (1 << 30) as f64];
+
+pub struct Renderer {
+    canvas: Canvas,
+    invalid_sprite_policy: InvalidSpritePolicy,
+    placeholder_sheet: SpriteSheet,
+    placeholder: SpriteHandle,
+}
+
+impl Renderer {
+    pub fn new(game_resolution: Size) -> Self {
+        let mut builder = sprite::new_builder(None, "placeholder");
+        let placeholder = builder.add(sprite::placeholder_sprite());
+        let placeholder_sheet = builder.build();
+
+        let pixel_count = (game_resolution.width * game_resolution.height) as usize;
+        Self {
+            canvas: Canvas {
+                size: game_resolution,
+                pixels: Color::BLACK.to_premultiplied_srgb8().repeat(pixel_count),
+                linear: std::array::from_fn(|byte| srgb_to_linear(byte as f32 / 255.0)),
+            },
+            invalid_sprite_policy: InvalidSpritePolicy::default(),
+            placeholder_sheet,
+            placeholder,
+        }
+    }
+
+    /// Start a sprite sheet that is only kept in memory.
+    pub fn create_sprite_sheet_builder<'a>(&'a self, name: &'a str) -> SpriteSheetBuilder<'a> {
+        sprite::new_builder(None, name)
+    }
+
+    /// Load a sheet that was packed with [SpriteSheetBuilder::pack], keeping it in memory.
+    pub fn load_sprite_sheet(
+        &self,
+        name: &str,
+        packed: PackedSpriteSheet,
+    ) -> Result<SpriteSheet, RenderError> {
+        sprite::load(None, name, packed)
+    }
+
+    /// Create an empty tilemap that is only kept in memory.
+    /// `size` is given in tiles, and `tile_size` in pixels.
+    pub fn create_tilemap(
+        &self,
+        size: (u32, u32),
+        tile_size: (u32, u32),
+        layer_count: usize,
+    ) -> Tilemap {
+        Tilemap::new(None, size, tile_size, layer_count)
+    }
+
+    /// Width and height of the canvas in pixels.
+    pub fn canvas_size(&self) -> Size {
+        self.canvas.size
+    }
+
+    /// Change the resolution of the canvas, like [crate::Renderer::set_canvas_size].
+    /// The canvas is black until the next call to [Renderer::render].
+    pub fn set_canvas_size(&mut self, size: Size) -> Result<(), RenderError> {
+        target::check_size("canvas", size, &super::limits())?;
+        let pixel_count = (size.width * size.height) as usize;
+        self.canvas.size = size;
+        self.canvas.pixels = Color::BLACK.to_premultiplied_srgb8().repeat(pixel_count);
+        Ok(())
+    }
+
+    /// Choose how sprites with unresolvable handles are drawn.
+    pub fn set_invalid_sprite_policy(&mut self, policy: InvalidSpritePolicy) {
+        self.invalid_sprite_policy = policy;
+    }
+
+    /// The canvas as drawn by the last call to [Renderer::render],
+    /// in the same format as [crate::Renderer::read_canvas].
+    pub fn canvas(&self) -> &[u8] {
+        &self.canvas.pixels
+    }
+
+    /// Create an off-screen canvas that is only kept in memory.
+    pub fn create_render_target(
+        &self,
+        name: &str,
+        size: Size,
+    ) -> Result<RenderTarget, RenderError> {
+        RenderTarget::new(None, name, size)
+    }
+
+    /// Render a scene to a target, like [crate::Renderer::render_to_target].
+    /// Targets created by either renderer can be drawn to,
+    /// but only the copy in memory is updated.
+    pub fn render_to_target(
+        &mut self,
+        target: &mut RenderTarget,
+        sprite_sheets: &[&SpriteSheet],
+        scene: &Scene,
+    ) -> Result<(), RenderError> {
+        // The scene is drawn on the canvas, which is swapped back afterwards.
+        let size = target.size();
+        let pixels = vec![0; (size.width * size.height) as usize * 4];
+        let canvas_size = std::mem::replace(&mut self.canvas.size, size);
+        let canvas_pixels = std::mem::replace(&mut self.canvas.pixels, pixels);
+        let drawn = self.render(sprite_sheets, scene);
+        self.canvas.size = canvas_size;
+        let pixels = std::mem::replace(&mut self.canvas.pixels, canvas_pixels);
+        drawn?;
+        target.sheet.set_texture_pixels(pixels);
+        Ok(())
+    }
+
+    /// Draw a scene to the canvas, in the same order as [crate::Renderer::render].
+    pub fn render(
+        &mut self,
+        sprite_sheets: &[&SpriteSheet],
+        scene: &Scene,
+    ) -> Result<(), RenderError> {
+        // Sprites are resolved first, so that an invalid sprite leaves the canvas untouched.
+        let resolve = |instances: &[SpriteInstance]| {
+            let mut sprites = Vec::with_capacity(instances.len());
+            for instance in instances {
+                let (page, entry) = match sprite::lookup(sprite_sheets, instance.sprite) {
+                    Ok(found) => found,
+                    Err(err) => match self.invalid_sprite_policy {
+                        InvalidSpritePolicy::Skip => continue,
+                        InvalidSpritePolicy::Placeholder => {
+                            sprite::lookup(&[&self.placeholder_sheet], self.placeholder).unwrap()
+                        }
+                        InvalidSpritePolicy::Error => return Err(RenderError::InvalidSprite(err)),
+                    },
+                };
+                let data = InstanceData::new(entry, instance.position);
+                sprites.push((page, data, instance.blend));
+            }
+            Ok(sprites)
+        };
+        let sprites = resolve(&scene.sprites)?;
+        // Materials are GPU shaders, so their items are drawn as if they had none.
+        let mut material_draws = Vec::with_capacity(scene.material_draws.len());
+        for draw in &scene.material_draws {
+            material_draws.push((resolve(&draw.sprites)?, &draw.rectangles));
+        }
+
+        let canvas = &mut self.canvas;
+        let background = scene.background.to_premultiplied_srgb8();
+        for pixel in canvas.pixels.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&background);
+        }
+
+        for instance in &scene.tilemaps {
+            let tilemap = instance.tilemap;
+            for layer in 0..tilemap.layer_count() {
+                for (position, tile) in tilemap.layer_tiles(layer) {
+                    let Ok((page, entry)) = sprite::lookup(sprite_sheets, tile) else {
+                        continue;
+                    };
+                    let position = [
+                        instance.position[0] + position[0],
+                        instance.position[1] + position[1],
+                    ];
+                    canvas.draw_sprite(page, &InstanceData::new(entry, position), instance.blend);
+                }
+            }
+        }
+        for (page, instance, mode) in sprites {
+            canvas.draw_sprite(page, &instance, mode);
+        }
+        for rectangle in &scene.rectangles {
+            canvas.draw_rectangle(rectangle);
+        }
+        for (sprites, rectangles) in material_draws {
+            for (page, instance, mode) in sprites {
+                canvas.draw_sprite(page, &instance, mode);
+            }
+            for rectangle in rectangles {
+                canvas.fill_rectangle(rectangle);
+            }
+        }
+        for pixel in &scene.pixels {
+            let [x, y] = pixel.position;
+            // Points cover the pixel whose center is nearest, preferring the left and top.
+            canvas.blend(
+                (x - 0.5).ceil() as i32,
+                (y + 0.5).floor() as i32,
+                pixel.color.to_premultiplied(),
+                BlendMode::Alpha,
+            );
+        }
+        for LineStrip { points, blend } in &scene.linestrips {
+            for segment in points.windows(2) {
+                canvas.draw_line(&segment[0], &segment[1], *blend);
+            }
+        }
+        for circle in &scene.circles {
+            canvas.draw_circle(circle);
+        }
+
+        let mut glyphs = Vec::new();
+        for text in &scene.texts {
+            text.font.layout(text, |sprite, position| {
+                glyphs.push((sprite, position, text.color))
+            });
+        }
+        for text in &scene.rich_texts {
+            glyphs.extend(text.glyphs());
+        }
+        for (sprite, position, color) in glyphs {
+            if let Ok((page, entry)) = sprite::lookup(sprite_sheets, sprite) {
+                let instance = InstanceData::new(entry, position).with_color(color);
+                canvas.draw_sprite(page, &instance, BlendMode::Alpha);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Canvas pixels, and how to draw on them.
+struct Canvas {
+    size: Size,
+    /// Pixels in `Rgba8UnormSrgb`, top-to-bottom left-to-right.
+    pixels: Vec<u8>,
+    /// Linear value of every sRGB byte.
+    linear: [f32; 256],
+}
+
+impl Canvas {
+    /// Blend a premultiplied linear color onto a canvas pixel, if it's on the canvas.
+    fn blend(&mut self, x: i32, y: i32, color: [f32; 4], mode: BlendMode) {
+        let Size { width, height } = self.size;
+        if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
+            return;
+        }
+        // The canvas is stored top-to-bottom, but the Y-axis points up.
+        let i = ((height - 1 - y as u32) * width + x as u32) as usize * 4;
+        let pixel = &mut self.pixels[i..i + 4];
+
+        let color = color.map(|c| c.clamp(0.0, 1.0));
+        let canvas = [
+            self.linear[pixel[0] as usize],
+            self.linear[pixel[1] as usize],
+            self.linear[pixel[2] as usize],
+            pixel[3] as f32 / 255.0,
+        ];
+        let blended = mode.apply(color, canvas);
+        for c in 0..3 {
+            pixel[c] = to_byte(linear_to_srgb(blended[c]));
+        }
+        pixel[3] = to_byte(blended[3]);
+    }
+
+    fn draw_sprite(&mut self, page: &SheetPage, instance: &InstanceData, mode: BlendMode) {
+        let [width, height] = instance.dimensions;
+        let [x, y] = instance.position;
+        for j in 0..height {
+            // Sprites are stored top-to-bottom.
+            let row = instance.sheet_position + (height - 1 - j) * width;
+            for i in 0..width {
+                let texel = page.texel(row + i);
+                let color = [
+                    self.linear[texel[0] as usize] * instance.color[0],
+                    self.linear[texel[1] as usize] * instance.color[1],
+                    self.linear[texel[2] as usize] * instance.color[2],
+                    texel[3] as f32 / 255.0 * instance.color[3],
+                ];
+                if color != [0.0; 4] {
+                    self.blend(x + i as i32, y + j as i32, color, mode);
+                }
+            }
+        }
+    }
+
+    /// Draw the outline of a rectangle as a closed strip through its corners.
+    /// Like any line, every side leaves out its last pixel, which the next side starts with.
+    /// As a result, sides of rectangles one pixel wide or high are drawn twice,
+    /// and a rectangle of a single pixel isn't drawn at all.
+    fn draw_rectangle(&mut self, rectangle: &Rectangle) {
+        let [x0, y0] = rectangle.position;
+        let [width, height] = rectangle.dimensions;
+        if width == 0 || height == 0 {
+            return;
+        }
+        let (x1, y1) = (x0 + width as i32 - 1, y0 + height as i32 - 1);
+        let corners = [[x0, y0], [x1, y0], [x1, y1], [x0, y1], [x0, y0]];
+        for side in corners.windows(2) {
+            let [[mut x, mut y], [x_end, y_end]] = [side[0], side[1]];
+            while [x, y] != [x_end, y_end] {
+                self.blend(x, y, rectangle.color.to_premultiplied(), rectangle.blend);
+                x += (x_end - x).signum();
+                y += (y_end - y).signum();
+            }
+        }
+    }
+
+    fn fill_rectangle(&mut self, rectangle: &Rectangle) {
+        let [x0, y0] = rectangle.position;
+        let [width, height] = rectangle.dimensions;
+        for y in y0..y0 + height as i32 {
+            for x in x0..x0 + width as i32 {
+                self.blend(x, y, rectangle.color.to_premultiplied(), rectangle.blend);
+            }
+        }
+    }
+
+    /// Draw every pixel whose diamond, the points less than half a pixel from its center in
+    /// Manhattan distance, is exited by the line.
+    /// Colors are interpolated between the endpoints.
+    fn draw_line(&mut self, start: &PrimitiveVertex, end: &PrimitiveVertex, mode: BlendMode) {
+        let a = start.position.map(f64::from);
+        let b = end.position.map(f64::from);
+        let a = [a[0] + NUDGE[0], a[1] + NUDGE[1]];
+        let b = [b[0] + NUDGE[0], b[1] + NUDGE[1]];
+        let d = [b[0] - a[0], b[1] - a[1]];
+        let length_squared = d[0] * d[0] + d[1] * d[1];
+        if length_squared == 0.0 {
+            return;
+        }
+
+        // Pixel centers are at integer coordinates.
+        // Only the pixels next to the line in every column or row along its major axis can be hit.
+        let major = if d[0].abs() >= d[1].abs() { 0 } else { 1 };
+        let minor = 1 - major;
+        let first = a[major].min(b[major]).floor() as i32;
+        let last = a[major].max(b[major]).ceil() as i32;
+        for m in first..=last {
+            let t = ((m as f64 - a[major]) / d[major]).clamp(0.0, 1.0);
+            let n = (a[minor] + d[minor] * t).round() as i32;
+            for n in n - 1..=n + 1 {
+                let mut center = [0.0; 2];
+                center[major] = m as f64;
+                center[minor] = n as f64;
+                if manhattan_distance(a, b, center) >= 0.5 || manhattan(b, center) < 0.5 {
+                    continue;
+                }
+                let t = ((center[0] - a[0]) * d[0] + (center[1] - a[1]) * d[1]) / length_squared;
+                let t = t.clamp(0.0, 1.0) as f32;
+                let (start, end) = (start.color.to_premultiplied(), end.color.to_premultiplied());
+                let color = std::array::from_fn(|c| start[c] + (end[c] - start[c]) * t);
+                self.blend(center[0] as i32, center[1] as i32, color, mode);
+            }
+        }
+    }
+
+    /// Draw the pixels of a circle's quad that the fragment shader keeps.
+    fn draw_circle(&mut self, circle: &Circle) {
+        let diameter = circle.diameter as f32;
+        let pixel_size = 2.0 / diameter;
+        for j in 0..circle.diameter {
+            for i in 0..circle.diameter {
+                let position = [(i as f32 + 0.5) / diameter, (j as f32 + 0.5) / diameter];
+                let coord = position.map(|p| 2.0 * p - 1.0);
+                let distance = (coord[0] * coord[0] + coord[1] * coord[1]).sqrt();
+                if 1.0 - pixel_size <= distance && distance <= 1.0 {
+                    let [x, y] = circle.offset;
+                    let color = circle.color.to_premultiplied();
+                    self.blend(x + i as i32, y + j as i32, color, circle.blend);
+                }
+            }
+        }
+    }
+}
+
+fn manhattan(a: [f64; 2], b: [f64; 2]) -> f64 {
+    (a[0] - b[0]).abs() + (a[1] - b[1]).abs()
+}
+
+/// Shortest Manhattan distance from a point to the segment from `a` to `b`.
+/// The distance is piecewise linear along the segment,
+/// so the minimum is at an endpoint or where the segment is level with the point.
+fn manhattan_distance(a: [f64; 2], b: [f64; 2], point: [f64; 2]) -> f64 {
+    let d = [b[0] - a[0], b[1] - a[1]];
+    let mut distance = manhattan(a, point).min(manhattan(b, point));
+    for axis in 0..2 {
+        let t = (point[axis] - a[axis]) / d[axis];
+        if (0.0..=1.0).contains(&t) {
+            let on_segment = [a[0] + d[0] * t, a[1] + d[1] * t];
+            distance = distance.min(manhattan(on_segment, point));
+        }
+    }
+    distance
+}
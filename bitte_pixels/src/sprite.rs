@@ -1,42 +1,102 @@
-use std::{num::NonZeroU32, rc::Rc};
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    num::NonZeroU32,
+    ops::Range,
+    rc::Rc,
+    sync::atomic::{AtomicU32, Ordering},
+};
 use wgpu::util::DeviceExt;
 
+use super::{blend, BlendMode, Color};
+
 /// Sprite data to submit for drawing.
 #[derive(Clone)]
 pub struct SpriteInstance {
     /// Position on the target canvas.
     pub position: [i32; 2],
-    /// Index/identifier in the sprite sheet.
+    /// Sprite sheet and index in the sheet.
     pub sprite: SpriteHandle,
+    /// How the sprite blends with the canvas.
+    pub blend: BlendMode,
 }
 
 /// Sent to the shader for rendering.
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct InstanceData {
+pub(crate) struct InstanceData {
     /// Position on the canvas
-    position: [i32; 2],
+    pub position: [i32; 2],
     /// Width and height in pixels.
-    dimensions: [u32; 2],
+    pub dimensions: [u32; 2],
     /// Zero-indexed position in the sheet.
-    sheet_position: u32,
+    pub sheet_position: u32,
+    /// Multiplied with the sprite's pixels.
+    pub color: [f32; 4],
 }
 
-const INSTANCE_LAYOUT: wgpu::VertexBufferLayout = wgpu::VertexBufferLayout {
+impl InstanceData {
+    /// Instance data for drawing a sprite at a position.
+    pub(crate) fn new(entry: &SpriteEntry, position: [i32; 2]) -> Self {
+        InstanceData {
+            position: [position[0] + entry.offset.0, position[1] + entry.offset.1],
+            sheet_position: entry.address,
+            dimensions: [entry.dimensions.0.into(), entry.dimensions.1.into()],
+            color: [1.0; 4],
+        }
+    }
+
+    /// Tint the sprite with a color.
+    pub(crate) fn with_color(self, color: Color) -> Self {
+        InstanceData {
+            color: color.to_premultiplied(),
+            ..self
+        }
+    }
+}
+
+pub(crate) const INSTANCE_LAYOUT: wgpu::VertexBufferLayout = wgpu::VertexBufferLayout {
     array_stride: std::mem::size_of::<InstanceData>() as wgpu::BufferAddress,
     step_mode: wgpu::VertexStepMode::Instance,
     attributes: &wgpu::vertex_attr_array![
         1 => Sint32x2,
         2 => Uint32x2,
         3 => Uint32,
+        4 => Float32x4,
     ],
 };
 
+/// What to do when a sprite's handle can't be resolved during rendering,
+/// because its sheet wasn't passed to render, or because it has been removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvalidSpritePolicy {
+    /// Silently leave the sprite out.
+    Skip,
+    /// Draw a magenta and black checkerboard in place of the sprite.
+    Placeholder,
+    /// Abort rendering the frame with [crate::RenderError::InvalidSprite].
+    Error,
+}
+
+impl Default for InvalidSpritePolicy {
+    /// Placeholders in debug builds, so mistakes are visible, and skipping otherwise.
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            InvalidSpritePolicy::Placeholder
+        } else {
+            InvalidSpritePolicy::Skip
+        }
+    }
+}
+
 pub(crate) struct Renderer {
     ctx: Rc<super::Context>,
-    sprite_sheet_layout: wgpu::BindGroupLayout,
-    pipeline: wgpu::RenderPipeline,
+    sprite_sheet_layout: Rc<wgpu::BindGroupLayout>,
+    pub(crate) pipelines: blend::Pipelines,
     instance_buffer: wgpu::Buffer,
+    pub invalid_sprite_policy: InvalidSpritePolicy,
+    placeholder_sheet: SpriteSheet,
+    placeholder: SpriteHandle,
 }
 
 impl Renderer {
@@ -68,102 +128,221 @@ impl Renderer {
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("sprite"),
-            bind_group_layouts: &[&ctx.canvas.dimensions_layout, &sprite_sheet_layout],
+            bind_group_layouts: &[&ctx.dimensions_layout, &sprite_sheet_layout],
             push_constant_ranges: &[],
         });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("sprite"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &ctx.shaders,
-                entry_point: "sprite_v",
-                buffers: &[super::QUAD_LAYOUT, INSTANCE_LAYOUT],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &ctx.shaders,
-                entry_point: "sprite_f",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: ctx.canvas.color_format,
-                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::all(),
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleStrip,
-                ..Default::default()
-            },
-            multisample: wgpu::MultisampleState::default(),
-            depth_stencil: None,
-            multiview: None,
+        let pipelines = blend::Pipelines::new(ctx.shaders.clone(), {
+            let ctx = ctx.clone();
+            move |module, blend| create_pipeline(&ctx, &pipeline_layout, module, blend)
         });
 
+        let sprite_sheet_layout = Rc::new(sprite_sheet_layout);
+        let gpu = Gpu {
+            context: ctx.clone(),
+            layout: sprite_sheet_layout.clone(),
+        };
+        let mut builder = new_builder(Some(gpu), "placeholder");
+        let placeholder = builder.add(placeholder_sprite());
+        let placeholder_sheet = builder.build();
+
         Self {
             ctx,
             sprite_sheet_layout,
-            pipeline,
+            pipelines,
             instance_buffer,
+            invalid_sprite_policy: InvalidSpritePolicy::default(),
+            placeholder_sheet,
+            placeholder,
         }
     }
 
+    pub fn sprite_sheet_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.sprite_sheet_layout
+    }
+
     pub fn create_sprite_sheet_builder<'a>(&'a self, name: &'a str) -> SpriteSheetBuilder<'a> {
-        let mut res = SpriteSheetBuilder {
-            name,
-            context: self.ctx.as_ref(),
-            layout: &self.sprite_sheet_layout,
-            table: Vec::new(),
-            pixel_count: 0,
-            data: Vec::new(),
-        };
-        // Add a dummy sprite to prevent zero-size buffer errors
-        res.add(SpriteData {
-            dimensions: (NonZeroU32::new(1).unwrap(), NonZeroU32::new(1).unwrap()),
-            offset: (0, 0),
-            data: vec![0, 0, 0, 0],
-            pixels: 1,
-        });
-        res
+        new_builder(Some(self.gpu()), name)
+    }
+
+    pub fn load_sprite_sheet(
+        &self,
+        name: &str,
+        packed: PackedSpriteSheet,
+    ) -> Result<SpriteSheet, super::RenderError> {
+        load(Some(self.gpu()), name, packed)
+    }
+
+    pub(crate) fn gpu(&self) -> Gpu {
+        Gpu {
+            context: self.ctx.clone(),
+            layout: self.sprite_sheet_layout.clone(),
+        }
+    }
+
+    /// Find the page and table entry of a sprite, or what to draw instead if it's invalid.
+    /// Returns `None` if the sprite should be skipped.
+    pub(crate) fn resolve<'a>(
+        &'a self,
+        sprite_sheets: &[&'a SpriteSheet],
+        handle: SpriteHandle,
+    ) -> Result<Option<(&'a SheetPage, &'a SpriteEntry)>, HandleError> {
+        match lookup(sprite_sheets, handle) {
+            Ok(found) => Ok(Some(found)),
+            Err(err) => match self.invalid_sprite_policy {
+                InvalidSpritePolicy::Skip => Ok(None),
+                InvalidSpritePolicy::Placeholder => Ok(Some(
+                    lookup(&[&self.placeholder_sheet], self.placeholder).unwrap(),
+                )),
+                InvalidSpritePolicy::Error => Err(err),
+            },
+        }
+    }
+
+    /// A page to bind where a sprite sheet is expected, but no sprites are drawn from it.
+    pub(crate) fn placeholder_page(&self) -> &SheetPage {
+        &self.placeholder_sheet.pages[0]
     }
 
     pub fn render<'a>(
-        &'a mut self,
+        &'a self,
         render_pass: &mut wgpu::RenderPass<'a>,
-        sprite_sheet: &'a SpriteSheet,
+        sprite_sheets: &[&'a SpriteSheet],
         sprites: &[SpriteInstance],
-    ) {
-        let instances: Box<[InstanceData]> = sprites
-            .iter()
-            .map(|sprite| {
-                let entry = &sprite_sheet.table[sprite.sprite.0];
-                let position = [
-                    sprite.position[0] + entry.offset.0,
-                    sprite.position[1] + entry.offset.1,
-                ];
-                InstanceData {
-                    position,
-                    sheet_position: entry.address,
-                    dimensions: [entry.dimensions.0.into(), entry.dimensions.1.into()],
+    ) -> Result<(), HandleError> {
+        // Consecutive sprites on the same sheet page and with the same blend mode
+        // are drawn in a single batch.
+        // Batches are never reordered, so sprites keep their draw order.
+        let mut batches: Vec<(&'a SheetPage, BlendMode, Range<u32>)> = Vec::new();
+        let mut instances: Vec<InstanceData> = Vec::with_capacity(sprites.len());
+        for sprite in sprites {
+            let Some((page, entry)) = self.resolve(sprite_sheets, sprite.sprite)? else {
+                continue;
+            };
+            let start = batches.last().map_or(0, |(_, _, range)| range.end);
+            match batches.last_mut() {
+                Some((last, mode, range)) if std::ptr::eq(*last, page) && *mode == sprite.blend => {
+                    range.end += 1
                 }
-            })
-            .collect();
+                _ => batches.push((page, sprite.blend, start..start + 1)),
+            }
+            instances.push(InstanceData::new(entry, sprite.position));
+        }
         self.ctx.queue.write_buffer(
             &self.instance_buffer,
             0,
-            bytemuck::cast_slice(instances.as_ref()),
+            bytemuck::cast_slice(instances.as_slice()),
         );
 
-        render_pass.set_pipeline(&self.pipeline);
-        render_pass.set_bind_group(1, &sprite_sheet.bind_group, &[]);
         render_pass.set_vertex_buffer(0, self.ctx.quad_buffer.slice(..));
         render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-        render_pass.draw(
-            0..super::QUAD_VERTICES.len() as u32,
-            0..sprites.len() as u32,
-        );
+        for (page, mode, range) in batches {
+            let Some(bind_group) = page.bind_group() else {
+                continue;
+            };
+            render_pass.set_pipeline(self.pipelines.get(mode));
+            render_pass.set_bind_group(1, bind_group, &[]);
+            render_pass.draw(0..super::QUAD_VERTICES.len() as u32, range);
+        }
+        Ok(())
     }
 }
 
-// TODO: Figure out whether unused variables can be dropped.
+fn create_pipeline(
+    ctx: &super::Context,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    blend: wgpu::BlendState,
+) -> wgpu::RenderPipeline {
+    ctx.device
+        .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("sprite"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module,
+                entry_point: "sprite_v",
+                buffers: &[super::QUAD_LAYOUT, INSTANCE_LAYOUT],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module,
+                entry_point: "sprite_f",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: ctx.color_format,
+                    blend: Some(blend),
+                    write_mask: wgpu::ColorWrites::all(),
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            multisample: wgpu::MultisampleState::default(),
+            depth_stencil: None,
+            multiview: None,
+        })
+}
+
+/// Find the page and table entry of a sprite among the sheets passed to render.
+pub(crate) fn lookup<'a>(
+    sprite_sheets: &[&'a SpriteSheet],
+    handle: SpriteHandle,
+) -> Result<(&'a SheetPage, &'a SpriteEntry), HandleError> {
+    let sheet = sprite_sheets
+        .iter()
+        .find(|sheet| sheet.id == handle.sheet)
+        .ok_or(HandleError::MissingSheet)?;
+    let entry = sheet.entry(handle)?;
+    Ok((&sheet.pages[entry.page as usize], entry))
+}
+
+/// Start a sheet that is uploaded to `gpu`, or only kept in memory for the software renderer.
+pub(crate) fn new_builder(gpu: Option<Gpu>, name: &str) -> SpriteSheetBuilder<'_> {
+    let page_capacity = page_capacity(&limits(gpu.as_ref()));
+    let mut res = SpriteSheetBuilder {
+        id: SheetId::next(),
+        name,
+        gpu,
+        table: Vec::new(),
+        page_capacity,
+        pages: vec![PackedPage::default()],
+        names: HashMap::new(),
+    };
+    // Add a dummy sprite to prevent zero-size buffer errors
+    res.add(SpriteData {
+        dimensions: (NonZeroU32::new(1).unwrap(), NonZeroU32::new(1).unwrap()),
+        offset: (0, 0),
+        data: vec![0, 0, 0, 0],
+        pixels: 1,
+    });
+    res
+}
+
+/// Load a sheet that was packed with [SpriteSheetBuilder::pack].
+pub(crate) fn load(
+    gpu: Option<Gpu>,
+    name: &str,
+    packed: PackedSpriteSheet,
+) -> Result<SpriteSheet, super::RenderError> {
+    let capacity = page_capacity(&limits(gpu.as_ref()));
+    if packed.pages.iter().any(|page| page.pixel_count > capacity) {
+        return Err(super::RenderError::Other(format!(
+            "sprite sheet {name:?} has pages larger than {capacity} pixels"
+        )));
+    }
+    Ok(upload(gpu, name, SheetId::next(), packed))
+}
+
+/// An 8x8 magenta and black checkerboard, drawn in place of invalid sprites.
+pub(crate) fn placeholder_sprite() -> SpriteData {
+    let data = (0..64)
+        .flat_map(|i| match (i % 8 / 2 + i / 16) % 2 {
+            0 => [255, 0, 255, 255],
+            _ => [0, 0, 0, 255],
+        })
+        .collect();
+    SpriteData::new((8, 8), (0, 0), data).unwrap()
+}
+
 pub struct SpriteSheet {
     /// Sprites are __not__ stored as packed boxes in a 2-dimensional grid,
     /// (as is the usual approach).
@@ -172,132 +351,920 @@ pub struct SpriteSheet {
     /// instead indexing the texture with integer coordinates to get unfiltered pixels.
     /// However, 1-dimensional textures are limited to 2048 pixels (WebGL2),
     /// so this sequence is stored in a 2-dimensional texture.
-    /// This brings the maximum pixel count per sprite sheet up to 4 million.
-    _texture: wgpu::Texture,
-    _texture_view: wgpu::TextureView,
+    /// This limits a single texture to `max_texture_dimension_2d`² pixels,
+    /// so larger sheets are split into several pages with a texture each.
+    pages: Vec<SheetPage>,
+    table: Vec<Slot>,
+    /// Slots of removed sprites, to be reused by later insertions.
+    free_slots: Vec<usize>,
+    names: HashMap<String, Range<usize>>,
+    id: SheetId,
+    /// Bumped whenever existing sprites are changed or moved.
+    revision: u64,
+    name: String,
+    /// Missing for sheets that are only drawn by the software renderer.
+    gpu: Option<Gpu>,
+}
+
+/// What a sheet needs to upload its pages.
+#[derive(Clone)]
+pub(crate) struct Gpu {
+    context: Rc<super::Context>,
+    layout: Rc<wgpu::BindGroupLayout>,
+}
+
+pub(crate) struct SheetPage {
+    /// Missing for sheets that are only drawn by the software renderer.
+    texture: Option<PageTexture>,
+    /// Width of the texture, for converting addresses to texel coordinates.
+    width: u32,
+    /// Copy of the texture contents, kept so that sprites can be moved when compacting,
+    /// and drawn by the software renderer.
+    data: Vec<u8>,
+    /// Unallocated address ranges, sorted by address.
+    free: Vec<Range<u32>>,
+}
+
+struct PageTexture {
+    /// Shared with the canvas of render targets, which are drawn as sprites.
+    texture: Rc<wgpu::Texture>,
+    _view: wgpu::TextureView,
     bind_group: wgpu::BindGroup,
-    table: Box<[SpriteEntry]>,
+}
+
+/// An entry in the sprite table.
+/// The generation is bumped whenever the sprite is removed,
+/// which invalidates any outstanding handles to it.
+struct Slot {
+    generation: u32,
+    entry: Option<SpriteEntry>,
+}
+
+/// Minimum amount of pixels in pages that are added at runtime.
+const GROWTH_PIXELS: u32 = 1 << 20;
+
+impl SpriteSheet {
+    /// Identifier that handles into this sheet are tagged with.
+    pub fn id(&self) -> SheetId {
+        self.id
+    }
+
+    pub(crate) fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Replace the pixels of a sheet made by [texture_sheet] that has no texture.
+    pub(crate) fn set_texture_pixels(&mut self, pixels: Vec<u8>) {
+        self.pages[0].data = pixels;
+        self.revision += 1;
+    }
+
+    pub(crate) fn page(&self, page: u32) -> &SheetPage {
+        &self.pages[page as usize]
+    }
+
+    /// Number of textures that the sheet is split into.
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Whether sprites of the sheet are drawn from `texture`, as those of render targets are.
+    pub(crate) fn reads_texture(&self, texture: &Rc<wgpu::Texture>) -> bool {
+        self.pages.iter().any(|page| {
+            page.texture
+                .as_ref()
+                .is_some_and(|page| Rc::ptr_eq(&page.texture, texture))
+        })
+    }
+
+    /// Look up a sprite added with [SpriteSheetBuilder::add_named].
+    /// Named animations resolve to their first frame.
+    pub fn sprite(&self, name: &str) -> Option<SpriteHandle> {
+        self.animation(name).map(|animation| animation.get_frame(0))
+    }
+
+    /// Look up an animation added with [SpriteSheetBuilder::add_named_animation].
+    pub fn animation(&self, name: &str) -> Option<AnimationHandle> {
+        self.names.get(name).map(|range| AnimationHandle {
+            sheet: self.id,
+            offset: range.start,
+            frame_count: range.len(),
+            generation: self.table[range.start].generation,
+        })
+    }
+
+    /// Whether the handle refers to a sprite that is currently in this sheet.
+    pub fn contains(&self, handle: SpriteHandle) -> bool {
+        self.entry(handle).is_ok()
+    }
+
+    /// Width and height of a sprite in pixels.
+    pub fn dimensions(&self, handle: SpriteHandle) -> Result<(u32, u32), HandleError> {
+        let entry = self.entry(handle)?;
+        Ok((entry.dimensions.0.get(), entry.dimensions.1.get()))
+    }
+
+    fn entry(&self, handle: SpriteHandle) -> Result<&SpriteEntry, HandleError> {
+        if handle.sheet != self.id {
+            return Err(HandleError::WrongSheet);
+        }
+        match self.table.get(handle.index) {
+            Some(Slot {
+                generation,
+                entry: Some(entry),
+            }) if *generation == handle.generation => Ok(entry),
+            _ => Err(HandleError::Removed),
+        }
+    }
+
+    /// Add a sprite to the sheet at runtime.
+    ///
+    /// Only the pixels of the new sprite are uploaded.
+    /// If no page has room for it, fragmented pages are compacted,
+    /// and a new page is created as a last resort.
+    ///
+    /// Panics if the sprite is larger than an entire page.
+    pub fn insert(&mut self, sprite: SpriteData) -> SpriteHandle {
+        let entry = self.allocate(sprite);
+        let index = match self.free_slots.pop() {
+            Some(index) => {
+                self.table[index].entry = Some(entry);
+                index
+            }
+            None => {
+                self.table.push(Slot {
+                    generation: 0,
+                    entry: Some(entry),
+                });
+                self.table.len() - 1
+            }
+        };
+        SpriteHandle {
+            sheet: self.id,
+            index,
+            generation: self.table[index].generation,
+        }
+    }
+
+    /// Add an animation to the sheet at runtime.
+    /// Frames are always given fresh, consecutive slots.
+    ///
+    /// Panics if `sprites` is empty.
+    pub fn insert_animation(&mut self, sprites: Vec<SpriteData>) -> AnimationHandle {
+        assert!(!sprites.is_empty(), "animation has no frames");
+        let offset = self.table.len();
+        for sprite in sprites {
+            let entry = self.allocate(sprite);
+            self.table.push(Slot {
+                generation: 0,
+                entry: Some(entry),
+            });
+        }
+        AnimationHandle {
+            sheet: self.id,
+            offset,
+            frame_count: self.table.len() - offset,
+            generation: 0,
+        }
+    }
+
+    /// Replace the image of a sprite, keeping the handle valid.
+    pub fn replace(&mut self, handle: SpriteHandle, sprite: SpriteData) -> Result<(), HandleError> {
+        let old = self.entry(handle)?;
+        let (page, address) = (old.page as usize, old.address);
+        let old_pixels = old.pixel_count();
+
+        let entry = if old_pixels == sprite.pixels {
+            self.write(page, address, &sprite.data);
+            SpriteEntry {
+                page: page as u32,
+                address,
+                dimensions: sprite.dimensions,
+                offset: sprite.offset,
+            }
+        } else {
+            self.pages[page].release(address..address + old_pixels);
+            self.table[handle.index].entry = None;
+            self.allocate(sprite)
+        };
+        self.table[handle.index].entry = Some(entry);
+        self.revision += 1;
+        Ok(())
+    }
+
+    /// Remove a sprite from the sheet, freeing its pixels.
+    /// Any handles to the sprite become invalid.
+    ///
+    /// Removing a frame of a named animation also removes the name,
+    /// as the animation is no longer whole. Its other frames stay in the sheet.
+    pub fn remove(&mut self, handle: SpriteHandle) -> Result<(), HandleError> {
+        let entry = self.entry(handle)?;
+        let (page, address) = (entry.page as usize, entry.address);
+        let pixels = entry.pixel_count();
+        self.pages[page].release(address..address + pixels);
+
+        let slot = &mut self.table[handle.index];
+        slot.entry = None;
+        slot.generation += 1;
+        self.free_slots.push(handle.index);
+        self.names.retain(|_, range| !range.contains(&handle.index));
+        self.revision += 1;
+        Ok(())
+    }
+
+    /// Move all sprites to the start of their pages,
+    /// merging the free space into one contiguous block per page.
+    ///
+    /// Handles stay valid, but every page is uploaded again.
+    pub fn compact(&mut self) {
+        for page in 0..self.pages.len() {
+            self.compact_page(page);
+        }
+    }
+
+    fn compact_page(&mut self, page_index: usize) {
+        let mut entries: Vec<&mut SpriteEntry> = self
+            .table
+            .iter_mut()
+            .filter_map(|slot| slot.entry.as_mut())
+            .filter(|entry| entry.page as usize == page_index)
+            .collect();
+        entries.sort_by_key(|entry| entry.address);
+
+        let page = &mut self.pages[page_index];
+        let mut end = 0;
+        for entry in entries {
+            let pixels = entry.pixel_count();
+            let from = entry.address as usize * 4..(entry.address + pixels) as usize * 4;
+            page.data.copy_within(from, end as usize * 4);
+            entry.address = end;
+            end += pixels;
+        }
+        let capacity = page.capacity();
+        page.data[end as usize * 4..].fill(0);
+        page.free = free_list(end..capacity);
+        self.upload_range(page_index, 0..capacity);
+        self.revision += 1;
+    }
+
+    /// Find room for a sprite and upload it.
+    fn allocate(&mut self, sprite: SpriteData) -> SpriteEntry {
+        let max_pixels = page_capacity(&limits(self.gpu.as_ref()));
+        assert!(
+            sprite.pixels <= max_pixels,
+            "sprite with {} pixels doesn't fit in a sprite sheet page",
+            sprite.pixels
+        );
+
+        let found = self
+            .pages
+            .iter_mut()
+            .enumerate()
+            .find_map(|(i, page)| Some((i, page.claim(sprite.pixels)?)));
+        let (page, address) = match found {
+            Some(found) => found,
+            None => match self
+                .pages
+                .iter()
+                .position(|page| page.free_pixels() >= sprite.pixels)
+            {
+                Some(page) => {
+                    info!("Compacting page {page} of sprite sheet {:?}", self.name);
+                    self.compact_page(page);
+                    (page, self.pages[page].claim(sprite.pixels).unwrap())
+                }
+                None => {
+                    let pixels = sprite.pixels.max(GROWTH_PIXELS).min(max_pixels);
+                    let data = PackedPage {
+                        pixel_count: pixels,
+                        data: vec![0; pixels as usize * 4],
+                    };
+                    let mut page =
+                        upload_page(self.gpu.as_ref(), &self.name, self.pages.len(), data);
+                    page.free = free_list(0..page.capacity());
+                    self.pages.push(page);
+                    let page = self.pages.len() - 1;
+                    (page, self.pages[page].claim(sprite.pixels).unwrap())
+                }
+            },
+        };
+
+        self.write(page, address, &sprite.data);
+        SpriteEntry {
+            page: page as u32,
+            address,
+            dimensions: sprite.dimensions,
+            offset: sprite.offset,
+        }
+    }
+
+    /// Write pixels to the page at an address, and upload them.
+    fn write(&mut self, page: usize, address: u32, data: &[u8]) {
+        let start = address as usize * 4;
+        self.pages[page].data[start..start + data.len()].copy_from_slice(data);
+        self.upload_range(page, address..address + data.len() as u32 / 4);
+    }
+
+    /// Upload a range of addresses from the copy of a page.
+    fn upload_range(&self, page: usize, range: Range<u32>) {
+        let (Some(gpu), page) = (&self.gpu, &self.pages[page]) else {
+            return;
+        };
+        let Some(texture) = &page.texture else {
+            return;
+        };
+
+        // The range covers a partial first row, some full rows, and a partial last row.
+        // Each of these is a rectangle that can be uploaded on its own.
+        let width = page.width;
+        let mut rows = Vec::with_capacity(3);
+        let (mut cursor, end) = (range.start, range.end);
+        if cursor % width != 0 {
+            let row_end = (cursor / width + 1) * width;
+            rows.push((cursor, row_end.min(end) - cursor, 1));
+            cursor = row_end.min(end);
+        }
+        let full_rows = (end - cursor) / width;
+        if full_rows > 0 {
+            rows.push((cursor, width, full_rows));
+            cursor += full_rows * width;
+        }
+        if cursor < end {
+            rows.push((cursor, end - cursor, 1));
+        }
+
+        for (address, width, height) in rows {
+            let bytes = address as usize * 4..(address + width * height) as usize * 4;
+            gpu.context.queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: address % page.width,
+                        y: address / page.width,
+                        z: 0,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &page.data[bytes],
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(width * 4),
+                    rows_per_image: None,
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+}
+
+impl SheetPage {
+    /// Bind group of the page's texture, unless the sheet is only kept in memory.
+    pub(crate) fn bind_group(&self) -> Option<&wgpu::BindGroup> {
+        Some(&self.texture.as_ref()?.bind_group)
+    }
+
+    /// Color of the pixel at an address, in `Rgba8UnormSrgb`.
+    pub(crate) fn texel(&self, address: u32) -> [u8; 4] {
+        let i = address as usize * 4;
+        self.data[i..i + 4].try_into().unwrap()
+    }
+
+    fn capacity(&self) -> u32 {
+        (self.data.len() / 4) as u32
+    }
+
+    fn free_pixels(&self) -> u32 {
+        self.free.iter().map(|range| range.len() as u32).sum()
+    }
+
+    /// Allocate pixels from the first free range that is large enough.
+    fn claim(&mut self, pixels: u32) -> Option<u32> {
+        let i = self
+            .free
+            .iter()
+            .position(|range| range.len() as u32 >= pixels)?;
+        let address = self.free[i].start;
+        self.free[i].start += pixels;
+        if self.free[i].is_empty() {
+            self.free.remove(i);
+        }
+        Some(address)
+    }
+
+    /// Return pixels to the free list, merging them with adjacent free ranges.
+    fn release(&mut self, range: Range<u32>) {
+        let i = self.free.partition_point(|free| free.start < range.start);
+        self.free.insert(i, range);
+        if i + 1 < self.free.len() && self.free[i].end == self.free[i + 1].start {
+            self.free[i].end = self.free.remove(i + 1).end;
+        }
+        if i > 0 && self.free[i - 1].end == self.free[i].start {
+            self.free[i - 1].end = self.free.remove(i).end;
+        }
+    }
+}
+
+/// A free list consisting of a single range, unless it's empty.
+fn free_list(range: Range<u32>) -> Vec<Range<u32>> {
+    std::iter::once(range)
+        .filter(|range| !range.is_empty())
+        .collect()
+}
+
+/// Reasons that a [SpriteHandle] can't be used with a [SpriteSheet].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandleError {
+    /// The handle belongs to a different sheet.
+    WrongSheet,
+    /// The handle's sheet wasn't passed to render.
+    MissingSheet,
+    /// The sprite has been removed from the sheet.
+    Removed,
+}
+
+impl std::fmt::Display for HandleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            HandleError::WrongSheet => "sprite handle belongs to a different sheet",
+            HandleError::MissingSheet => "sprite sheet wasn't passed to render",
+            HandleError::Removed => "sprite has been removed from the sheet",
+        })
+    }
 }
 
 /// Sprite sheet data, for use with [crate::Renderer::create_sprite_sheet].
 pub struct SpriteSheetBuilder<'a> {
+    /// Identifier of the resulting sheet.
+    id: SheetId,
+
     /// Name for debugging messages.
     name: &'a str,
 
-    /// Render context.
-    /// We use limits, device, and queue here.
-    context: &'a super::Context,
-
-    /// Bindgroup layout, which for some reason cannot be constant.
-    layout: &'a wgpu::BindGroupLayout,
+    /// Render context and bind group layout to upload the sheet with,
+    /// missing if it's built for the software renderer.
+    gpu: Option<Gpu>,
 
     /// Entry lookup table.
     table: Vec<SpriteEntry>,
 
-    /// Current amount of pixels.
-    pixel_count: u32,
+    /// Maximum amount of pixels in a single page.
+    page_capacity: u32,
 
-    /// Image data.
-    ///
-    /// Sprite image data is __one-dimensional__.
-    /// Given a sprite `address` and a coordinate `(x, y)`,
-    /// the sampling coordinate can be computed by `address + x + y * width`.
-    ///
-    /// We lose linear filtering this way,
-    /// but we get to avoid the 2D box packing problem.
-    /// We aren't interested in linear filtering anyway.
-    data: Vec<u8>,
+    /// Image data, split into pages that each fit in a texture.
+    pages: Vec<PackedPage>,
+
+    /// Sprites and animations that can be looked up by name.
+    names: HashMap<String, Range<usize>>,
 }
 
 impl<'a> SpriteSheetBuilder<'a> {
+    /// Sprites never straddle two pages,
+    /// so a sprite that doesn't fit in the remaining space starts a new page.
+    ///
+    /// Panics if the sprite is larger than an entire page.
     fn push_sprite(&mut self, mut sprite: SpriteData) {
+        assert!(
+            sprite.pixels <= self.page_capacity,
+            "sprite with {} pixels doesn't fit in a sprite sheet page",
+            sprite.pixels
+        );
+        if self.pages.last().unwrap().pixel_count + sprite.pixels > self.page_capacity {
+            self.pages.push(PackedPage::default());
+        }
+        let page_index = (self.pages.len() - 1) as u32;
+        let page = self.pages.last_mut().unwrap();
         self.table.push(SpriteEntry {
-            address: self.pixel_count,
+            page: page_index,
+            address: page.pixel_count,
             dimensions: sprite.dimensions,
             offset: sprite.offset,
         });
-        self.data.append(&mut sprite.data);
-        self.pixel_count += sprite.pixels;
+        page.data.append(&mut sprite.data);
+        page.pixel_count += sprite.pixels;
     }
 
     pub fn add(&mut self, sprite: SpriteData) -> SpriteHandle {
         self.push_sprite(sprite);
-        SpriteHandle(self.table.len() - 1)
+        SpriteHandle {
+            sheet: self.id,
+            index: self.table.len() - 1,
+            generation: 0,
+        }
     }
 
+    /// Panics if `sprites` is empty.
     pub fn add_animation(&mut self, sprites: Vec<SpriteData>) -> AnimationHandle {
+        assert!(!sprites.is_empty(), "animation has no frames");
         let offset = self.table.len();
         for sprite in sprites {
             self.push_sprite(sprite);
         }
         let end = self.table.len();
         AnimationHandle {
+            sheet: self.id,
             offset,
             frame_count: end - offset,
+            generation: 0,
         }
     }
 
-    pub fn build(mut self) -> SpriteSheet {
-        let device = &self.context.device;
-
-        let max_width = self.context.limits.max_texture_dimension_2d;
-        let width = self.pixel_count.min(max_width);
-        let height = 1 + self.pixel_count / max_width;
-        info!(
-            "Sprite sheet {:?} dimensions are: {width}x{height}",
-            self.name
+    /// Add a sprite that can be looked up with [SpriteSheet::sprite].
+    pub fn add_named(&mut self, name: &str, sprite: SpriteData) -> SpriteHandle {
+        let handle = self.add(sprite);
+        self.names
+            .insert(name.to_owned(), handle.index..handle.index + 1);
+        handle
+    }
+
+    /// Add an animation that can be looked up with [SpriteSheet::animation].
+    pub fn add_named_animation(&mut self, name: &str, sprites: Vec<SpriteData>) -> AnimationHandle {
+        let handle = self.add_animation(sprites);
+        self.names.insert(
+            name.to_owned(),
+            handle.offset..handle.offset + handle.frame_count,
         );
+        handle
+    }
 
-        // Pad the texture data to match the exact dimensions of the texture.
-        let padding = (width * height - self.pixel_count) as usize;
-        self.data.extend(std::iter::repeat(0).take(padding * 4));
+    /// Finish the sheet without uploading it to the GPU.
+    /// The result can be cached with [PackedSpriteSheet::write]
+    /// and uploaded later with [crate::Renderer::load_sprite_sheet].
+    ///
+    /// A loaded sheet gets a new [SheetId],
+    /// so handles into it should be looked up by name rather than kept from the builder.
+    pub fn pack(self) -> PackedSpriteSheet {
+        PackedSpriteSheet {
+            table: self.table,
+            names: self.names,
+            pages: self.pages,
+        }
+    }
 
-        let texture = device.create_texture_with_data(
-            &self.context.queue,
-            &wgpu::TextureDescriptor {
-                label: Some(self.name),
-                size: wgpu::Extent3d {
-                    width,
-                    height,
-                    depth_or_array_layers: 1,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-                view_formats: &[wgpu::TextureFormat::Rgba8UnormSrgb],
+    pub fn build(mut self) -> SpriteSheet {
+        let (gpu, name, id) = (self.gpu.take(), self.name, self.id);
+        upload(gpu, name, id, self.pack())
+    }
+}
+
+/// Limits of the device that pages are uploaded to, or of the device that would be created.
+fn limits(gpu: Option<&Gpu>) -> wgpu::Limits {
+    gpu.map_or_else(super::limits, |gpu| gpu.context.limits.clone())
+}
+
+/// Amount of pixels that fit in a single sprite sheet texture.
+fn page_capacity(limits: &wgpu::Limits) -> u32 {
+    limits
+        .max_texture_dimension_2d
+        .saturating_mul(limits.max_texture_dimension_2d)
+}
+
+/// Create the sprite sheet textures from packed data, with a single upload per page.
+fn upload(gpu: Option<Gpu>, name: &str, id: SheetId, packed: PackedSpriteSheet) -> SpriteSheet {
+    let pages = packed
+        .pages
+        .into_iter()
+        .enumerate()
+        .map(|(index, page)| upload_page(gpu.as_ref(), name, index, page))
+        .collect();
+
+    SpriteSheet {
+        pages,
+        table: packed
+            .table
+            .into_iter()
+            .map(|entry| Slot {
+                generation: 0,
+                entry: Some(entry),
+            })
+            .collect(),
+        free_slots: Vec::new(),
+        names: packed.names,
+        id,
+        revision: 0,
+        name: name.to_owned(),
+        gpu,
+    }
+}
+
+fn upload_page(gpu: Option<&Gpu>, name: &str, index: usize, mut page: PackedPage) -> SheetPage {
+    let max_width = limits(gpu).max_texture_dimension_2d;
+    let width = page.pixel_count.clamp(1, max_width);
+    let height = page.pixel_count.div_ceil(max_width).max(1);
+    info!("Sprite sheet {name:?} page {index} dimensions are: {width}x{height}");
+
+    // Pad the texture data to match the exact dimensions of the texture.
+    // The padding is left free for sprites inserted at runtime.
+    let padding = (width * height - page.pixel_count) as usize;
+    page.data.extend(std::iter::repeat_n(0, padding * 4));
+
+    SheetPage {
+        texture: gpu.map(|gpu| create_page_texture(gpu, name, (width, height), &page.data)),
+        width,
+        free: free_list(page.pixel_count..width * height),
+        data: page.data,
+    }
+}
+
+fn create_page_texture(
+    gpu: &Gpu,
+    name: &str,
+    (width, height): (u32, u32),
+    data: &[u8],
+) -> PageTexture {
+    let device = &gpu.context.device;
+    let texture = device.create_texture_with_data(
+        &gpu.context.queue,
+        &wgpu::TextureDescriptor {
+            label: Some(name),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
             },
-            wgpu::util::TextureDataOrder::default(),
-            self.data.as_ref(),
-        );
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[wgpu::TextureFormat::Rgba8UnormSrgb],
+        },
+        wgpu::util::TextureDataOrder::default(),
+        data,
+    );
+
+    bind_page_texture(gpu, name, Rc::new(texture))
+}
 
-        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+fn bind_page_texture(gpu: &Gpu, name: &str, texture: Rc<wgpu::Texture>) -> PageTexture {
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some(self.name),
-            layout: self.layout,
+    let bind_group = gpu
+        .context
+        .device
+        .create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(name),
+            layout: &gpu.layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
-                resource: wgpu::BindingResource::TextureView(&texture_view),
+                resource: wgpu::BindingResource::TextureView(&view),
             }],
         });
 
-        SpriteSheet {
-            _texture: texture,
-            _texture_view: texture_view,
-            bind_group,
-            table: self.table.into_boxed_slice(),
+    PageTexture {
+        texture,
+        _view: view,
+        bind_group,
+    }
+}
+
+/// A sheet with a single sprite covering a whole texture, which render targets are drawn through.
+/// The texture is exactly as wide as the sprite, so the addresses of its pixels are also texel
+/// coordinates, and the sprite shader reads it like any other page.
+/// Without a texture, the sprite is drawn from the page's copy in memory.
+pub(crate) fn texture_sheet(
+    gpu: Option<(Gpu, Rc<wgpu::Texture>)>,
+    name: &str,
+    (width, height): (NonZeroU32, NonZeroU32),
+) -> (SpriteSheet, SpriteHandle) {
+    let id = SheetId::next();
+    let page = SheetPage {
+        texture: gpu
+            .as_ref()
+            .map(|(gpu, texture)| bind_page_texture(gpu, name, texture.clone())),
+        width: width.get(),
+        data: vec![0; (width.get() * height.get()) as usize * 4],
+        free: Vec::new(),
+    };
+    let sheet = SpriteSheet {
+        pages: vec![page],
+        table: vec![Slot {
+            generation: 0,
+            entry: Some(SpriteEntry {
+                page: 0,
+                address: 0,
+                dimensions: (width, height),
+                offset: (0, 0),
+            }),
+        }],
+        free_slots: Vec::new(),
+        names: HashMap::new(),
+        id,
+        revision: 0,
+        name: name.to_owned(),
+        // The texture is only written by render passes, so the sheet never uploads anything.
+        gpu: None,
+    };
+    let handle = SpriteHandle {
+        sheet: id,
+        index: 0,
+        generation: 0,
+    };
+    (sheet, handle)
+}
+
+/// A finished sprite sheet that hasn't been uploaded to the GPU yet.
+///
+/// Packing and serializing a sheet ahead of time lets the game skip
+/// decoding and packing its sprites on every start.
+/// The file format is little-endian and versioned:
+///
+/// ```text
+/// magic "BTSS", version, page count, entry count, name count  (u32 each)
+/// entries: page, address, width, height (u32), offset x, offset y (i32)
+/// names:   byte length (u32), UTF-8 name, first sprite, frame count (u32)
+/// pages:   pixel count (u32), pixel count * 4 bytes of Rgba8UnormSrgb in 1D sheet order
+/// ```
+pub struct PackedSpriteSheet {
+    table: Vec<SpriteEntry>,
+    names: HashMap<String, Range<usize>>,
+    pages: Vec<PackedPage>,
+}
+
+#[derive(Default)]
+struct PackedPage {
+    /// Current amount of pixels.
+    pixel_count: u32,
+
+    /// Image data.
+    ///
+    /// Sprite image data is __one-dimensional__.
+    /// Given a sprite `address` and a coordinate `(x, y)`,
+    /// the sampling coordinate can be computed by `address + x + y * width`.
+    ///
+    /// We lose linear filtering this way,
+    /// but we get to avoid the 2D box packing problem.
+    /// We aren't interested in linear filtering anyway.
+    data: Vec<u8>,
+}
+
+const SHEET_MAGIC: &[u8; 4] = b"BTSS";
+const SHEET_VERSION: u32 = 2;
+
+impl PackedSpriteSheet {
+    /// Serialize the sheet.
+    pub fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        writer.write_all(SHEET_MAGIC)?;
+        for value in [
+            SHEET_VERSION,
+            self.pages.len() as u32,
+            self.table.len() as u32,
+            self.names.len() as u32,
+        ] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for entry in &self.table {
+            writer.write_all(&entry.page.to_le_bytes())?;
+            writer.write_all(&entry.address.to_le_bytes())?;
+            writer.write_all(&entry.dimensions.0.get().to_le_bytes())?;
+            writer.write_all(&entry.dimensions.1.get().to_le_bytes())?;
+            writer.write_all(&entry.offset.0.to_le_bytes())?;
+            writer.write_all(&entry.offset.1.to_le_bytes())?;
+        }
+        for (name, range) in &self.names {
+            writer.write_all(&(name.len() as u32).to_le_bytes())?;
+            writer.write_all(name.as_bytes())?;
+            writer.write_all(&(range.start as u32).to_le_bytes())?;
+            writer.write_all(&(range.len() as u32).to_le_bytes())?;
+        }
+        for page in &self.pages {
+            writer.write_all(&page.pixel_count.to_le_bytes())?;
+            writer.write_all(&page.data)?;
+        }
+        Ok(())
+    }
+
+    /// Deserialize a sheet written by [PackedSpriteSheet::write].
+    pub fn read<R: Read>(mut reader: R) -> Result<Self, SheetFileError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != SHEET_MAGIC {
+            return Err(SheetFileError::Format);
+        }
+        let version = read_u32(&mut reader)?;
+        if version != SHEET_VERSION {
+            return Err(SheetFileError::Version(version));
+        }
+        let page_count = read_u32(&mut reader)?;
+        let entry_count = read_u32(&mut reader)?;
+        let name_count = read_u32(&mut reader)?;
+
+        let mut table = Vec::new();
+        for _ in 0..entry_count {
+            let page = read_u32(&mut reader)?;
+            let address = read_u32(&mut reader)?;
+            let width = NonZeroU32::new(read_u32(&mut reader)?);
+            let height = NonZeroU32::new(read_u32(&mut reader)?);
+            let offset = (read_u32(&mut reader)? as i32, read_u32(&mut reader)? as i32);
+            let (Some(width), Some(height)) = (width, height) else {
+                return Err(SheetFileError::Corrupt("sprite with zero size"));
+            };
+            if page >= page_count {
+                return Err(SheetFileError::Corrupt("sprite on a missing page"));
+            }
+            table.push(SpriteEntry {
+                page,
+                address,
+                dimensions: (width, height),
+                offset,
+            });
+        }
+
+        let mut names = HashMap::new();
+        for _ in 0..name_count {
+            let len = read_u32(&mut reader)?;
+            let name = read_bytes(&mut reader, len.into())?;
+            let name = String::from_utf8(name)
+                .map_err(|_| SheetFileError::Corrupt("name isn't valid UTF-8"))?;
+            let offset = read_u32(&mut reader)? as usize;
+            let frame_count = read_u32(&mut reader)? as usize;
+            if frame_count == 0 || offset + frame_count > table.len() {
+                return Err(SheetFileError::Corrupt("name refers to missing sprites"));
+            }
+            names.insert(name, offset..offset + frame_count);
+        }
+
+        let mut pages = Vec::new();
+        for _ in 0..page_count {
+            let pixel_count = read_u32(&mut reader)?;
+            let data = read_bytes(&mut reader, pixel_count as u64 * 4)?;
+            pages.push(PackedPage { pixel_count, data });
+        }
+
+        for entry in &table {
+            let pixels = entry.dimensions.0.get() as u64 * entry.dimensions.1.get() as u64;
+            if entry.address as u64 + pixels > pages[entry.page as usize].pixel_count as u64 {
+                return Err(SheetFileError::Corrupt("sprite outside of pixel data"));
+            }
+        }
+
+        Ok(Self {
+            table,
+            names,
+            pages,
+        })
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Read exactly `len` bytes.
+/// The buffer grows as bytes arrive, so a corrupt length can't allocate more than the input holds.
+fn read_bytes<R: Read>(reader: &mut R, len: u64) -> std::io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+#[derive(Debug)]
+pub enum SheetFileError {
+    Io(std::io::Error),
+    Format,
+    Version(u32),
+    Corrupt(&'static str),
+}
+
+impl From<std::io::Error> for SheetFileError {
+    fn from(err: std::io::Error) -> Self {
+        SheetFileError::Io(err)
+    }
+}
+
+impl std::fmt::Display for SheetFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SheetFileError::Io(err) => write!(f, "couldn't read sprite sheet: {err}"),
+            SheetFileError::Format => f.write_str("not a sprite sheet file"),
+            SheetFileError::Version(v) => write!(f, "unsupported sprite sheet version {v}"),
+            SheetFileError::Corrupt(err) => write!(f, "corrupt sprite sheet: {err}"),
         }
     }
 }
 
-struct SpriteEntry {
+pub(crate) struct SpriteEntry {
+    pub page: u32,
     address: u32,
     dimensions: (NonZeroU32, NonZeroU32),
     offset: (i32, i32),
 }
 
+impl SpriteEntry {
+    fn pixel_count(&self) -> u32 {
+        self.dimensions.0.get() * self.dimensions.1.get()
+    }
+}
+
 /// Sprite data for submitting to the sheet.
 pub struct SpriteData {
     /// Sprite width and height.
@@ -305,7 +1272,7 @@ pub struct SpriteData {
     /// Offset relative to drawing coordinate.
     /// If (0, 0), the bottom-left corner is fixed to the coordinate.
     offset: (i32, i32),
-    /// Sprite data, in Rgba8UnormSrgb, top-to-bottom left-to-right.
+    /// Sprite data, in premultiplied Rgba8UnormSrgb, top-to-bottom left-to-right.
     data: Vec<u8>,
     /// Number of pixels in sprite
     pixels: u32,
@@ -313,7 +1280,8 @@ pub struct SpriteData {
 
 impl SpriteData {
     /// Create a new sprite instance for submission to a sprite sheet.
-    /// `data is given in `Rgba8UnormSrgb`, and must match in size with `dimensions`.
+    /// `data` is given in `Rgba8UnormSrgb` with premultiplied alpha,
+    /// and must match in size with `dimensions`.
     pub fn new(dimensions: (u32, u32), offset: (i32, i32), data: Vec<u8>) -> Option<Self> {
         let dimensions = (
             NonZeroU32::new(dimensions.0)?,
@@ -331,19 +1299,103 @@ impl SpriteData {
             None
         }
     }
+
+    pub(crate) fn dimensions(&self) -> (u32, u32) {
+        (self.dimensions.0.get(), self.dimensions.1.get())
+    }
+
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Identifies a single [SpriteSheet], so several sheets can be drawn in one frame.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SheetId(u32);
+
+impl SheetId {
+    fn next() -> Self {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        SheetId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
 }
 
+/// Refers to a sprite in a specific [SpriteSheet].
+/// Handles to sprites that have been removed are detected and never drawn.
 #[derive(Clone, Copy)]
-pub struct SpriteHandle(usize);
+pub struct SpriteHandle {
+    sheet: SheetId,
+    index: usize,
+    generation: u32,
+}
+
+impl SpriteHandle {
+    /// The sheet that this sprite belongs to.
+    pub fn sheet(&self) -> SheetId {
+        self.sheet
+    }
+}
 
 #[derive(Clone, Copy)]
 pub struct AnimationHandle {
+    sheet: SheetId,
     offset: usize,
     frame_count: usize,
+    generation: u32,
 }
 
 impl AnimationHandle {
     pub fn get_frame(&self, time: usize) -> SpriteHandle {
-        SpriteHandle(self.offset + (time % self.frame_count))
+        SpriteHandle {
+            sheet: self.sheet,
+            index: self.offset + (time % self.frame_count),
+            generation: self.generation,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(capacity: u32) -> SheetPage {
+        SheetPage {
+            texture: None,
+            width: capacity,
+            data: vec![0; capacity as usize * 4],
+            free: free_list(0..capacity),
+        }
+    }
+
+    #[test]
+    fn released_ranges_merge_with_their_neighbours() {
+        let mut page = page(16);
+        let addresses: Vec<_> = (0..4).map(|_| page.claim(4).unwrap()).collect();
+        assert_eq!(addresses, [0, 4, 8, 12]);
+        assert_eq!(page.claim(1), None);
+
+        page.release(0..4);
+        page.release(8..12);
+        assert_eq!(page.free, [0..4, 8..12]);
+        // Merges with the range before and after it.
+        page.release(4..8);
+        assert_eq!(page.free, free_list(0..12));
+        page.release(12..16);
+        assert_eq!(page.free, free_list(0..16));
+        assert_eq!(page.claim(16), Some(0));
+    }
+
+    #[test]
+    fn claims_take_the_first_range_that_fits() {
+        let mut page = page(16);
+        for _ in 0..4 {
+            page.claim(4);
+        }
+        page.release(0..4);
+        page.release(8..12);
+        assert_eq!(page.claim(2), Some(0));
+        assert_eq!(page.claim(4), Some(8));
+        assert_eq!(page.claim(2), Some(2));
+        assert!(page.free.is_empty());
     }
 }
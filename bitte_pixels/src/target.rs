@@ -0,0 +1,195 @@
+//! Off-screen canvases that scenes are rendered to.
+//!
+//! A [RenderTarget] is drawn like any other sprite, through a sheet of its own,
+//! which makes it useful for minimaps, picture-in-picture, reflections,
+//! or caching a static background that is expensive to draw.
+//! The target is the same as the canvas of the frame, with the Y-axis pointing up,
+//! so it comes out upright when drawn as a sprite.
+
+use std::{num::NonZeroU32, rc::Rc};
+use wgpu::util::DeviceExt;
+
+use super::{
+    sprite::{self, SpriteHandle, SpriteSheet},
+    RenderError, Size,
+};
+
+/// An off-screen canvas, drawn to with [crate::Renderer::render_to_target]
+/// or [crate::software::Renderer::render_to_target].
+///
+/// To draw the target in another scene, pass [RenderTarget::sheet] along with the other
+/// sprite sheets, and draw [RenderTarget::sprite].
+/// Its pixels are premultiplied, like those of every sprite.
+///
+/// Each renderer draws the target from what it rendered to it:
+/// the GPU renderer from the texture, and the software renderer from a copy in memory.
+pub struct RenderTarget {
+    /// Missing for targets that are only drawn by the software renderer.
+    pub(crate) canvas: Option<Canvas>,
+    pub(crate) sheet: SpriteSheet,
+    sprite: SpriteHandle,
+    size: Size,
+}
+
+impl RenderTarget {
+    /// Create a target on the GPU, or only in memory for the software renderer.
+    pub(crate) fn new(
+        gpu: Option<(&Rc<super::Context>, sprite::Gpu)>,
+        name: &str,
+        size: Size,
+    ) -> Result<Self, RenderError> {
+        let limits = gpu
+            .as_ref()
+            .map_or_else(super::limits, |(ctx, _)| ctx.limits.clone());
+        let dimensions = check_size("render target", size, &limits)?;
+        let canvas = gpu.as_ref().map(|(ctx, _)| Canvas::new(ctx, name, size));
+        let texture = canvas.as_ref().map(|canvas| canvas.texture.clone());
+        let (sheet, sprite) =
+            sprite::texture_sheet(gpu.map(|(_, gpu)| gpu).zip(texture), name, dimensions);
+        Ok(Self {
+            canvas,
+            sheet,
+            sprite,
+            size,
+        })
+    }
+
+    /// Width and height in pixels.
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// The sprite covering the whole target.
+    pub fn sprite(&self) -> SpriteHandle {
+        self.sprite
+    }
+
+    /// The sheet that [RenderTarget::sprite] is drawn from.
+    pub fn sheet(&self) -> &SpriteSheet {
+        &self.sheet
+    }
+}
+
+/// Make sure a texture, described by `what` in errors, can be created at a size,
+/// on a device with `limits`.
+pub(crate) fn check_size(
+    what: &str,
+    size: Size,
+    limits: &wgpu::Limits,
+) -> Result<(NonZeroU32, NonZeroU32), RenderError> {
+    let max = limits.max_texture_dimension_2d;
+    match (NonZeroU32::new(size.width), NonZeroU32::new(size.height)) {
+        (Some(width), Some(height)) if width.get() <= max && height.get() <= max => {
+            Ok((width, height))
+        }
+        _ => Err(RenderError::Other(format!(
+            "{what} size {}x{} isn't between 1 and {max}",
+            size.width, size.height
+        ))),
+    }
+}
+
+/// A texture that can both be a target and source
+pub(crate) struct Canvas {
+    pub(crate) texture: Rc<wgpu::Texture>,
+    pub(crate) view: wgpu::TextureView,
+    pub(crate) size: Size,
+    _dimensions_buffer: wgpu::Buffer,
+    pub(crate) dimensions_bind_group: wgpu::BindGroup,
+}
+
+impl Canvas {
+    /// Make sure none of `sheets` is drawn from the canvas,
+    /// as a texture can't be drawn to while it is read from.
+    pub(crate) fn check_unread(&self, sheets: &[&SpriteSheet]) -> Result<(), RenderError> {
+        match sheets
+            .iter()
+            .any(|sheet| sheet.reads_texture(&self.texture))
+        {
+            true => Err(RenderError::Other(
+                "scene draws the render target it is rendered to".to_owned(),
+            )),
+            false => Ok(()),
+        }
+    }
+
+    pub(crate) fn new(ctx: &super::Context, name: &str, size: Size) -> Self {
+        let device = &ctx.device;
+        let dimensions_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(name),
+            contents: bytemuck::cast_slice(&[size.width, size.height, 0, 0]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let dimensions_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(name),
+            layout: &ctx.dimensions_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &dimensions_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            }],
+        });
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(name),
+            size: wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: ctx.color_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[ctx.color_format],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture: Rc::new(texture),
+            view,
+            size,
+            _dimensions_buffer: dimensions_buffer,
+            dimensions_bind_group,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Renderer;
+
+    #[test]
+    fn targets_cant_be_drawn_into_themselves() {
+        let size = Size {
+            width: 4,
+            height: 4,
+        };
+        let renderer = match pollster::block_on(Renderer::new_headless(size, None)) {
+            Ok(renderer) => renderer,
+            Err(RenderError::AcquireAdapter) => {
+                eprintln!("warning: no adapter available, skipping");
+                return;
+            }
+            Err(err) => panic!("couldn't create headless renderer: {err}"),
+        };
+        // `render_to_target` borrows the target mutably, so its sheet can only be passed along
+        // through another reference to the same texture. The check itself is tested instead.
+        let target = renderer.create_render_target("target", size).unwrap();
+        let other = renderer.create_render_target("other", size).unwrap();
+        let canvas = target.canvas.as_ref().unwrap();
+        assert!(canvas.check_unread(&[other.sheet()]).is_ok());
+        assert!(canvas
+            .check_unread(&[other.sheet(), target.sheet()])
+            .is_err());
+    }
+}
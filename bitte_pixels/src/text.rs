@@ -0,0 +1,479 @@
+//! Text drawn with bitmap fonts.
+//!
+//! A [Font] maps characters to glyph sprites, which are stored in an ordinary [SpriteSheet].
+//! Fonts can be assembled glyph by glyph, or cut from a grid image with [Font::from_grid].
+//! Text is laid out top to bottom, one line every [Font::line_height] pixels,
+//! and can be aligned and wrapped to a width in pixels.
+
+use std::{collections::HashMap, ops::Range, rc::Rc};
+
+use super::rich_text::RichTextInstance;
+use super::sprite::{
+    self, InstanceData, SheetPage, SpriteData, SpriteHandle, SpriteSheet, SpriteSheetBuilder,
+};
+use super::{blend, BlendMode, Color};
+
+const MAX_GLYPHS: u64 = 4096;
+
+/// Text to submit for drawing.
+#[derive(Clone)]
+pub struct Text<'a> {
+    pub font: &'a Font,
+    pub text: &'a str,
+    /// Position of the top of the first line.
+    /// Depending on `align`, this is the left edge, center, or right edge of each line.
+    pub position: [i32; 2],
+    /// Multiplied with the glyph pixels.
+    pub color: Color,
+    pub align: Align,
+    /// Width in pixels to wrap lines at, if any.
+    pub max_width: Option<u32>,
+}
+
+impl<'a> Text<'a> {
+    /// Left-aligned, unwrapped text in the font's own colors.
+    pub fn new(font: &'a Font, text: &'a str, position: [i32; 2]) -> Self {
+        Self {
+            font,
+            text,
+            position,
+            color: Color::WHITE,
+            align: Align::Left,
+            max_width: None,
+        }
+    }
+}
+
+/// Horizontal alignment of lines, relative to the text position.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// How glyphs are spaced when cut from a grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Spacing {
+    /// Every glyph advances by the cell width.
+    Fixed,
+    /// Empty columns on the right of each cell are trimmed,
+    /// and glyphs advance by their trimmed width plus the given number of pixels.
+    /// Empty cells, such as space, advance by half the cell width.
+    Variable(i32),
+}
+
+#[derive(Clone, Copy)]
+pub struct Glyph {
+    /// Sprite to draw with its bottom-left corner on the bottom of the line,
+    /// or `None` for whitespace.
+    pub sprite: Option<SpriteHandle>,
+    /// Distance to the next glyph in pixels.
+    pub advance: i32,
+}
+
+/// A line of laid out text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextLine {
+    /// Byte range of the line in the text, excluding the line break.
+    pub range: Range<usize>,
+    /// Width of the line in pixels.
+    pub width: u32,
+}
+
+/// A bitmap font, mapping characters to sprites.
+pub struct Font {
+    glyphs: HashMap<char, Glyph>,
+    kerning: HashMap<(char, char), i32>,
+    line_height: u32,
+    fallback: Option<char>,
+}
+
+impl Font {
+    /// Create a font without any glyphs.
+    pub fn new(line_height: u32) -> Self {
+        Self {
+            glyphs: HashMap::new(),
+            kerning: HashMap::new(),
+            line_height,
+            fallback: None,
+        }
+    }
+
+    /// Cut glyphs for `chars` out of a grid of equally sized cells, row by row from the top-left,
+    /// and add them to a sprite sheet.
+    /// `image` is given in `Rgba8UnormSrgb`, top-to-bottom left-to-right.
+    ///
+    /// Panics if the image doesn't have a cell for every character.
+    pub fn from_grid(
+        builder: &mut SpriteSheetBuilder,
+        image: &[u8],
+        image_width: u32,
+        cell_size: (u32, u32),
+        chars: &str,
+        spacing: Spacing,
+    ) -> Self {
+        let (cell_width, cell_height) = cell_size;
+        let columns = image_width / cell_width;
+        let rows = image.len() as u32 / 4 / image_width / cell_height;
+        assert!(
+            chars.chars().count() as u32 <= columns * rows,
+            "font image has too few cells"
+        );
+
+        let mut font = Font::new(cell_height);
+        for (i, c) in chars.chars().enumerate() {
+            let (column, row) = (i as u32 % columns, i as u32 / columns);
+            let pixel = |x: u32, y: u32| {
+                let index = ((row * cell_height + y) * image_width + column * cell_width + x) * 4;
+                &image[index as usize..index as usize + 4]
+            };
+
+            let width = match spacing {
+                Spacing::Fixed => cell_width,
+                Spacing::Variable(_) => (0..cell_width)
+                    .rev()
+                    .find(|&x| (0..cell_height).any(|y| pixel(x, y)[3] != 0))
+                    .map_or(0, |x| x + 1),
+            };
+            let empty = (0..width).all(|x| (0..cell_height).all(|y| pixel(x, y)[3] == 0));
+            let sprite = (!empty).then(|| {
+                let mut data = Vec::with_capacity((width * cell_height * 4) as usize);
+                for y in 0..cell_height {
+                    for x in 0..width {
+                        data.extend_from_slice(pixel(x, y));
+                    }
+                }
+                builder.add(SpriteData::new((width, cell_height), (0, 0), data).unwrap())
+            });
+
+            let advance = match spacing {
+                Spacing::Fixed => cell_width as i32,
+                Spacing::Variable(_) if empty => cell_width as i32 / 2,
+                Spacing::Variable(gap) => width as i32 + gap,
+            };
+            font.add_glyph(c, Glyph { sprite, advance });
+        }
+        font
+    }
+
+    pub fn add_glyph(&mut self, c: char, glyph: Glyph) {
+        self.glyphs.insert(c, glyph);
+    }
+
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs.get(&c)
+    }
+
+    /// Adjust the distance between two consecutive characters, in pixels.
+    pub fn set_kerning(&mut self, left: char, right: char, adjustment: i32) {
+        self.kerning.insert((left, right), adjustment);
+    }
+
+    pub fn kerning(&self, left: char, right: char) -> i32 {
+        self.kerning.get(&(left, right)).copied().unwrap_or(0)
+    }
+
+    /// Draw characters that the font has no glyph for as `c` instead.
+    pub fn set_fallback(&mut self, c: Option<char>) {
+        self.fallback = c;
+    }
+
+    /// Distance between lines in pixels.
+    pub fn line_height(&self) -> u32 {
+        self.line_height
+    }
+
+    /// Find the glyph to draw for a character, if any.
+    pub(crate) fn resolve(&self, c: char) -> Option<&Glyph> {
+        self.glyphs
+            .get(&c)
+            .or_else(|| self.glyphs.get(&self.fallback?))
+    }
+
+    /// Width of a single line of text in pixels.
+    pub fn width(&self, text: &str) -> u32 {
+        self.width_with(text, &|c| self.resolve(c).copied())
+    }
+
+    /// Split text into lines at line breaks,
+    /// and between words wherever a line would be wider than `max_width`.
+    /// Words that are wider than `max_width` on their own are split between characters.
+    pub fn lines(&self, text: &str, max_width: Option<u32>) -> Vec<TextLine> {
+        self.lines_with(text, max_width, &|c| self.resolve(c).copied())
+    }
+
+    /// Width and height of text in pixels, as it would be drawn.
+    pub fn measure(&self, text: &str, max_width: Option<u32>) -> (u32, u32) {
+        let lines = self.lines(text, max_width);
+        let width = lines.iter().map(|line| line.width).max().unwrap_or(0);
+        (width, lines.len() as u32 * self.line_height)
+    }
+
+    // Layout is parameterized over glyph lookup,
+    // so rich text can lay out inline icons along with the font's glyphs.
+
+    fn width_with(&self, text: &str, glyph: &dyn Fn(char) -> Option<Glyph>) -> u32 {
+        let mut width = 0;
+        let mut prev = None;
+        for c in text.chars() {
+            if let Some(prev) = prev {
+                width += self.kerning(prev, c);
+            }
+            width += glyph(c).map_or(0, |glyph| glyph.advance);
+            prev = Some(c);
+        }
+        width.max(0) as u32
+    }
+
+    pub(crate) fn lines_with(
+        &self,
+        text: &str,
+        max_width: Option<u32>,
+        glyph: &dyn Fn(char) -> Option<Glyph>,
+    ) -> Vec<TextLine> {
+        let mut lines = Vec::new();
+        let mut paragraph_start = 0;
+        for paragraph in text.split('\n') {
+            let end = paragraph_start + paragraph.len();
+            match max_width {
+                None => lines.push(self.line(text, paragraph_start..end, glyph)),
+                Some(max_width) => {
+                    self.wrap(text, paragraph_start..end, max_width, glyph, &mut lines)
+                }
+            }
+            paragraph_start = end + 1;
+        }
+        lines
+    }
+
+    fn line(
+        &self,
+        text: &str,
+        range: Range<usize>,
+        glyph: &dyn Fn(char) -> Option<Glyph>,
+    ) -> TextLine {
+        let width = self.width_with(text[range.clone()].trim_end_matches(' '), glyph);
+        TextLine { range, width }
+    }
+
+    fn wrap(
+        &self,
+        text: &str,
+        paragraph: Range<usize>,
+        max_width: u32,
+        glyph: &dyn Fn(char) -> Option<Glyph>,
+        lines: &mut Vec<TextLine>,
+    ) {
+        let width = |range: Range<usize>| self.width_with(&text[range], glyph);
+        let mut line_start = paragraph.start;
+        let mut line_end = paragraph.start;
+        let mut word_start = paragraph.start;
+        for word in text[paragraph.clone()].split(' ') {
+            let word_end = word_start + word.len();
+            if !word.is_empty() {
+                if line_end > line_start && width(line_start..word_end) > max_width {
+                    lines.push(self.line(text, line_start..line_end, glyph));
+                    line_start = word_start;
+                }
+                // Split words that don't fit on a line of their own.
+                while width(line_start..word_end) > max_width {
+                    let split = text[line_start..word_end]
+                        .char_indices()
+                        .skip(1)
+                        .map(|(i, _)| line_start + i)
+                        .take_while(|&i| width(line_start..i) <= max_width)
+                        .last()
+                        .unwrap_or_else(|| {
+                            line_start + text[line_start..].chars().next().unwrap().len_utf8()
+                        });
+                    if split == word_end {
+                        break;
+                    }
+                    lines.push(self.line(text, line_start..split, glyph));
+                    line_start = split;
+                }
+                line_end = word_end;
+            }
+            word_start = word_end + 1;
+        }
+        lines.push(self.line(text, line_start..paragraph.end, glyph));
+    }
+
+    /// Position every character of `text` that has a glyph,
+    /// passing its byte index, glyph and position on the canvas to `place`.
+    pub(crate) fn layout_with(
+        &self,
+        text: &str,
+        position: [i32; 2],
+        align: Align,
+        max_width: Option<u32>,
+        glyph: &dyn Fn(char) -> Option<Glyph>,
+        mut place: impl FnMut(usize, Glyph, [i32; 2]),
+    ) {
+        for (i, line) in self.lines_with(text, max_width, glyph).iter().enumerate() {
+            let mut x = position[0]
+                - match align {
+                    Align::Left => 0,
+                    Align::Center => line.width as i32 / 2,
+                    Align::Right => line.width as i32,
+                };
+            let y = position[1] - (i as i32 + 1) * self.line_height as i32;
+            let mut prev = None;
+            for (index, c) in text[line.range.clone()].char_indices() {
+                if let Some(prev) = prev {
+                    x += self.kerning(prev, c);
+                }
+                if let Some(glyph) = glyph(c) {
+                    place(line.range.start + index, glyph, [x, y]);
+                    x += glyph.advance;
+                }
+                prev = Some(c);
+            }
+        }
+    }
+
+    /// Position every visible glyph of `text`.
+    pub(crate) fn layout(&self, text: &Text, mut place: impl FnMut(SpriteHandle, [i32; 2])) {
+        let glyph = |c| self.resolve(c).copied();
+        self.layout_with(
+            text.text,
+            text.position,
+            text.align,
+            text.max_width,
+            &glyph,
+            |_, glyph, position| {
+                if let Some(sprite) = glyph.sprite {
+                    place(sprite, position);
+                }
+            },
+        );
+    }
+}
+
+pub(crate) struct Renderer {
+    ctx: Rc<super::Context>,
+    pub(crate) pipelines: blend::Pipelines,
+    instance_buffer: wgpu::Buffer,
+}
+
+impl Renderer {
+    pub(crate) fn new(
+        ctx: Rc<super::Context>,
+        sprite_sheet_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let device = &ctx.device;
+
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("glyph instances"),
+            size: MAX_GLYPHS * std::mem::size_of::<InstanceData>() as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("text"),
+            bind_group_layouts: &[&ctx.dimensions_layout, sprite_sheet_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipelines = blend::Pipelines::new(ctx.shaders.clone(), {
+            let ctx = ctx.clone();
+            move |module, blend| create_pipeline(&ctx, &pipeline_layout, module, blend)
+        });
+
+        Self {
+            ctx,
+            pipelines,
+            instance_buffer,
+        }
+    }
+
+    /// Draw text, leaving out glyphs whose sheet isn't among `sprite_sheets`.
+    pub(crate) fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        sprite_sheets: &[&'a SpriteSheet],
+        texts: &[Text],
+        rich_texts: &[RichTextInstance],
+    ) {
+        let mut batches: Vec<(&'a SheetPage, Range<u32>)> = Vec::new();
+        let mut instances: Vec<InstanceData> = Vec::new();
+        let mut push = |sprite, position, color| {
+            let Ok((page, entry)) = sprite::lookup(sprite_sheets, sprite) else {
+                return;
+            };
+            let start = batches.last().map_or(0, |(_, range)| range.end);
+            match batches.last_mut() {
+                Some((last, range)) if std::ptr::eq(*last, page) => range.end += 1,
+                _ => batches.push((page, start..start + 1)),
+            }
+            instances.push(InstanceData::new(entry, position).with_color(color));
+        };
+        for text in texts {
+            text.font
+                .layout(text, |sprite, position| push(sprite, position, text.color));
+        }
+        for text in rich_texts {
+            for (sprite, position, color) in text.glyphs() {
+                push(sprite, position, color);
+            }
+        }
+        if instances.len() as u64 > MAX_GLYPHS {
+            warn!("Only the first {MAX_GLYPHS} glyphs are drawn");
+            instances.truncate(MAX_GLYPHS as usize);
+        }
+        self.ctx.queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(instances.as_slice()),
+        );
+
+        render_pass.set_pipeline(self.pipelines.get(BlendMode::Alpha));
+        render_pass.set_vertex_buffer(0, self.ctx.quad_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        for (page, range) in batches {
+            let range = range.start.min(MAX_GLYPHS as u32)..range.end.min(MAX_GLYPHS as u32);
+            let Some(bind_group) = page.bind_group() else {
+                continue;
+            };
+            render_pass.set_bind_group(1, bind_group, &[]);
+            render_pass.draw(0..super::QUAD_VERTICES.len() as u32, range);
+        }
+    }
+}
+
+fn create_pipeline(
+    ctx: &super::Context,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    blend: wgpu::BlendState,
+) -> wgpu::RenderPipeline {
+    ctx.device
+        .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("text"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module,
+                entry_point: "sprite_v",
+                buffers: &[super::QUAD_LAYOUT, sprite::INSTANCE_LAYOUT],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module,
+                entry_point: "sprite_f",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: ctx.color_format,
+                    blend: Some(blend),
+                    write_mask: wgpu::ColorWrites::all(),
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            multisample: wgpu::MultisampleState::default(),
+            depth_stencil: None,
+            multiview: None,
+        })
+}
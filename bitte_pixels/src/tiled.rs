@@ -0,0 +1,987 @@
+//! Import of maps made with the [Tiled](https://www.mapeditor.org/) editor.
+//!
+//! Both the XML (`.tmx`/`.tsx`) and JSON (`.tmj`/`.tsj`) formats are supported,
+//! for orthogonal, finite maps.
+//! Tiles that are used by the map are cut out of the tileset images
+//! and added to a [SpriteSheetBuilder],
+//! while object layers are exposed as plain data.
+//!
+//! ```ignore
+//! let mut builder = renderer.create_sprite_sheet_builder("level");
+//! let map = tiled::load_map("assets/level.tmx", &mut builder)?;
+//! let sheet = builder.build();
+//! let tilemap = map.create_tilemap(&renderer);
+//! ```
+//!
+//! All coordinates are converted to the canvas convention,
+//! with the origin in the bottom-left corner of the map and the Y-axis pointing up.
+
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use base64::Engine;
+use serde_json::Value;
+
+use super::{
+    sprite::{SpriteData, SpriteHandle, SpriteSheetBuilder},
+    tilemap::Tilemap,
+    Color, Renderer,
+};
+
+const FLIP_HORIZONTAL: u32 = 0x8000_0000;
+const FLIP_VERTICAL: u32 = 0x4000_0000;
+const FLIP_DIAGONAL: u32 = 0x2000_0000;
+const GID_MASK: u32 = 0x0fff_ffff;
+
+/// A map loaded with [load_map].
+pub struct TiledMap {
+    /// Width and height in tiles.
+    pub size: (u32, u32),
+    /// Width and height of a tile in pixels.
+    pub tile_size: (u32, u32),
+    /// Tile layers in drawing order.
+    pub tile_layers: Vec<TileLayer>,
+    /// Object layers in file order.
+    pub object_layers: Vec<ObjectLayer>,
+    pub properties: Properties,
+}
+
+pub struct TileLayer {
+    pub name: String,
+    /// Tiles row by row, starting with the bottom row.
+    pub tiles: Vec<Option<SpriteHandle>>,
+    pub properties: Properties,
+}
+
+pub struct ObjectLayer {
+    pub name: String,
+    pub objects: Vec<MapObject>,
+    pub properties: Properties,
+}
+
+/// Spawn points, triggers, and other objects placed on the map.
+pub struct MapObject {
+    pub id: u32,
+    pub name: String,
+    /// The object's class, called type in older versions of Tiled.
+    pub class: String,
+    /// Position of the bottom-left corner in pixels.
+    /// For points and polygons, this is the origin that the points are relative to.
+    pub position: [f32; 2],
+    /// Width and height in pixels.
+    pub size: [f32; 2],
+    /// Clockwise rotation in degrees, around the object's top-left corner as placed in Tiled.
+    pub rotation: f32,
+    pub shape: ObjectShape,
+    pub properties: Properties,
+}
+
+pub enum ObjectShape {
+    Rectangle,
+    Ellipse,
+    Point,
+    /// Points relative to the object position.
+    Polygon(Vec<[f32; 2]>),
+    /// Points relative to the object position.
+    Polyline(Vec<[f32; 2]>),
+    /// A tile placed as an object.
+    Tile(SpriteHandle),
+}
+
+pub type Properties = HashMap<String, PropertyValue>;
+
+/// Custom properties of maps, layers and objects.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PropertyValue {
+    String(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    /// Color as written by Tiled, in `#AARRGGBB` format.
+    Color(String),
+    /// Path relative to the file that the property was read from.
+    File(String),
+    /// ID of an object on the map.
+    Object(u32),
+    /// Nested properties of a custom class.
+    Class(Properties),
+}
+
+#[derive(Debug)]
+pub enum TiledError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, String),
+    Image(PathBuf, String),
+    Invalid(String),
+    Unsupported(&'static str),
+}
+
+impl std::fmt::Display for TiledError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TiledError::Io(path, err) => write!(f, "couldn't read {path:?}: {err}"),
+            TiledError::Parse(path, err) => write!(f, "couldn't parse {path:?}: {err}"),
+            TiledError::Image(path, err) => write!(f, "couldn't decode image {path:?}: {err}"),
+            TiledError::Invalid(err) => write!(f, "invalid map: {err}"),
+            TiledError::Unsupported(feature) => write!(f, "unsupported map feature: {feature}"),
+        }
+    }
+}
+
+impl TiledMap {
+    /// Create a tilemap with a layer for each tile layer of the map.
+    pub fn create_tilemap(&self, renderer: &Renderer) -> Tilemap {
+        let mut tilemap =
+            renderer.create_tilemap(self.size, self.tile_size, self.tile_layers.len());
+        for (layer, tile_layer) in self.tile_layers.iter().enumerate() {
+            for (i, tile) in tile_layer.tiles.iter().enumerate() {
+                if tile.is_some() {
+                    let (x, y) = (i as u32 % self.size.0, i as u32 / self.size.0);
+                    tilemap.set_tile(layer, x, y, *tile);
+                }
+            }
+        }
+        tilemap
+    }
+
+    pub fn tile_layer(&self, name: &str) -> Option<&TileLayer> {
+        self.tile_layers.iter().find(|layer| layer.name == name)
+    }
+
+    pub fn object_layer(&self, name: &str) -> Option<&ObjectLayer> {
+        self.object_layers.iter().find(|layer| layer.name == name)
+    }
+}
+
+/// Load a `.tmx` or `.tmj` map, adding the tiles that it uses to `builder`.
+/// The format is chosen by file extension, defaulting to XML.
+pub fn load_map(
+    path: impl AsRef<Path>,
+    builder: &mut SpriteSheetBuilder,
+) -> Result<TiledMap, TiledError> {
+    let path = path.as_ref();
+    let source = read_file(path)?;
+    let raw = if is_json(path) {
+        json::parse_map(path, &source)?
+    } else {
+        xml::parse_map(path, &source)?
+    };
+    Importer::new(raw).import(builder)
+}
+
+fn is_json(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("tmj" | "tsj" | "json")
+    )
+}
+
+fn read_file(path: &Path) -> Result<String, TiledError> {
+    std::fs::read_to_string(path).map_err(|err| TiledError::Io(path.to_owned(), err))
+}
+
+fn invalid(err: impl Into<String>) -> TiledError {
+    TiledError::Invalid(err.into())
+}
+
+/// A map as read from either format, before tiles are resolved to sprites.
+struct RawMap {
+    size: (u32, u32),
+    tile_size: (u32, u32),
+    tilesets: Vec<RawTileset>,
+    layers: Vec<RawLayer>,
+    properties: Properties,
+}
+
+struct RawTileset {
+    first_gid: u32,
+    tile_size: (u32, u32),
+    margin: u32,
+    spacing: u32,
+    columns: u32,
+    /// Offset of tiles in pixels, with the Y-axis pointing down.
+    offset: (i32, i32),
+    /// Image of the whole tileset, or `None` for image collections.
+    image: Option<RawImage>,
+    /// Images of individual tiles in image collections.
+    tile_images: HashMap<u32, RawImage>,
+}
+
+struct RawImage {
+    path: PathBuf,
+    /// Color that should be made transparent, in `RRGGBB` format.
+    transparent: Option<String>,
+}
+
+enum RawLayer {
+    Tiles {
+        name: String,
+        /// Global tile IDs, row by row from the top.
+        gids: Vec<u32>,
+        properties: Properties,
+    },
+    Objects {
+        name: String,
+        objects: Vec<RawObject>,
+        properties: Properties,
+    },
+}
+
+struct RawObject {
+    id: u32,
+    name: String,
+    class: String,
+    /// Position in Tiled coordinates, with the Y-axis pointing down.
+    position: [f32; 2],
+    size: [f32; 2],
+    rotation: f32,
+    shape: RawShape,
+    properties: Properties,
+}
+
+enum RawShape {
+    Rectangle,
+    Ellipse,
+    Point,
+    Polygon(Vec<[f32; 2]>),
+    Polyline(Vec<[f32; 2]>),
+    Tile(u32),
+}
+
+/// Decode the contents of a `<data>` element or a base64 `data` string.
+fn decode_tile_data(
+    text: &str,
+    encoding: Option<&str>,
+    compression: Option<&str>,
+) -> Result<Vec<u32>, TiledError> {
+    match encoding {
+        Some("csv") => text
+            .split(',')
+            .map(|gid| gid.trim().parse().map_err(|_| invalid("bad CSV tile data")))
+            .collect(),
+        Some("base64") => {
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(text.trim())
+                .map_err(|err| invalid(format!("bad base64 tile data: {err}")))?;
+            let mut data = Vec::new();
+            let result = match compression {
+                None | Some("") => {
+                    data = bytes;
+                    Ok(0)
+                }
+                Some("zlib") => {
+                    flate2::read::ZlibDecoder::new(bytes.as_slice()).read_to_end(&mut data)
+                }
+                Some("gzip") => {
+                    flate2::read::GzDecoder::new(bytes.as_slice()).read_to_end(&mut data)
+                }
+                Some(_) => return Err(TiledError::Unsupported("tile data compression")),
+            };
+            result.map_err(|err| invalid(format!("bad compressed tile data: {err}")))?;
+            Ok(data
+                .chunks_exact(4)
+                .map(|gid| u32::from_le_bytes(gid.try_into().unwrap()))
+                .collect())
+        }
+        _ => Err(TiledError::Unsupported("tile data encoding")),
+    }
+}
+
+mod xml {
+    use super::*;
+    use roxmltree::{Document, Node};
+
+    fn attr<T: std::str::FromStr>(node: Node, name: &str) -> Result<Option<T>, TiledError> {
+        node.attribute(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| invalid(format!("bad value for {name:?}: {value:?}")))
+            })
+            .transpose()
+    }
+
+    fn required<T: std::str::FromStr>(node: Node, name: &str) -> Result<T, TiledError> {
+        attr(node, name)?.ok_or_else(|| invalid(format!("missing attribute {name:?}")))
+    }
+
+    fn string(node: Node, name: &str) -> String {
+        node.attribute(name).unwrap_or_default().to_owned()
+    }
+
+    fn child<'a, 'i>(node: Node<'a, 'i>, name: &str) -> Option<Node<'a, 'i>> {
+        node.children().find(|child| child.has_tag_name(name))
+    }
+
+    fn parse_document<'i>(path: &Path, source: &'i str) -> Result<Document<'i>, TiledError> {
+        Document::parse(source).map_err(|err| TiledError::Parse(path.to_owned(), err.to_string()))
+    }
+
+    pub(super) fn parse_map(path: &Path, source: &str) -> Result<RawMap, TiledError> {
+        let document = parse_document(path, source)?;
+        let map = document.root_element();
+        if !map.has_tag_name("map") {
+            return Err(invalid("root element isn't a map"));
+        }
+        if map.attribute("orientation").unwrap_or("orthogonal") != "orthogonal" {
+            return Err(TiledError::Unsupported("non-orthogonal maps"));
+        }
+        if attr(map, "infinite")?.unwrap_or(0) != 0 {
+            return Err(TiledError::Unsupported("infinite maps"));
+        }
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut tilesets = Vec::new();
+        for tileset in map.children().filter(|n| n.has_tag_name("tileset")) {
+            let first_gid = required(tileset, "firstgid")?;
+            tilesets.push(match tileset.attribute("source") {
+                Some(source) => load_tileset(&dir.join(source), first_gid)?,
+                None => parse_tileset(tileset, dir, first_gid)?,
+            });
+        }
+
+        let mut layers = Vec::new();
+        parse_layers(map, &mut layers)?;
+
+        Ok(RawMap {
+            size: (required(map, "width")?, required(map, "height")?),
+            tile_size: (required(map, "tilewidth")?, required(map, "tileheight")?),
+            tilesets,
+            layers,
+            properties: parse_properties(map)?,
+        })
+    }
+
+    /// Load an external `.tsx` or `.tsj` tileset.
+    pub(super) fn load_tileset(path: &Path, first_gid: u32) -> Result<RawTileset, TiledError> {
+        let source = read_file(path)?;
+        if is_json(path) {
+            return json::parse_external_tileset(path, &source, first_gid);
+        }
+        let document = parse_document(path, &source)?;
+        parse_tileset(
+            document.root_element(),
+            path.parent().unwrap_or(Path::new("")),
+            first_gid,
+        )
+    }
+
+    fn parse_image(node: Node, dir: &Path) -> Result<RawImage, TiledError> {
+        Ok(RawImage {
+            path: dir.join(
+                node.attribute("source")
+                    .ok_or_else(|| invalid("image without source"))?,
+            ),
+            transparent: node.attribute("trans").map(str::to_owned),
+        })
+    }
+
+    fn parse_tileset(node: Node, dir: &Path, first_gid: u32) -> Result<RawTileset, TiledError> {
+        let mut tile_images = HashMap::new();
+        for tile in node.children().filter(|n| n.has_tag_name("tile")) {
+            if let Some(image) = child(tile, "image") {
+                tile_images.insert(required(tile, "id")?, parse_image(image, dir)?);
+            }
+        }
+        let offset = match child(node, "tileoffset") {
+            Some(offset) => (
+                attr(offset, "x")?.unwrap_or(0),
+                attr(offset, "y")?.unwrap_or(0),
+            ),
+            None => (0, 0),
+        };
+        Ok(RawTileset {
+            first_gid,
+            tile_size: (required(node, "tilewidth")?, required(node, "tileheight")?),
+            margin: attr(node, "margin")?.unwrap_or(0),
+            spacing: attr(node, "spacing")?.unwrap_or(0),
+            columns: attr(node, "columns")?.unwrap_or(0),
+            offset,
+            image: child(node, "image")
+                .map(|image| parse_image(image, dir))
+                .transpose()?,
+            tile_images,
+        })
+    }
+
+    fn parse_layers(parent: Node, layers: &mut Vec<RawLayer>) -> Result<(), TiledError> {
+        for node in parent.children().filter(Node::is_element) {
+            match node.tag_name().name() {
+                "layer" => {
+                    let data = child(node, "data").ok_or_else(|| invalid("layer without data"))?;
+                    if child(data, "chunk").is_some() {
+                        return Err(TiledError::Unsupported("infinite maps"));
+                    }
+                    let gids = match data.attribute("encoding") {
+                        None => data
+                            .children()
+                            .filter(|n| n.has_tag_name("tile"))
+                            .map(|tile| Ok(attr(tile, "gid")?.unwrap_or(0)))
+                            .collect::<Result<_, TiledError>>()?,
+                        encoding => decode_tile_data(
+                            data.text().unwrap_or_default(),
+                            encoding,
+                            data.attribute("compression"),
+                        )?,
+                    };
+                    layers.push(RawLayer::Tiles {
+                        name: string(node, "name"),
+                        gids,
+                        properties: parse_properties(node)?,
+                    });
+                }
+                "objectgroup" => {
+                    let objects = node
+                        .children()
+                        .filter(|n| n.has_tag_name("object"))
+                        .map(parse_object)
+                        .collect::<Result<_, _>>()?;
+                    layers.push(RawLayer::Objects {
+                        name: string(node, "name"),
+                        objects,
+                        properties: parse_properties(node)?,
+                    });
+                }
+                "group" => parse_layers(node, layers)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_points(node: Node) -> Result<Vec<[f32; 2]>, TiledError> {
+        string(node, "points")
+            .split_whitespace()
+            .map(|point| {
+                let (x, y) = point.split_once(',').ok_or_else(|| invalid("bad point"))?;
+                let parse = |v: &str| v.parse::<f32>().map_err(|_| invalid("bad point"));
+                Ok([parse(x)?, parse(y)?])
+            })
+            .collect()
+    }
+
+    fn parse_object(node: Node) -> Result<RawObject, TiledError> {
+        let shape = if let Some(gid) = attr(node, "gid")? {
+            RawShape::Tile(gid)
+        } else if child(node, "ellipse").is_some() {
+            RawShape::Ellipse
+        } else if child(node, "point").is_some() {
+            RawShape::Point
+        } else if let Some(polygon) = child(node, "polygon") {
+            RawShape::Polygon(parse_points(polygon)?)
+        } else if let Some(polyline) = child(node, "polyline") {
+            RawShape::Polyline(parse_points(polyline)?)
+        } else {
+            RawShape::Rectangle
+        };
+        let class = node.attribute("class").or(node.attribute("type"));
+        Ok(RawObject {
+            id: attr(node, "id")?.unwrap_or(0),
+            name: string(node, "name"),
+            class: class.unwrap_or_default().to_owned(),
+            position: [
+                attr(node, "x")?.unwrap_or(0.),
+                attr(node, "y")?.unwrap_or(0.),
+            ],
+            size: [
+                attr(node, "width")?.unwrap_or(0.),
+                attr(node, "height")?.unwrap_or(0.),
+            ],
+            rotation: attr(node, "rotation")?.unwrap_or(0.),
+            shape,
+            properties: parse_properties(node)?,
+        })
+    }
+
+    fn parse_properties(node: Node) -> Result<Properties, TiledError> {
+        let mut properties = Properties::new();
+        let Some(list) = child(node, "properties") else {
+            return Ok(properties);
+        };
+        for property in list.children().filter(|n| n.has_tag_name("property")) {
+            let text = property
+                .attribute("value")
+                .or(property.text())
+                .unwrap_or_default();
+            let bad = || invalid(format!("bad property value {text:?}"));
+            let value = match property.attribute("type").unwrap_or("string") {
+                "int" => PropertyValue::Int(text.parse().map_err(|_| bad())?),
+                "float" => PropertyValue::Float(text.parse().map_err(|_| bad())?),
+                "bool" => PropertyValue::Bool(text.parse().map_err(|_| bad())?),
+                "color" => PropertyValue::Color(text.to_owned()),
+                "file" => PropertyValue::File(text.to_owned()),
+                "object" => PropertyValue::Object(text.parse().map_err(|_| bad())?),
+                "class" => PropertyValue::Class(parse_properties(property)?),
+                _ => PropertyValue::String(text.to_owned()),
+            };
+            properties.insert(string(property, "name"), value);
+        }
+        Ok(properties)
+    }
+}
+
+mod json {
+    use super::*;
+
+    fn parse_value(path: &Path, source: &str) -> Result<Value, TiledError> {
+        serde_json::from_str(source)
+            .map_err(|err| TiledError::Parse(path.to_owned(), err.to_string()))
+    }
+
+    fn uint(value: &Value, name: &str) -> Result<u32, TiledError> {
+        value[name]
+            .as_u64()
+            .map(|v| v as u32)
+            .ok_or_else(|| invalid(format!("missing or bad field {name:?}")))
+    }
+
+    fn float(value: &Value, name: &str) -> f32 {
+        value[name].as_f64().unwrap_or(0.) as f32
+    }
+
+    fn string(value: &Value, name: &str) -> String {
+        value[name].as_str().unwrap_or_default().to_owned()
+    }
+
+    pub(super) fn parse_map(path: &Path, source: &str) -> Result<RawMap, TiledError> {
+        let map = parse_value(path, source)?;
+        if map["orientation"].as_str().unwrap_or("orthogonal") != "orthogonal" {
+            return Err(TiledError::Unsupported("non-orthogonal maps"));
+        }
+        if map["infinite"].as_bool().unwrap_or(false) {
+            return Err(TiledError::Unsupported("infinite maps"));
+        }
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut tilesets = Vec::new();
+        for tileset in map["tilesets"].as_array().into_iter().flatten() {
+            let first_gid = uint(tileset, "firstgid")?;
+            tilesets.push(match tileset["source"].as_str() {
+                Some(source) => xml::load_tileset(&dir.join(source), first_gid)?,
+                None => parse_tileset(tileset, dir, first_gid)?,
+            });
+        }
+
+        let mut layers = Vec::new();
+        parse_layers(&map["layers"], &mut layers)?;
+
+        Ok(RawMap {
+            size: (uint(&map, "width")?, uint(&map, "height")?),
+            tile_size: (uint(&map, "tilewidth")?, uint(&map, "tileheight")?),
+            tilesets,
+            layers,
+            properties: parse_properties(&map["properties"])?,
+        })
+    }
+
+    pub(super) fn parse_external_tileset(
+        path: &Path,
+        source: &str,
+        first_gid: u32,
+    ) -> Result<RawTileset, TiledError> {
+        let tileset = parse_value(path, source)?;
+        parse_tileset(&tileset, path.parent().unwrap_or(Path::new("")), first_gid)
+    }
+
+    fn parse_image(value: &Value, dir: &Path) -> Option<RawImage> {
+        Some(RawImage {
+            path: dir.join(value["image"].as_str()?),
+            transparent: value["transparentcolor"].as_str().map(str::to_owned),
+        })
+    }
+
+    fn parse_tileset(value: &Value, dir: &Path, first_gid: u32) -> Result<RawTileset, TiledError> {
+        let mut tile_images = HashMap::new();
+        for tile in value["tiles"].as_array().into_iter().flatten() {
+            if let Some(image) = parse_image(tile, dir) {
+                tile_images.insert(uint(tile, "id")?, image);
+            }
+        }
+        let offset = &value["tileoffset"];
+        Ok(RawTileset {
+            first_gid,
+            tile_size: (uint(value, "tilewidth")?, uint(value, "tileheight")?),
+            margin: uint(value, "margin").unwrap_or(0),
+            spacing: uint(value, "spacing").unwrap_or(0),
+            columns: uint(value, "columns").unwrap_or(0),
+            offset: (
+                offset["x"].as_i64().unwrap_or(0) as i32,
+                offset["y"].as_i64().unwrap_or(0) as i32,
+            ),
+            image: parse_image(value, dir),
+            tile_images,
+        })
+    }
+
+    fn parse_layers(value: &Value, layers: &mut Vec<RawLayer>) -> Result<(), TiledError> {
+        for layer in value.as_array().into_iter().flatten() {
+            match layer["type"].as_str() {
+                Some("tilelayer") => {
+                    if layer.get("chunks").is_some() {
+                        return Err(TiledError::Unsupported("infinite maps"));
+                    }
+                    let gids = match &layer["data"] {
+                        Value::String(data) => decode_tile_data(
+                            data,
+                            layer["encoding"].as_str(),
+                            layer["compression"].as_str(),
+                        )?,
+                        Value::Array(data) => data
+                            .iter()
+                            .map(|gid| gid.as_u64().map(|gid| gid as u32))
+                            .collect::<Option<_>>()
+                            .ok_or_else(|| invalid("bad tile data"))?,
+                        _ => return Err(invalid("layer without data")),
+                    };
+                    layers.push(RawLayer::Tiles {
+                        name: string(layer, "name"),
+                        gids,
+                        properties: parse_properties(&layer["properties"])?,
+                    });
+                }
+                Some("objectgroup") => {
+                    let objects = layer["objects"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .map(parse_object)
+                        .collect::<Result<_, _>>()?;
+                    layers.push(RawLayer::Objects {
+                        name: string(layer, "name"),
+                        objects,
+                        properties: parse_properties(&layer["properties"])?,
+                    });
+                }
+                Some("group") => parse_layers(&layer["layers"], layers)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_points(value: &Value) -> Vec<[f32; 2]> {
+        value
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|point| [float(point, "x"), float(point, "y")])
+            .collect()
+    }
+
+    fn parse_object(value: &Value) -> Result<RawObject, TiledError> {
+        let shape = if let Some(gid) = value["gid"].as_u64() {
+            RawShape::Tile(gid as u32)
+        } else if value["ellipse"].as_bool() == Some(true) {
+            RawShape::Ellipse
+        } else if value["point"].as_bool() == Some(true) {
+            RawShape::Point
+        } else if value.get("polygon").is_some() {
+            RawShape::Polygon(parse_points(&value["polygon"]))
+        } else if value.get("polyline").is_some() {
+            RawShape::Polyline(parse_points(&value["polyline"]))
+        } else {
+            RawShape::Rectangle
+        };
+        let class = value["class"].as_str().or(value["type"].as_str());
+        Ok(RawObject {
+            id: uint(value, "id").unwrap_or(0),
+            name: string(value, "name"),
+            class: class.unwrap_or_default().to_owned(),
+            position: [float(value, "x"), float(value, "y")],
+            size: [float(value, "width"), float(value, "height")],
+            rotation: float(value, "rotation"),
+            shape,
+            properties: parse_properties(&value["properties"])?,
+        })
+    }
+
+    fn parse_properties(value: &Value) -> Result<Properties, TiledError> {
+        let mut properties = Properties::new();
+        for property in value.as_array().into_iter().flatten() {
+            let v = &property["value"];
+            let bad = || invalid(format!("bad property value {v}"));
+            let value = match property["type"].as_str().unwrap_or("string") {
+                "int" => PropertyValue::Int(v.as_i64().ok_or_else(bad)?),
+                "float" => PropertyValue::Float(v.as_f64().ok_or_else(bad)?),
+                "bool" => PropertyValue::Bool(v.as_bool().ok_or_else(bad)?),
+                "color" => PropertyValue::Color(v.as_str().ok_or_else(bad)?.to_owned()),
+                "file" => PropertyValue::File(v.as_str().ok_or_else(bad)?.to_owned()),
+                "object" => PropertyValue::Object(v.as_u64().ok_or_else(bad)? as u32),
+                "class" => PropertyValue::Class(parse_class(v)?),
+                _ => PropertyValue::String(v.as_str().ok_or_else(bad)?.to_owned()),
+            };
+            properties.insert(string(property, "name"), value);
+        }
+        Ok(properties)
+    }
+
+    /// Class members are stored as a plain object, without type information.
+    fn parse_class(value: &Value) -> Result<Properties, TiledError> {
+        let members = value.as_object().into_iter().flatten();
+        members
+            .map(|(name, value)| {
+                let value = match value {
+                    Value::Bool(b) => PropertyValue::Bool(*b),
+                    Value::Number(n) => match n.as_i64() {
+                        Some(n) => PropertyValue::Int(n),
+                        None => PropertyValue::Float(n.as_f64().unwrap_or(0.)),
+                    },
+                    Value::Object(_) => PropertyValue::Class(parse_class(value)?),
+                    value => PropertyValue::String(value.as_str().unwrap_or_default().to_owned()),
+                };
+                Ok((name.clone(), value))
+            })
+            .collect()
+    }
+}
+
+/// Resolves global tile IDs to sprites,
+/// decoding tileset images and adding tiles to the sheet as they are first used.
+struct Importer {
+    raw: RawMap,
+    images: HashMap<PathBuf, (u32, u32, Vec<u8>)>,
+    sprites: HashMap<u32, SpriteHandle>,
+}
+
+impl Importer {
+    fn new(raw: RawMap) -> Self {
+        Self {
+            raw,
+            images: HashMap::new(),
+            sprites: HashMap::new(),
+        }
+    }
+
+    fn import(mut self, builder: &mut SpriteSheetBuilder) -> Result<TiledMap, TiledError> {
+        let (width, height) = self.raw.size;
+        let (tile_width, tile_height) = self.raw.tile_size;
+        if width == 0 || height == 0 || tile_width == 0 || tile_height == 0 {
+            return Err(invalid("map and tile sizes must not be zero"));
+        }
+        let map_height = height as f32 * tile_height as f32;
+        let mut tile_layers = Vec::new();
+        let mut object_layers = Vec::new();
+
+        for layer in std::mem::take(&mut self.raw.layers) {
+            match layer {
+                RawLayer::Tiles {
+                    name,
+                    gids,
+                    properties,
+                } => {
+                    if gids.len() as u64 != width as u64 * height as u64 {
+                        return Err(invalid(format!("layer {name:?} has the wrong size")));
+                    }
+                    // Tiled stores rows from the top, tilemaps from the bottom.
+                    let mut tiles = Vec::with_capacity(gids.len());
+                    for row in gids.chunks_exact(width as usize).rev() {
+                        for &gid in row {
+                            tiles.push(self.sprite(gid, builder)?);
+                        }
+                    }
+                    tile_layers.push(TileLayer {
+                        name,
+                        tiles,
+                        properties,
+                    });
+                }
+                RawLayer::Objects {
+                    name,
+                    objects,
+                    properties,
+                } => {
+                    let objects = objects
+                        .into_iter()
+                        .map(|object| self.object(object, map_height, builder))
+                        .collect::<Result<_, _>>()?;
+                    object_layers.push(ObjectLayer {
+                        name,
+                        objects,
+                        properties,
+                    });
+                }
+            }
+        }
+
+        Ok(TiledMap {
+            size: self.raw.size,
+            tile_size: self.raw.tile_size,
+            tile_layers,
+            object_layers,
+            properties: std::mem::take(&mut self.raw.properties),
+        })
+    }
+
+    fn object(
+        &mut self,
+        object: RawObject,
+        map_height: f32,
+        builder: &mut SpriteSheetBuilder,
+    ) -> Result<MapObject, TiledError> {
+        let flip = |points: Vec<[f32; 2]>| points.into_iter().map(|[x, y]| [x, -y]).collect();
+        let [x, y] = object.position;
+        // Tile objects are positioned by their bottom-left corner, everything else by the top-left.
+        let (position, shape) = match object.shape {
+            RawShape::Tile(gid) => {
+                let sprite = self
+                    .sprite(gid, builder)?
+                    .ok_or_else(|| invalid("tile object without a tile"))?;
+                ([x, map_height - y], ObjectShape::Tile(sprite))
+            }
+            RawShape::Rectangle => ([x, map_height - y - object.size[1]], ObjectShape::Rectangle),
+            RawShape::Ellipse => ([x, map_height - y - object.size[1]], ObjectShape::Ellipse),
+            RawShape::Point => ([x, map_height - y], ObjectShape::Point),
+            RawShape::Polygon(points) => ([x, map_height - y], ObjectShape::Polygon(flip(points))),
+            RawShape::Polyline(points) => {
+                ([x, map_height - y], ObjectShape::Polyline(flip(points)))
+            }
+        };
+        Ok(MapObject {
+            id: object.id,
+            name: object.name,
+            class: object.class,
+            position,
+            size: object.size,
+            rotation: object.rotation,
+            shape,
+            properties: object.properties,
+        })
+    }
+
+    /// Get the sprite of a global tile ID, including flip flags.
+    fn sprite(
+        &mut self,
+        gid: u32,
+        builder: &mut SpriteSheetBuilder,
+    ) -> Result<Option<SpriteHandle>, TiledError> {
+        if gid & GID_MASK == 0 {
+            return Ok(None);
+        }
+        if let Some(sprite) = self.sprites.get(&gid) {
+            return Ok(Some(*sprite));
+        }
+
+        let index = self
+            .raw
+            .tilesets
+            .iter()
+            .rposition(|tileset| tileset.first_gid <= gid & GID_MASK)
+            .ok_or_else(|| invalid(format!("tile {gid} has no tileset")))?;
+        let tileset = &self.raw.tilesets[index];
+        let id = (gid & GID_MASK) - tileset.first_gid;
+
+        let (image, source) = match (tileset.tile_images.get(&id), &tileset.image) {
+            (Some(image), _) => (image, None),
+            (None, Some(image)) if tileset.columns > 0 => {
+                let (w, h) = tileset.tile_size;
+                let (col, row) = (id % tileset.columns, id / tileset.columns);
+                // Corrupt IDs can place tiles past what fits in a `u32`.
+                let place = |i: u32, size: u32| {
+                    size.checked_add(tileset.spacing)?
+                        .checked_mul(i)?
+                        .checked_add(tileset.margin)
+                };
+                let outside = || invalid(format!("tile {gid} is outside of its image"));
+                let x = place(col, w).ok_or_else(outside)?;
+                let y = place(row, h).ok_or_else(outside)?;
+                (image, Some((x, y, w, h)))
+            }
+            _ => return Err(invalid(format!("tile {gid} has no image"))),
+        };
+        let offset = (tileset.offset.0, -tileset.offset.1);
+
+        if !self.images.contains_key(&image.path) {
+            let decoded = decode_png(&image.path, image.transparent.as_deref())?;
+            self.images.insert(image.path.clone(), decoded);
+        }
+        let (image_width, image_height, pixels) = &self.images[&image.path];
+        let (x, y, w, h) = source.unwrap_or((0, 0, *image_width, *image_height));
+        if x as u64 + w as u64 > *image_width as u64 || y as u64 + h as u64 > *image_height as u64 {
+            return Err(invalid(format!("tile {gid} is outside of its image")));
+        }
+
+        // Copy the tile out of the image, applying flips.
+        // Diagonal flips swap the axes, and are applied before the others.
+        let diagonal = gid & FLIP_DIAGONAL != 0;
+        let (out_w, out_h) = if diagonal { (h, w) } else { (w, h) };
+        let mut data = Vec::with_capacity((w * h * 4) as usize);
+        for dy in 0..out_h {
+            for dx in 0..out_w {
+                let sx = if gid & FLIP_HORIZONTAL != 0 {
+                    out_w - 1 - dx
+                } else {
+                    dx
+                };
+                let sy = if gid & FLIP_VERTICAL != 0 {
+                    out_h - 1 - dy
+                } else {
+                    dy
+                };
+                let (sx, sy) = if diagonal { (sy, sx) } else { (sx, sy) };
+                let i = (((y + sy) * image_width + x + sx) * 4) as usize;
+                data.extend_from_slice(&pixels[i..i + 4]);
+            }
+        }
+
+        let sprite = SpriteData::new((out_w, out_h), offset, data)
+            .ok_or_else(|| invalid(format!("tile {gid} is empty")))?;
+        let handle = builder.add(sprite);
+        self.sprites.insert(gid, handle);
+        Ok(Some(handle))
+    }
+}
+
+/// Decode a PNG file into its dimensions and premultiplied `Rgba8UnormSrgb` pixels.
+fn decode_png(path: &Path, transparent: Option<&str>) -> Result<(u32, u32, Vec<u8>), TiledError> {
+    let image_err = |err: png::DecodingError| TiledError::Image(path.to_owned(), err.to_string());
+    let file = std::fs::File::open(path).map_err(|err| TiledError::Io(path.to_owned(), err))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(image_err)?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(image_err)?;
+    buffer.truncate(info.buffer_size());
+
+    let mut pixels: Vec<u8> = match info.color_type {
+        png::ColorType::Rgba => buffer,
+        png::ColorType::Rgb => buffer
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buffer
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|&p| [p, p, p, 255]).collect(),
+        png::ColorType::Indexed => {
+            return Err(TiledError::Image(
+                path.to_owned(),
+                "unexpanded palette".into(),
+            ))
+        }
+    };
+
+    if let Some(color) = transparent {
+        let color = u32::from_str_radix(color.trim_start_matches('#'), 16)
+            .map_err(|_| invalid(format!("bad transparent color {color:?}")))?;
+        let [_, r, g, b] = color.to_be_bytes();
+        for pixel in pixels.chunks_exact_mut(4) {
+            if pixel[..3] == [r, g, b] {
+                pixel.copy_from_slice(&[0, 0, 0, 0]);
+            }
+        }
+    }
+
+    // Sprites are premultiplied, so translucent pixels are darkened in linear space.
+    for pixel in pixels.chunks_exact_mut(4) {
+        if pixel[3] < 255 {
+            let color = Color::srgb8(pixel[0], pixel[1], pixel[2], pixel[3]);
+            pixel.copy_from_slice(&color.to_premultiplied_srgb8());
+        }
+    }
+
+    Ok((info.width, info.height, pixels))
+}
@@ -0,0 +1,429 @@
+//! Grids of sprites that are uploaded once and drawn in bulk.
+//!
+//! A [Tilemap] is split into square chunks of tiles,
+//! each with its own instance buffer on the GPU.
+//! Changing a tile only marks its chunk as dirty,
+//! and dirty chunks are rebuilt the next time the tilemap is drawn.
+//! Chunks that fall outside of the canvas aren't drawn at all.
+
+use std::{cell::RefCell, ops::Range, rc::Rc};
+
+use super::sprite::{self, HandleError, InstanceData, SheetId, SpriteHandle, SpriteSheet};
+use super::{blend, Size};
+
+/// Width and height of a chunk, in tiles.
+const CHUNK_SIZE: u32 = 16;
+
+/// Maximum amount of tilemaps that can be drawn in a single frame.
+const MAX_TILEMAP_DRAWS: u64 = 64;
+
+/// A tilemap to submit for drawing.
+#[derive(Clone, Copy)]
+pub struct TilemapInstance<'a> {
+    pub tilemap: &'a Tilemap,
+    /// Position of the bottom-left corner of the map on the canvas.
+    /// Move this opposite to the camera to scroll the map.
+    pub position: [i32; 2],
+    /// How the tiles blend with the canvas.
+    pub blend: super::BlendMode,
+}
+
+/// A grid of sprites in one or more layers.
+///
+/// Tile `(0, 0)` is in the bottom-left corner,
+/// and layers are drawn in order, so higher layers cover lower ones.
+pub struct Tilemap {
+    /// Missing for tilemaps that are only drawn by the software renderer.
+    ctx: Option<Rc<super::Context>>,
+    /// Width and height in tiles.
+    size: (u32, u32),
+    /// Width and height of a tile in pixels.
+    tile_size: (u32, u32),
+    /// Tiles of each layer, row by row.
+    layers: Vec<Vec<Option<SpriteHandle>>>,
+    /// Width and height in chunks.
+    chunk_counts: (u32, u32),
+    chunks: Vec<Chunk>,
+}
+
+struct Chunk {
+    /// Instances of every layer, one layer after another.
+    instance_buffer: Option<wgpu::Buffer>,
+    state: RefCell<ChunkState>,
+}
+
+#[derive(Default)]
+struct ChunkState {
+    dirty: bool,
+    /// Instance ranges to draw for each layer.
+    batches: Vec<Vec<Batch>>,
+    /// Sheet revisions that the instances were built from.
+    /// If any of these change, sprites may have moved and the chunk is rebuilt.
+    revisions: Vec<(SheetId, u64)>,
+    /// Pixel area covered by the chunk's tiles, relative to the tilemap.
+    bounds: Option<([i32; 2], [i32; 2])>,
+}
+
+/// Consecutive tiles on the same sprite sheet page.
+struct Batch {
+    sheet: SheetId,
+    page: u32,
+    range: Range<u32>,
+}
+
+impl Tilemap {
+    pub(crate) fn new(
+        ctx: Option<Rc<super::Context>>,
+        size: (u32, u32),
+        tile_size: (u32, u32),
+        layer_count: usize,
+    ) -> Self {
+        let chunk_counts = (size.0.div_ceil(CHUNK_SIZE), size.1.div_ceil(CHUNK_SIZE));
+        let chunk_capacity = (CHUNK_SIZE * CHUNK_SIZE) as u64 * layer_count as u64;
+        let chunks = (0..chunk_counts.0 * chunk_counts.1)
+            .map(|_| Chunk {
+                instance_buffer: ctx.as_ref().map(|ctx| {
+                    ctx.device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("tilemap chunk"),
+                        size: chunk_capacity.max(1) * std::mem::size_of::<InstanceData>() as u64,
+                        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    })
+                }),
+                state: RefCell::new(ChunkState::default()),
+            })
+            .collect();
+        let tile_count = (size.0 * size.1) as usize;
+        Self {
+            ctx,
+            size,
+            tile_size,
+            layers: vec![vec![None; tile_count]; layer_count],
+            chunk_counts,
+            chunks,
+        }
+    }
+
+    /// Width and height in tiles.
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// Width and height of a single tile in pixels.
+    pub fn tile_size(&self) -> (u32, u32) {
+        self.tile_size
+    }
+
+    pub fn layer_count(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Get the sprite of a tile.
+    /// Returns `None` for empty tiles and coordinates outside of the map.
+    pub fn tile(&self, layer: usize, x: u32, y: u32) -> Option<SpriteHandle> {
+        if x >= self.size.0 || y >= self.size.1 {
+            return None;
+        }
+        *self
+            .layers
+            .get(layer)?
+            .get((x + y * self.size.0) as usize)?
+    }
+
+    /// Set the sprite of a tile, or clear it with `None`.
+    ///
+    /// Panics if the layer or coordinates are outside of the map.
+    pub fn set_tile(&mut self, layer: usize, x: u32, y: u32, tile: Option<SpriteHandle>) {
+        assert!(x < self.size.0 && y < self.size.1, "tile outside of map");
+        self.layers[layer][(x + y * self.size.0) as usize] = tile;
+        let chunk = x / CHUNK_SIZE + y / CHUNK_SIZE * self.chunk_counts.0;
+        self.chunks[chunk as usize].state.get_mut().dirty = true;
+    }
+
+    /// Set every tile of a layer to the same sprite.
+    pub fn fill(&mut self, layer: usize, tile: Option<SpriteHandle>) {
+        self.layers[layer].fill(tile);
+        for chunk in &mut self.chunks {
+            chunk.state.get_mut().dirty = true;
+        }
+    }
+
+    /// Tiles of a layer in the order that they're drawn, chunk by chunk and row by row,
+    /// with their position in pixels relative to the tilemap.
+    pub(crate) fn layer_tiles(
+        &self,
+        layer: usize,
+    ) -> impl Iterator<Item = ([i32; 2], SpriteHandle)> + '_ {
+        (0..self.chunks.len() as u32).flat_map(move |index| {
+            let (cx, cy) = (index % self.chunk_counts.0, index / self.chunk_counts.0);
+            let xs = cx * CHUNK_SIZE..((cx + 1) * CHUNK_SIZE).min(self.size.0);
+            let ys = cy * CHUNK_SIZE..((cy + 1) * CHUNK_SIZE).min(self.size.1);
+            ys.flat_map(move |y| xs.clone().map(move |x| (x, y)))
+                .filter_map(move |(x, y)| {
+                    let tile = self.layers[layer][(x + y * self.size.0) as usize]?;
+                    let position = [(x * self.tile_size.0) as i32, (y * self.tile_size.1) as i32];
+                    Some((position, tile))
+                })
+        })
+    }
+
+    /// Rebuild and upload a chunk if its tiles or their sprites have changed.
+    fn prepare_chunk(&self, index: u32, sprite_sheets: &[&SpriteSheet]) {
+        let chunk = &self.chunks[index as usize];
+        let (Some(ctx), Some(instance_buffer)) = (&self.ctx, &chunk.instance_buffer) else {
+            return;
+        };
+        let mut state = chunk.state.borrow_mut();
+        let outdated = state.revisions.iter().any(|(id, revision)| {
+            sprite_sheets
+                .iter()
+                .any(|sheet| sheet.id() == *id && sheet.revision() != *revision)
+        });
+        if !state.dirty && !outdated {
+            return;
+        }
+
+        let (cx, cy) = (index % self.chunk_counts.0, index / self.chunk_counts.0);
+        let xs = cx * CHUNK_SIZE..((cx + 1) * CHUNK_SIZE).min(self.size.0);
+        let ys = cy * CHUNK_SIZE..((cy + 1) * CHUNK_SIZE).min(self.size.1);
+
+        let mut instances = Vec::new();
+        let mut complete = true;
+        state.batches.clear();
+        state.revisions.clear();
+        state.bounds = None;
+        for layer in &self.layers {
+            let mut batches: Vec<Batch> = Vec::new();
+            for y in ys.clone() {
+                for x in xs.clone() {
+                    let Some(handle) = layer[(x + y * self.size.0) as usize] else {
+                        continue;
+                    };
+                    let entry = match sprite::lookup(sprite_sheets, handle) {
+                        Ok((_, entry)) => entry,
+                        Err(err) => {
+                            // Try again once the sheet is passed to render.
+                            complete &= err != HandleError::MissingSheet;
+                            continue;
+                        }
+                    };
+
+                    let position = [(x * self.tile_size.0) as i32, (y * self.tile_size.1) as i32];
+                    let instance = InstanceData::new(entry, position);
+                    let min = instance.position;
+                    let max = [
+                        min[0] + instance.dimensions[0] as i32,
+                        min[1] + instance.dimensions[1] as i32,
+                    ];
+                    state.bounds = Some(match state.bounds {
+                        Some((lo, hi)) => (
+                            [lo[0].min(min[0]), lo[1].min(min[1])],
+                            [hi[0].max(max[0]), hi[1].max(max[1])],
+                        ),
+                        None => (min, max),
+                    });
+
+                    let sheet = handle.sheet();
+                    if !state.revisions.iter().any(|(id, _)| *id == sheet) {
+                        let revision = sprite_sheets
+                            .iter()
+                            .find(|s| s.id() == sheet)
+                            .map_or(0, |s| s.revision());
+                        state.revisions.push((sheet, revision));
+                    }
+
+                    let i = instances.len() as u32;
+                    match batches.last_mut() {
+                        Some(batch) if batch.sheet == sheet && batch.page == entry.page => {
+                            batch.range.end += 1
+                        }
+                        _ => batches.push(Batch {
+                            sheet,
+                            page: entry.page,
+                            range: i..i + 1,
+                        }),
+                    }
+                    instances.push(instance);
+                }
+            }
+            state.batches.push(batches);
+        }
+
+        ctx.queue.write_buffer(
+            instance_buffer,
+            0,
+            bytemuck::cast_slice(instances.as_slice()),
+        );
+        state.dirty = !complete;
+    }
+}
+
+pub(crate) struct Renderer {
+    ctx: Rc<super::Context>,
+    pub(crate) pipelines: blend::Pipelines,
+    offset_buffer: wgpu::Buffer,
+    offset_bind_group: wgpu::BindGroup,
+    /// Distance between tilemap offsets in the uniform buffer.
+    offset_stride: u64,
+}
+
+impl Renderer {
+    pub(crate) fn new(
+        ctx: Rc<super::Context>,
+        sprite_sheet_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let device = &ctx.device;
+
+        // Each drawn tilemap gets a slot for its offset, selected with a dynamic offset.
+        let offset_stride = ctx.limits.min_uniform_buffer_offset_alignment.max(16) as u64;
+        let offset_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("tilemap offsets"),
+            size: MAX_TILEMAP_DRAWS * offset_stride,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let offset_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("tilemap offset"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: Some(std::num::NonZeroU64::new(16).unwrap()),
+                },
+                count: None,
+            }],
+        });
+
+        let offset_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("tilemap offset"),
+            layout: &offset_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &offset_buffer,
+                    offset: 0,
+                    size: std::num::NonZeroU64::new(16),
+                }),
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("tilemap"),
+            bind_group_layouts: &[&ctx.dimensions_layout, sprite_sheet_layout, &offset_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipelines = blend::Pipelines::new(ctx.shaders.clone(), {
+            let ctx = ctx.clone();
+            move |module, blend| create_pipeline(&ctx, &pipeline_layout, module, blend)
+        });
+
+        Self {
+            ctx,
+            pipelines,
+            offset_buffer,
+            offset_bind_group,
+            offset_stride,
+        }
+    }
+
+    pub(crate) fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        sprite_sheets: &[&'a SpriteSheet],
+        tilemaps: &[TilemapInstance<'a>],
+        canvas: Size,
+    ) {
+        if tilemaps.len() as u64 > MAX_TILEMAP_DRAWS {
+            warn!("Only the first {MAX_TILEMAP_DRAWS} tilemaps are drawn");
+        }
+        let tilemaps = &tilemaps[..tilemaps.len().min(MAX_TILEMAP_DRAWS as usize)];
+
+        render_pass.set_vertex_buffer(0, self.ctx.quad_buffer.slice(..));
+
+        for (slot, instance) in tilemaps.iter().enumerate() {
+            let tilemap = instance.tilemap;
+            render_pass.set_pipeline(self.pipelines.get(instance.blend));
+            let offset = slot as u64 * self.offset_stride;
+            self.ctx.queue.write_buffer(
+                &self.offset_buffer,
+                offset,
+                bytemuck::cast_slice(&[instance.position[0], instance.position[1], 0, 0]),
+            );
+            render_pass.set_bind_group(2, &self.offset_bind_group, &[offset as u32]);
+
+            // Only chunks that overlap the canvas are drawn.
+            let visible: Vec<&Chunk> = (0..tilemap.chunks.len() as u32)
+                .filter_map(|index| {
+                    tilemap.prepare_chunk(index, sprite_sheets);
+                    let chunk = &tilemap.chunks[index as usize];
+                    let (min, max) = chunk.state.borrow().bounds?;
+                    let x = instance.position[0];
+                    let y = instance.position[1];
+                    let visible = max[0] + x > 0
+                        && max[1] + y > 0
+                        && min[0] + x < canvas.width as i32
+                        && min[1] + y < canvas.height as i32;
+                    visible.then_some(chunk)
+                })
+                .collect();
+
+            for layer in 0..tilemap.layers.len() {
+                for chunk in &visible {
+                    let Some(instance_buffer) = &chunk.instance_buffer else {
+                        continue;
+                    };
+                    render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+                    for batch in &chunk.state.borrow().batches[layer] {
+                        let Some(&sheet) = sprite_sheets.iter().find(|s| s.id() == batch.sheet)
+                        else {
+                            continue;
+                        };
+                        let Some(bind_group) = sheet.page(batch.page).bind_group() else {
+                            continue;
+                        };
+                        render_pass.set_bind_group(1, bind_group, &[]);
+                        render_pass.draw(0..super::QUAD_VERTICES.len() as u32, batch.range.clone());
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn create_pipeline(
+    ctx: &super::Context,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    blend: wgpu::BlendState,
+) -> wgpu::RenderPipeline {
+    ctx.device
+        .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("tilemap"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module,
+                entry_point: "tile_v",
+                buffers: &[super::QUAD_LAYOUT, sprite::INSTANCE_LAYOUT],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module,
+                entry_point: "sprite_f",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: ctx.color_format,
+                    blend: Some(blend),
+                    write_mask: wgpu::ColorWrites::all(),
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            multisample: wgpu::MultisampleState::default(),
+            depth_stencil: None,
+            multiview: None,
+        })
+}
@@ -0,0 +1,179 @@
+//! Rasterization of TrueType and OpenType fonts at a fixed pixel size.
+//!
+//! Outlines are rasterized with anti-aliasing, then thresholded to fully opaque or transparent pixels,
+//! so text stays as crisp as the rest of the canvas.
+//! Glyphs are rasterized on demand by [TrueTypeFont::prepare], and cached in a [SpriteSheet].
+//! The result is an ordinary [Font], laid out and drawn like a bitmap font.
+//!
+//! ```ignore
+//! let mut font = TrueTypeFont::load("assets/NotoSansJP.otf", 12)?;
+//! font.add_fallback(std::fs::read("assets/NotoSans.ttf")?)?;
+//! font.prepare(&mut glyph_sheet, message);
+//! scene.texts.push(Text::new(font.font(), message, [4, 100]));
+//! ```
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use ab_glyph::{Font as _, FontVec, PxScale, ScaleFont};
+
+use super::{
+    sprite::{SpriteData, SpriteSheet},
+    text::{Font, Glyph},
+};
+
+#[derive(Debug)]
+pub enum TrueTypeError {
+    Io(std::io::Error),
+    Invalid,
+}
+
+impl From<std::io::Error> for TrueTypeError {
+    fn from(err: std::io::Error) -> Self {
+        TrueTypeError::Io(err)
+    }
+}
+
+impl std::fmt::Display for TrueTypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrueTypeError::Io(err) => write!(f, "couldn't read font: {err}"),
+            TrueTypeError::Invalid => f.write_str("not a TrueType or OpenType font"),
+        }
+    }
+}
+
+/// A scalable font rasterized at one pixel size, with fallbacks for missing glyphs.
+pub struct TrueTypeFont {
+    /// The primary face, followed by fallbacks in order of preference.
+    faces: Vec<FontVec>,
+    scale: PxScale,
+    /// Minimum coverage for a pixel to be drawn.
+    threshold: f32,
+    /// Distance from the bottom of the line to the baseline.
+    descent: i32,
+    font: Font,
+    /// The face that each cached character was rasterized from.
+    sources: HashMap<char, usize>,
+    /// Characters that no face has a glyph for.
+    missing: HashSet<char>,
+    kerned: HashSet<(char, char)>,
+}
+
+impl TrueTypeFont {
+    /// Create a font from the contents of a `.ttf` or `.otf` file.
+    /// `pixel_size` is the distance from the highest ascender to the lowest descender.
+    pub fn new(data: Vec<u8>, pixel_size: u32) -> Result<Self, TrueTypeError> {
+        let face = FontVec::try_from_vec(data).map_err(|_| TrueTypeError::Invalid)?;
+        let scale = PxScale::from(pixel_size as f32);
+        let scaled = face.as_scaled(scale);
+        let descent = -scaled.descent().round() as i32;
+        let line_height = (scaled.height() + scaled.line_gap()).round().max(1.0) as u32;
+        Ok(Self {
+            faces: vec![face],
+            scale,
+            threshold: 0.5,
+            descent,
+            font: Font::new(line_height),
+            sources: HashMap::new(),
+            missing: HashSet::new(),
+            kerned: HashSet::new(),
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>, pixel_size: u32) -> Result<Self, TrueTypeError> {
+        Self::new(std::fs::read(path)?, pixel_size)
+    }
+
+    /// Add a font to take glyphs from when earlier fonts don't have them.
+    /// Its glyphs are rasterized at the same pixel size, and aligned to the same baseline.
+    pub fn add_fallback(&mut self, data: Vec<u8>) -> Result<(), TrueTypeError> {
+        let face = FontVec::try_from_vec(data).map_err(|_| TrueTypeError::Invalid)?;
+        self.faces.push(face);
+        self.missing.clear();
+        Ok(())
+    }
+
+    /// Set the coverage, from 0 to 1, above which a pixel is drawn.
+    /// Only affects glyphs that haven't been rasterized yet.
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+
+    /// The font to draw prepared text with.
+    pub fn font(&self) -> &Font {
+        &self.font
+    }
+
+    pub fn font_mut(&mut self) -> &mut Font {
+        &mut self.font
+    }
+
+    /// Rasterize the glyphs of `text` that aren't cached yet, inserting them into `sheet`.
+    /// The same sheet must be passed every time, and to [crate::Renderer::render].
+    pub fn prepare(&mut self, sheet: &mut SpriteSheet, text: &str) {
+        for c in text.chars() {
+            if !c.is_control() && self.font.glyph(c).is_none() && !self.missing.contains(&c) {
+                self.rasterize(sheet, c);
+            }
+        }
+
+        // Kerning only applies between glyphs of the same face.
+        let mut chars = text.chars().peekable();
+        while let (Some(left), Some(&right)) = (chars.next(), chars.peek()) {
+            // Pairs with a missing char are kerned once a fallback provides it.
+            let (Some(&a), Some(&b)) = (self.sources.get(&left), self.sources.get(&right)) else {
+                continue;
+            };
+            if !self.kerned.insert((left, right)) {
+                continue;
+            }
+            if a == b {
+                let face = self.faces[a].as_scaled(self.scale);
+                let kern = face.kern(face.glyph_id(left), face.glyph_id(right)).round() as i32;
+                if kern != 0 {
+                    self.font.set_kerning(left, right, kern);
+                }
+            }
+        }
+    }
+
+    fn rasterize(&mut self, sheet: &mut SpriteSheet, c: char) {
+        let Some(index) = self.faces.iter().position(|face| face.glyph_id(c).0 != 0) else {
+            self.missing.insert(c);
+            return;
+        };
+        let face = self.faces[index].as_scaled(self.scale);
+        let id = face.glyph_id(c);
+        let advance = face.h_advance(id).round() as i32;
+
+        let sprite = face
+            .outline_glyph(face.scaled_glyph(c))
+            .and_then(|outline| {
+                // Rounded outward, so that the bitmap holds every pixel that `draw` visits.
+                let bounds = outline.px_bounds();
+                let (min_x, max_y) = (bounds.min.x.floor(), bounds.max.y.ceil());
+                let width = (bounds.max.x.ceil() - min_x) as u32;
+                let height = (max_y - bounds.min.y.floor()) as u32;
+                let mut data = vec![0; (width * height * 4) as usize];
+                outline.draw(|x, y, coverage| {
+                    if coverage >= self.threshold {
+                        let i = ((y * width + x) * 4) as usize;
+                        data[i..i + 4].copy_from_slice(&[255; 4]);
+                    }
+                });
+                if data.iter().all(|&byte| byte == 0) {
+                    return None;
+                }
+                // Bounds are relative to the baseline with the Y-axis pointing down.
+                let offset = (min_x as i32, self.descent - max_y as i32);
+                let sprite = SpriteData::new((width, height), offset, data)?;
+                Some(sheet.insert(sprite))
+            });
+
+        self.font.add_glyph(c, Glyph { sprite, advance });
+        self.sources.insert(c, index);
+    }
+}
@@ -2,36 +2,113 @@ use log::debug;
 use std::rc::Rc;
 use wgpu::util::DeviceExt;
 
-use super::{Size, Vertex};
+use super::{sprite::SpriteData, Color, RenderError, Size, Vertex};
 
-fn calculate_active_quad(surface: &Size, internal: &Size) -> [Vertex; 4] {
-    let int_scale = std::cmp::min(
-        surface.width / internal.width,
-        surface.height / internal.height,
-    );
+/// How the canvas is scaled to fill the window.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UpscaleMode {
+    /// Scale by the largest whole number that fits, so every pixel is the same size.
+    /// The rest of the window is letterboxed.
+    #[default]
+    Integer,
+    /// Scale as large as fits while keeping the aspect ratio, letterboxing the rest.
+    /// Pixels are filtered with sharp bilinear filtering,
+    /// which only blends the edges between pixels to hide their uneven sizes.
+    Fit,
+    /// Fill the whole window, distorting the aspect ratio, with sharp bilinear filtering.
+    Stretch,
+    /// Fill the whole window while keeping the aspect ratio, cutting off the sides that overflow,
+    /// with sharp bilinear filtering.
+    FillCrop,
+}
+
+/// What fills the window around the upscaled canvas.
+pub enum Letterbox {
+    /// A solid color, black by default.
+    Color(Color),
+    /// An image scaled to cover the whole window while keeping its aspect ratio,
+    /// like the borders of console pixel games.
+    /// The image is given in `Rgba8UnormSrgb` with premultiplied alpha, like sprites,
+    /// and its offset is ignored.
+    Image(SpriteData),
+    /// A blurred copy of the canvas scaled to cover the whole window,
+    /// with its colors multiplied by `brightness`.
+    BlurredCanvas { brightness: f32 },
+}
 
-    let upscaled = Size {
-        width: internal.width * int_scale,
-        height: internal.height * int_scale,
+impl Default for Letterbox {
+    fn default() -> Self {
+        Letterbox::Color(Color::BLACK)
+    }
+}
+
+/// A letterbox ready to draw.
+enum Background {
+    Color(Color),
+    Image {
+        size: Size,
+        bind_group: wgpu::BindGroup,
+        _texture: wgpu::Texture,
+    },
+    BlurredCanvas {
+        brightness: f32,
+    },
+}
+
+/// Position and size of the upscaled canvas in surface pixels, from the top left.
+fn upscaled_rect(surface: &Size, internal: &Size, mode: UpscaleMode) -> [f32; 4] {
+    let (surface_width, surface_height) = (surface.width as f32, surface.height as f32);
+    let (internal_width, internal_height) = (internal.width as f32, internal.height as f32);
+    let scale_x = surface_width / internal_width;
+    let scale_y = surface_height / internal_height;
+    let scaled = |scale: f32| {
+        (
+            (internal_width * scale).round(),
+            (internal_height * scale).round(),
+        )
+    };
+    let (width, height) = match mode {
+        UpscaleMode::Integer => scaled(scale_x.min(scale_y).floor()),
+        UpscaleMode::Fit => scaled(scale_x.min(scale_y)),
+        UpscaleMode::Stretch => (surface_width, surface_height),
+        UpscaleMode::FillCrop => scaled(scale_x.max(scale_y)),
     };
 
     debug!(
-        "Upscale calculation.\nSurface: {:?}\nUpscale integer: {:?}\nUpscaled upscale: {:?}",
-        surface, int_scale, upscaled
+        "Upscale calculation.\nSurface: {:?}\nMode: {:?}\nUpscaled: {:?}",
+        surface,
+        mode,
+        (width, height)
     );
 
-    let x_offset = (surface.width - upscaled.width) / 2;
-    let x_padding = upscaled.width + x_offset;
-    let x1 = x_offset as f32 / surface.width as f32 * 2. - 1.;
-    let x2 = x_padding as f32 / surface.width as f32 * 2. - 1.;
+    // Offsets are negative when cropping, and the canvas extends past the edges of the surface.
+    let x_offset = ((surface_width - width) / 2.).floor();
+    let y_offset = ((surface_height - height) / 2.).floor();
+    [x_offset, y_offset, width, height]
+}
+
+/// The upscaled rectangle, or `None` if nothing of it is shown.
+/// That's the case for minimized windows, and in [UpscaleMode::Integer]
+/// for windows smaller than the canvas.
+fn visible_rect(surface: &Size, internal: &Size, mode: UpscaleMode) -> Option<[f32; 4]> {
+    let rect = upscaled_rect(surface, internal, mode);
+    let visible = surface.width > 0 && surface.height > 0 && rect[2] > 0.0 && rect[3] > 0.0;
+    visible.then_some(rect)
+}
+
+fn calculate_active_quad(surface: &Size, internal: &Size, mode: UpscaleMode) -> [Vertex; 4] {
+    let (surface_width, surface_height) = (surface.width as f32, surface.height as f32);
+    let [x_offset, y_offset, width, height] = upscaled_rect(surface, internal, mode);
+    let x_padding = width + x_offset;
+    let x1 = x_offset / surface_width * 2. - 1.;
+    let x2 = x_padding / surface_width * 2. - 1.;
 
     // Note that the Y-axis is flipped here.
     // The entire image is drawn-up upside-down, then flipped around at the end.
     // This allows us to use a positive Y-axis in the renderer.
-    let y_offset = (surface.height - upscaled.height) / 2;
-    let y_padding = upscaled.height + y_offset;
-    let y1 = y_padding as f32 / surface.height as f32 * 2. - 1.;
-    let y2 = y_offset as f32 / surface.height as f32 * 2. - 1.;
+    let y_padding = height + y_offset;
+    let y1 = y_padding / surface_height * 2. - 1.;
+    let y2 = y_offset / surface_height * 2. - 1.;
 
     [
         Vertex { x: x1, y: y1 },
@@ -41,25 +118,52 @@ fn calculate_active_quad(surface: &Size, internal: &Size) -> [Vertex; 4] {
     ]
 }
 
+pub(crate) struct Pipelines {
+    /// Samples the nearest texel, for integer scaling.
+    nearest: wgpu::RenderPipeline,
+    /// Samples with sharp bilinear filtering, for every other mode and for letterbox images.
+    sharp: wgpu::RenderPipeline,
+    /// Blurs the canvas for the letterbox, scaling colors by the blend constant.
+    blur: wgpu::RenderPipeline,
+}
+
 pub struct Renderer {
     ctx: Rc<super::Context>,
-    pipeline: wgpu::RenderPipeline,
+    pub(crate) mode: UpscaleMode,
+    /// Size of the canvas that the bind group samples.
+    pub(crate) canvas_size: Size,
+    /// Kept to create the pipelines again when shaders are reloaded.
+    #[cfg(feature = "hot-reload")]
+    pipeline_layout: wgpu::PipelineLayout,
+    pub(crate) pipelines: Pipelines,
+    bind_group_layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
     active_quad_buffer: wgpu::Buffer,
-    _sampler_nearest: wgpu::Sampler,
+    background: Background,
+    /// Where letterbox images and the blurred canvas are drawn.
+    background_quad_buffer: wgpu::Buffer,
+    sampler_nearest: wgpu::Sampler,
+    sampler_linear: wgpu::Sampler,
 }
 
 impl Renderer {
-    pub(crate) fn new(ctx: Rc<super::Context>) -> Self {
+    pub(crate) fn new(ctx: Rc<super::Context>, canvas: &super::Canvas) -> Self {
         let device = &ctx.device;
         let active_quad_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("canvas quad buffer"),
             contents: bytemuck::cast_slice(&calculate_active_quad(
-                &ctx.canvas.size,
-                &ctx.canvas.size,
+                &canvas.size,
+                &canvas.size,
+                UpscaleMode::default(),
             )),
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         });
+        let background_quad_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("letterbox quad buffer"),
+            size: std::mem::size_of::<[Vertex; 4]>() as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("canvas to surface bind group layout"),
@@ -68,7 +172,7 @@ impl Renderer {
                     binding: 0,
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
                         view_dimension: wgpu::TextureViewDimension::D2,
                         multisampled: false,
                     },
@@ -80,6 +184,12 @@ impl Renderer {
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
             ],
         });
 
@@ -91,31 +201,7 @@ impl Renderer {
 
         let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/upscale.wgsl"));
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("upscale to surface pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "upscale_v",
-                buffers: &[super::QUAD_LAYOUT, super::TEXCOORD_LAYOUT],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "upscale_f",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: ctx.canvas.color_format,
-                    blend: None,
-                    write_mask: wgpu::ColorWrites::all(),
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleStrip,
-                .. Default::default()
-            },
-            multisample: wgpu::MultisampleState::default(),
-            depth_stencil: None,
-            multiview: None,
-        });
+        let pipelines = create_pipelines(&ctx, &pipeline_layout, &shader);
 
         let sampler_nearest = device.create_sampler(&wgpu::SamplerDescriptor {
             label: Some("upscale sampler"),
@@ -124,41 +210,179 @@ impl Renderer {
             ..Default::default()
         });
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("upscale bind group"),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&ctx.canvas.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler_nearest),
-                },
-            ],
+        let sampler_linear = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("sharp upscale sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
         });
 
+        let bind_group = create_bind_group(
+            device,
+            "upscale bind group",
+            &bind_group_layout,
+            &canvas.view,
+            [&sampler_nearest, &sampler_linear],
+        );
+
         Self {
             ctx,
-            pipeline,
+            mode: UpscaleMode::default(),
+            canvas_size: canvas.size,
+            #[cfg(feature = "hot-reload")]
+            pipeline_layout,
+            pipelines,
+            bind_group_layout,
             bind_group,
             active_quad_buffer,
-            _sampler_nearest: sampler_nearest,
+            background: Background::Color(Color::BLACK),
+            background_quad_buffer,
+            sampler_nearest,
+            sampler_linear,
         }
     }
 
+    /// Create the pipelines again, from reloaded shaders.
+    #[cfg(feature = "hot-reload")]
+    pub(crate) fn create_pipelines(&self, module: &wgpu::ShaderModule) -> Pipelines {
+        create_pipelines(&self.ctx, &self.pipeline_layout, module)
+    }
+
     pub fn renew_active_quad(&self, queue: &wgpu::Queue, surface_size: Size) {
         queue.write_buffer(
             &self.active_quad_buffer,
             0,
             bytemuck::cast_slice(&calculate_active_quad(
                 &surface_size,
-                &self.ctx.canvas.size,
+                &self.canvas_size,
+                self.mode,
+            )),
+        );
+        let background_size = match &self.background {
+            Background::Color(_) => return,
+            Background::Image { size, .. } => *size,
+            Background::BlurredCanvas { .. } => self.canvas_size,
+        };
+        queue.write_buffer(
+            &self.background_quad_buffer,
+            0,
+            bytemuck::cast_slice(&calculate_active_quad(
+                &surface_size,
+                &background_size,
+                UpscaleMode::FillCrop,
             )),
         );
     }
 
+    /// Replace what fills the window around the canvas.
+    pub(crate) fn set_letterbox(
+        &mut self,
+        letterbox: Letterbox,
+        surface_size: Size,
+    ) -> Result<(), RenderError> {
+        self.background = match letterbox {
+            Letterbox::Color(color) => Background::Color(color),
+            Letterbox::Image(image) => {
+                let device = &self.ctx.device;
+                let (width, height) = image.dimensions();
+                let size = Size { width, height };
+                super::target::check_size("letterbox image", size, &self.ctx.limits)?;
+                let texture = device.create_texture_with_data(
+                    &self.ctx.queue,
+                    &wgpu::TextureDescriptor {
+                        label: Some("letterbox image"),
+                        size: wgpu::Extent3d {
+                            width,
+                            height,
+                            depth_or_array_layers: 1,
+                        },
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: wgpu::TextureDimension::D2,
+                        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                        usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                        view_formats: &[],
+                    },
+                    wgpu::util::TextureDataOrder::LayerMajor,
+                    image.data(),
+                );
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                let bind_group = create_bind_group(
+                    device,
+                    "letterbox image bind group",
+                    &self.bind_group_layout,
+                    &view,
+                    [&self.sampler_nearest, &self.sampler_linear],
+                );
+                Background::Image {
+                    size,
+                    bind_group,
+                    _texture: texture,
+                }
+            }
+            Letterbox::BlurredCanvas { brightness } => Background::BlurredCanvas { brightness },
+        };
+        self.renew_active_quad(&self.ctx.queue, surface_size);
+        Ok(())
+    }
+
+    /// Sample a new canvas, after it was created again at another size.
+    pub(crate) fn set_canvas(&mut self, canvas: &super::Canvas, surface_size: Size) {
+        self.canvas_size = canvas.size;
+        self.bind_group = create_bind_group(
+            &self.ctx.device,
+            "upscale bind group",
+            &self.bind_group_layout,
+            &canvas.view,
+            [&self.sampler_nearest, &self.sampler_linear],
+        );
+        self.renew_active_quad(&self.ctx.queue, surface_size);
+    }
+
+    /// Position and size of the upscaled canvas in pixels of a surface, from the top left.
+    pub(crate) fn upscaled_rect(&self, surface_size: Size) -> [f32; 4] {
+        upscaled_rect(&surface_size, &self.canvas_size, self.mode)
+    }
+
+    /// Map a position in surface pixels, from the top left, to canvas pixels.
+    /// The canvas Y-axis points up, so its rows are flipped on the way.
+    /// Returns `None` if the canvas isn't visible.
+    pub(crate) fn surface_to_canvas(
+        &self,
+        surface_size: Size,
+        position: [f32; 2],
+    ) -> Option<[f32; 2]> {
+        let [x_offset, y_offset, width, height] =
+            visible_rect(&surface_size, &self.canvas_size, self.mode)?;
+        let (canvas_width, canvas_height) = (
+            self.canvas_size.width as f32,
+            self.canvas_size.height as f32,
+        );
+        Some([
+            (position[0] - x_offset) / width * canvas_width,
+            canvas_height - (position[1] - y_offset) / height * canvas_height,
+        ])
+    }
+
+    /// Map a position in canvas pixels to surface pixels, from the top left.
+    /// Returns `None` if the canvas isn't visible.
+    pub(crate) fn canvas_to_surface(
+        &self,
+        surface_size: Size,
+        position: [f32; 2],
+    ) -> Option<[f32; 2]> {
+        let [x_offset, y_offset, width, height] =
+            visible_rect(&surface_size, &self.canvas_size, self.mode)?;
+        let (canvas_width, canvas_height) = (
+            self.canvas_size.width as f32,
+            self.canvas_size.height as f32,
+        );
+        Some([
+            x_offset + position[0] / canvas_width * width,
+            y_offset + (canvas_height - position[1]) / canvas_height * height,
+        ])
+    }
+
     pub(crate) fn render(
         &self,
         encoder: &mut wgpu::CommandEncoder,
@@ -170,7 +394,10 @@ impl Renderer {
                 view: target_surface,
                 resolve_target: None,
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    load: wgpu::LoadOp::Clear(match self.background {
+                        Background::Color(color) => color.into(),
+                        _ => wgpu::Color::BLACK,
+                    }),
                     store: wgpu::StoreOp::Store,
                 },
             })],
@@ -179,10 +406,150 @@ impl Renderer {
             occlusion_query_set: None, // TODO: Check this
         });
 
-        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(1, self.ctx.quad_buffer.slice(..));
+        match &self.background {
+            Background::Color(_) => {}
+            Background::Image { bind_group, .. } => {
+                render_pass.set_pipeline(&self.pipelines.sharp);
+                render_pass.set_bind_group(0, bind_group, &[]);
+                render_pass.set_vertex_buffer(0, self.background_quad_buffer.slice(..));
+                render_pass.draw(0..super::QUAD_VERTICES.len() as u32, 0..1);
+            }
+            Background::BlurredCanvas { brightness } => {
+                let brightness = *brightness as f64;
+                render_pass.set_pipeline(&self.pipelines.blur);
+                render_pass.set_blend_constant(wgpu::Color {
+                    r: brightness,
+                    g: brightness,
+                    b: brightness,
+                    a: 1.0,
+                });
+                render_pass.set_bind_group(0, &self.bind_group, &[]);
+                render_pass.set_vertex_buffer(0, self.background_quad_buffer.slice(..));
+                render_pass.draw(0..super::QUAD_VERTICES.len() as u32, 0..1);
+            }
+        }
+
+        render_pass.set_pipeline(match self.mode {
+            UpscaleMode::Integer => &self.pipelines.nearest,
+            _ => &self.pipelines.sharp,
+        });
         render_pass.set_bind_group(0, &self.bind_group, &[]);
         render_pass.set_vertex_buffer(0, self.active_quad_buffer.slice(..));
-        render_pass.set_vertex_buffer(1, self.ctx.quad_buffer.slice(..));
         render_pass.draw(0..super::QUAD_VERTICES.len() as u32, 0..1);
     }
 }
+
+/// Bind a texture to sample from, with the nearest and linear samplers.
+fn create_bind_group(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::BindGroupLayout,
+    view: &wgpu::TextureView,
+    [nearest, linear]: [&wgpu::Sampler; 2],
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(label),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(nearest),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(linear),
+            },
+        ],
+    })
+}
+
+/// Create the pipelines for nearest and for sharp bilinear sampling, and for blurring.
+fn create_pipelines(
+    ctx: &super::Context,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+) -> Pipelines {
+    let create_pipeline = |label, fragment_entry_point, blend| {
+        ctx.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module,
+                    entry_point: "upscale_v",
+                    buffers: &[super::QUAD_LAYOUT, super::TEXCOORD_LAYOUT],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module,
+                    entry_point: fragment_entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: ctx.color_format,
+                        blend,
+                        write_mask: wgpu::ColorWrites::all(),
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleStrip,
+                    ..Default::default()
+                },
+                multisample: wgpu::MultisampleState::default(),
+                depth_stencil: None,
+                multiview: None,
+            })
+    };
+    // Colors are multiplied by the blend constant, replacing what's below.
+    let scaled = wgpu::BlendState {
+        color: wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Constant,
+            dst_factor: wgpu::BlendFactor::Zero,
+            operation: wgpu::BlendOperation::Add,
+        },
+        alpha: wgpu::BlendComponent::REPLACE,
+    };
+    Pipelines {
+        nearest: create_pipeline("upscale to surface pipeline", "upscale_f", None),
+        sharp: create_pipeline("sharp upscale to surface pipeline", "upscale_sharp_f", None),
+        blur: create_pipeline("blurred letterbox pipeline", "upscale_blur_f", Some(scaled)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(width: u32, height: u32) -> Size {
+        Size { width, height }
+    }
+
+    #[test]
+    fn hidden_canvases_have_no_visible_rect() {
+        let canvas = size(8, 6);
+        let modes = [
+            UpscaleMode::Integer,
+            UpscaleMode::Fit,
+            UpscaleMode::Stretch,
+            UpscaleMode::FillCrop,
+        ];
+        for mode in modes {
+            assert_eq!(visible_rect(&size(0, 0), &canvas, mode), None, "{mode:?}");
+            assert_eq!(visible_rect(&size(20, 0), &canvas, mode), None, "{mode:?}");
+        }
+        assert_eq!(
+            visible_rect(&size(7, 12), &canvas, UpscaleMode::Integer),
+            None
+        );
+        assert_eq!(
+            visible_rect(&size(7, 12), &canvas, UpscaleMode::Fit),
+            Some([0.0, 3.0, 7.0, 5.0])
+        );
+        assert_eq!(
+            visible_rect(&size(20, 14), &canvas, UpscaleMode::Integer),
+            Some([2.0, 1.0, 16.0, 12.0])
+        );
+    }
+}
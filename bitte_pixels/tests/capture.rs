@@ -0,0 +1,94 @@
+//! Screenshots and recordings, decoded again to check what was saved.
+#![cfg(feature = "capture")]
+
+mod harness;
+
+use graphics::{
+    capture::{Capture, Frame, Recorder},
+    BlendMode, Color, Rectangle, Scene, Size,
+};
+
+fn decode(data: &[u8]) -> png::Reader<&[u8]> {
+    png::Decoder::new(data).read_info().unwrap()
+}
+
+#[test]
+fn frames_match_readback() {
+    let size = Size {
+        width: 8,
+        height: 4,
+    };
+    let output = Size {
+        width: 24,
+        height: 12,
+    };
+    let Some(mut renderer) = harness::headless(size, Some(output)) else {
+        return;
+    };
+    let mut scene = Scene::default();
+    scene.rectangles.push(Rectangle {
+        position: [1, 1],
+        dimensions: [3, 2],
+        color: Color::srgb(1.0, 0.0, 0.0, 1.0),
+        blend: BlendMode::Alpha,
+    });
+    renderer.render(&[], &scene).unwrap();
+
+    let canvas = renderer.capture_frame(Capture::Canvas).unwrap();
+    assert_eq!((canvas.size.width, canvas.size.height), (8, 4));
+    assert_eq!(canvas.pixels, renderer.read_canvas().unwrap());
+    let upscaled = renderer.capture_frame(Capture::Upscaled).unwrap();
+    assert_eq!((upscaled.size.width, upscaled.size.height), (24, 12));
+    assert_eq!(upscaled.pixels, renderer.read_output().unwrap());
+
+    let mut png = Vec::new();
+    upscaled.write_png(&mut png).unwrap();
+    let mut reader = decode(&png);
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut pixels).unwrap();
+    assert_eq!(pixels, upscaled.pixels);
+}
+
+#[test]
+fn recordings_keep_the_last_seconds() {
+    let frame = |value| Frame {
+        size: Size {
+            width: 2,
+            height: 2,
+        },
+        pixels: vec![value; 16],
+    };
+    let mut recorder = Recorder::new(1.0, 10.0);
+    for i in 0..30 {
+        // Frames come in faster than the frame rate, and only every other one is kept.
+        recorder.push(i as f32 * 0.05, frame(i as u8));
+    }
+    assert_eq!(recorder.len(), 10);
+
+    let mut apng = Vec::new();
+    recorder.write_apng(&mut apng).unwrap();
+    let mut reader = decode(&apng);
+    let animation = reader.info().animation_control.unwrap();
+    assert_eq!(animation.num_frames, 10);
+    assert_eq!(animation.num_plays, 0);
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    for i in 0..10 {
+        reader.next_frame(&mut pixels).unwrap();
+        let control = reader.info().frame_control.unwrap();
+        assert_eq!((control.delay_num, control.delay_den), (100, 1000));
+        assert_eq!(pixels, frame(10 + i * 2).pixels);
+    }
+
+    // A different size starts over.
+    recorder.push(
+        2.0,
+        Frame {
+            size: Size {
+                width: 1,
+                height: 1,
+            },
+            pixels: vec![0; 4],
+        },
+    );
+    assert_eq!(recorder.len(), 1);
+}
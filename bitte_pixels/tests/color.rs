@@ -0,0 +1,33 @@
+use graphics::Color;
+
+#[test]
+fn straight_alpha_is_premultiplied() {
+    let color = Color::linear(1.0, 0.5, 0.0, 0.5);
+    assert_eq!(color.to_premultiplied(), [0.5, 0.25, 0.0, 0.5]);
+    assert_eq!(color.to_linear(), [1.0, 0.5, 0.0, 0.5]);
+    assert_eq!(Color::TRANSPARENT.to_linear(), [0.0; 4]);
+}
+
+#[test]
+fn hex_colors_are_srgb() {
+    let color = Color::from_hex("#ff8000").unwrap();
+    assert_eq!(color.to_srgb8(), [255, 128, 0, 255]);
+    let [_, g, _, _] = color.to_premultiplied();
+    assert!((g - 0.2158).abs() < 1e-3, "{g} isn't linear");
+
+    assert_eq!(
+        Color::from_hex("ff800080"),
+        Some(Color::srgb8(255, 128, 0, 128))
+    );
+    for bad in ["", "#", "#ff80", "#ff80000", "#gg8000", "#+f8000"] {
+        assert_eq!(Color::from_hex(bad), None, "{bad:?}");
+    }
+}
+
+#[test]
+fn srgb_bytes_round_trip() {
+    for value in 0..=255 {
+        let color = Color::srgb8(value, 255 - value, value / 2, 255);
+        assert_eq!(color.to_srgb8(), [value, 255 - value, value / 2, 255]);
+    }
+}
@@ -0,0 +1,77 @@
+//! Window positions mapped to canvas positions and back, checked against the upscaled image.
+
+mod harness;
+
+use graphics::{Color, PrimitiveVertex, Scene, Size, UpscaleMode};
+
+#[test]
+fn window_positions_pick_canvas_pixels() {
+    // An 8x6 canvas is scaled by 2 into a 20x14 window, leaving a letterbox of 2 by 1 pixels.
+    let canvas = Size {
+        width: 8,
+        height: 6,
+    };
+    let output = Size {
+        width: 20,
+        height: 14,
+    };
+    let Some(mut renderer) = harness::headless(canvas, Some(output)) else {
+        return;
+    };
+    let scene = Scene {
+        pixels: vec![PrimitiveVertex {
+            position: [3.0, 1.0],
+            color: Color::WHITE,
+        }],
+        ..Default::default()
+    };
+    renderer.render(&[], &scene).unwrap();
+    let pixels = renderer.read_output().unwrap();
+
+    let mut lit = Vec::new();
+    for y in 0..output.height {
+        for x in 0..output.width {
+            let center = [x as f32 + 0.5, y as f32 + 0.5];
+            if pixels[((y * output.width + x) * 4) as usize] == 255 {
+                lit.push((x, y));
+                assert_eq!(
+                    renderer.window_to_canvas(center).map(|p| p.map(f32::floor)),
+                    Some([3.0, 1.0])
+                );
+            }
+            let on_canvas = (2..18).contains(&x) && (1..13).contains(&y);
+            assert_eq!(renderer.is_on_canvas(center), on_canvas, "({x}, {y})");
+        }
+    }
+    assert_eq!(lit, [(8, 9), (9, 9), (8, 10), (9, 10)]);
+
+    assert_eq!(renderer.canvas_to_window([3.5, 1.5]), Some([9.0, 10.0]));
+    for position in [[0.0, 0.0], [2.25, 5.5], [8.0, 6.0]] {
+        let window = renderer.canvas_to_window(position).unwrap();
+        assert_eq!(renderer.window_to_canvas(window), Some(position));
+    }
+}
+
+#[test]
+fn windows_too_small_for_the_canvas_map_nothing() {
+    let canvas = Size {
+        width: 8,
+        height: 6,
+    };
+    let output = Size {
+        width: 7,
+        height: 12,
+    };
+    let Some(mut renderer) = harness::headless(canvas, Some(output)) else {
+        return;
+    };
+    // No whole number scale fits, so nothing of the canvas is shown.
+    assert_eq!(renderer.window_to_canvas([3.0, 6.0]), None);
+    assert_eq!(renderer.canvas_to_window([4.0, 3.0]), None);
+    assert!(!renderer.is_on_canvas([3.0, 6.0]));
+
+    renderer.set_upscale_mode(UpscaleMode::Fit);
+    assert_eq!(renderer.window_to_canvas([0.0, 3.0]), Some([0.0, 6.0]));
+    assert_eq!(renderer.canvas_to_window([8.0, 0.0]), Some([7.0, 8.0]));
+    assert!(renderer.is_on_canvas([3.0, 6.0]));
+}
@@ -0,0 +1,707 @@
+//! Golden-image tests locking down how primitives are rasterized.
+//! Run with `UPDATE_GOLDEN=1` to accept changes to the images in `tests/golden/`.
+
+mod harness;
+
+use graphics::{
+    material::{MaterialDescriptor, MaterialDraw},
+    postprocess::PostEffect,
+    sprite::{SpriteData, SpriteInstance},
+    text::{Font, Glyph, Text},
+    tilemap::TilemapInstance,
+    BlendMode, Circle, Color, Letterbox, LineStrip, PrimitiveVertex, Rectangle, RenderError,
+    Renderer, Scene, Size, UpscaleMode,
+};
+use harness::Tolerance;
+
+const WHITE: Color = Color::WHITE;
+const RED: Color = Color::premultiplied(1.0, 0.0, 0.0, 1.0);
+
+fn size(width: u32, height: u32) -> Size {
+    Size { width, height }
+}
+
+#[test]
+fn rectangle_outlines() {
+    let mut renderers = harness::Renderers::new(size(32, 16));
+    let mut scene = Scene::default();
+    for (i, dimensions) in [[1, 1], [2, 2], [3, 3], [5, 3], [6, 8]].iter().enumerate() {
+        scene.rectangles.push(Rectangle {
+            position: [1 + i as i32 * 6, 1],
+            dimensions: *dimensions,
+            color: WHITE,
+            blend: BlendMode::Alpha,
+        });
+    }
+    // Touching the canvas edges.
+    scene.rectangles.push(Rectangle {
+        position: [0, 10],
+        dimensions: [32, 6],
+        color: RED,
+        blend: BlendMode::Alpha,
+    });
+    harness::check(
+        &mut renderers,
+        &[],
+        &scene,
+        "rectangle_outlines",
+        Tolerance::EXACT,
+        Tolerance::SOFTWARE,
+    );
+}
+
+#[test]
+fn circles() {
+    let mut renderers = harness::Renderers::new(size(48, 16));
+    let mut scene = Scene::default();
+    let mut x = 1;
+    for diameter in 1..=8 {
+        scene.circles.push(Circle {
+            offset: [x, 1],
+            diameter,
+            color: if diameter % 2 == 0 { RED } else { WHITE },
+            blend: BlendMode::Alpha,
+        });
+        x += diameter as i32 + 1;
+    }
+    harness::check(
+        &mut renderers,
+        &[],
+        &scene,
+        "circles",
+        Tolerance::EXACT,
+        Tolerance::SOFTWARE,
+    );
+}
+
+#[test]
+fn pixels_and_lines() {
+    let mut renderers = harness::Renderers::new(size(16, 16));
+    let vertex = |x: f32, y: f32| PrimitiveVertex {
+        position: [x, y],
+        color: WHITE,
+    };
+    let scene = Scene {
+        pixels: vec![vertex(0.0, 0.0), vertex(15.0, 15.0), vertex(3.0, 12.0)],
+        linestrips: vec![
+            LineStrip {
+                points: vec![vertex(2.0, 2.0), vertex(13.0, 2.0), vertex(13.0, 9.0)],
+                blend: BlendMode::Alpha,
+            },
+            LineStrip {
+                points: vec![vertex(2.0, 4.0), vertex(10.0, 10.0)],
+                blend: BlendMode::Alpha,
+            },
+        ],
+        ..Default::default()
+    };
+    harness::check(
+        &mut renderers,
+        &[],
+        &scene,
+        "pixels_and_lines",
+        Tolerance::EXACT,
+        // Drivers disagree on whether a line that starts exactly on a pixel center draws it,
+        // like the vertical line here on llvmpipe.
+        Tolerance {
+            pixels: 1,
+            ..Tolerance::SOFTWARE
+        },
+    );
+}
+
+#[test]
+fn sprite_sampling() {
+    let mut renderers = harness::Renderers::new(size(32, 16));
+
+    // Sprites with odd sizes, where every pixel has a distinct color.
+    let mut builder = renderers.create_sprite_sheet_builder("golden");
+    let sprites: Vec<_> = [(1, 1), (3, 5), (7, 2), (4, 4), (5, 9)]
+        .into_iter()
+        .map(|(width, height)| {
+            let data = (0..width * height)
+                .flat_map(|i| [(i * 37 % 256) as u8, (i * 91 % 256) as u8, 255, 255])
+                .collect();
+            builder.add(SpriteData::new((width, height), (0, 0), data).unwrap())
+        })
+        .collect();
+    let sheet = builder.build();
+
+    let mut x = 1;
+    let mut scene = Scene::default();
+    for (sprite, width) in sprites.into_iter().zip([1, 3, 7, 4, 5]) {
+        scene.sprites.push(SpriteInstance {
+            position: [x, 1],
+            sprite,
+            blend: BlendMode::Alpha,
+        });
+        x += width + 1;
+    }
+    harness::check(
+        &mut renderers,
+        &[&sheet],
+        &scene,
+        "sprite_sampling",
+        Tolerance::EXACT,
+        Tolerance::SOFTWARE,
+    );
+}
+
+#[test]
+fn tiles_text_and_blending() {
+    let mut renderers = harness::Renderers::new(size(32, 24));
+
+    let mut builder = renderers.create_sprite_sheet_builder("golden");
+    let checker = |a: [u8; 4], b: [u8; 4]| {
+        let data = (0..16).flat_map(|i| if (i + i / 4) % 2 == 0 { a } else { b });
+        SpriteData::new((4, 4), (0, 0), data.collect()).unwrap()
+    };
+    let grass = builder.add(checker([40, 140, 40, 255], [30, 110, 30, 255]));
+    // Premultiplied, half transparent.
+    let water = builder.add(checker([20, 40, 128, 128], [10, 30, 100, 128]));
+    let glyph = builder.add(SpriteData::new((2, 3), (0, -1), vec![255; 24]).unwrap());
+    let sheet = builder.build();
+
+    let mut tilemap = renderers.create_tilemap((6, 4), (4, 4), 2);
+    tilemap.fill(0, Some(grass));
+    for x in 1..5 {
+        tilemap.set_tile(1, x, 1, Some(water));
+    }
+
+    let mut font = Font::new(5);
+    font.add_glyph(
+        'a',
+        Glyph {
+            sprite: Some(glyph),
+            advance: 3,
+        },
+    );
+
+    let mut scene = Scene::default();
+    scene.tilemaps.push(TilemapInstance {
+        tilemap: &tilemap,
+        position: [2, 3],
+        blend: BlendMode::Alpha,
+    });
+    scene.sprites.push(SpriteInstance {
+        position: [20, 14],
+        sprite: water,
+        blend: BlendMode::Alpha,
+    });
+    scene.rectangles.push(Rectangle {
+        position: [12, 8],
+        dimensions: [14, 10],
+        color: Color::premultiplied(0.25, 0.0, 0.0, 0.25),
+        blend: BlendMode::Alpha,
+    });
+    scene.texts.push(Text {
+        color: Color::premultiplied(0.5, 0.5, 0.0, 0.5),
+        max_width: Some(12),
+        ..Text::new(&font, "aaaa aa aaa", [1, 22])
+    });
+    harness::check(
+        &mut renderers,
+        &[&sheet],
+        &scene,
+        "tiles_text_and_blending",
+        Tolerance::EXACT,
+        Tolerance::SOFTWARE,
+    );
+}
+
+#[test]
+fn blend_modes() {
+    use BlendMode::*;
+    let modes = [Alpha, Additive, Multiply, Screen, Subtract];
+    let mut renderers = harness::Renderers::new(size(40, 20));
+
+    let mut builder = renderers.create_sprite_sheet_builder("blend");
+    let stripes = (0..40 * 20).flat_map(|i| {
+        if i % 40 < 20 {
+            [200, 120, 60, 255]
+        } else {
+            [40, 80, 160, 255]
+        }
+    });
+    let background = builder.add(SpriteData::new((40, 20), (0, 0), stripes.collect()).unwrap());
+    // Premultiplied, with an opaque top half and a half transparent bottom half.
+    let data = (0..36).flat_map(|i| {
+        if i < 18 {
+            [60, 200, 120, 255]
+        } else {
+            [30, 100, 60, 128]
+        }
+    });
+    let square = builder.add(SpriteData::new((6, 6), (0, 0), data.collect()).unwrap());
+    let sheet = builder.build();
+
+    let mut scene = Scene::default();
+    scene.sprites.push(SpriteInstance {
+        position: [0, 0],
+        sprite: background,
+        blend: Alpha,
+    });
+    for (i, blend) in modes.into_iter().enumerate() {
+        let x = 1 + i as i32 * 8;
+        scene.sprites.push(SpriteInstance {
+            position: [x, 13],
+            sprite: square,
+            blend,
+        });
+        scene.rectangles.push(Rectangle {
+            position: [x, 7],
+            dimensions: [6, 5],
+            color: Color::premultiplied(0.5, 0.25, 0.0, 0.5),
+            blend,
+        });
+        scene.circles.push(Circle {
+            offset: [x, 1],
+            diameter: 6,
+            color: Color::premultiplied(0.0, 0.5, 0.5, 1.0),
+            blend,
+        });
+    }
+    harness::check(
+        &mut renderers,
+        &[&sheet],
+        &scene,
+        "blend_modes",
+        Tolerance::EXACT,
+        Tolerance::SOFTWARE,
+    );
+}
+
+#[test]
+fn upscale_modes() {
+    // Upscaling only happens on the GPU, and one image stacks the output of every mode.
+    let output = size(21, 10);
+    let Some(mut renderer) = upscaling_renderer(output) else {
+        return;
+    };
+    let scene = gradient_scene();
+
+    let mut stacked = Vec::new();
+    let modes = [
+        UpscaleMode::Integer,
+        UpscaleMode::Fit,
+        UpscaleMode::Stretch,
+        UpscaleMode::FillCrop,
+    ];
+    for mode in modes {
+        renderer.set_upscale_mode(mode);
+        renderer.render(&[], &scene).unwrap();
+        stacked.extend(renderer.read_output().unwrap());
+    }
+    harness::assert_golden(
+        "upscale_modes",
+        size(output.width, output.height * modes.len() as u32),
+        &stacked,
+        // Bilinear filtering isn't equally precise on every GPU.
+        Tolerance {
+            channel: 2,
+            pixels: 0,
+        },
+    );
+}
+
+#[test]
+fn background() {
+    let mut renderers = harness::Renderers::new(size(8, 6));
+    let scene = Scene {
+        background: Color::srgb8(40, 60, 90, 255),
+        rectangles: vec![Rectangle {
+            position: [1, 1],
+            dimensions: [6, 4],
+            color: Color::premultiplied(0.5, 0.25, 0.0, 0.5),
+            blend: BlendMode::Alpha,
+        }],
+        ..Default::default()
+    };
+    harness::check(
+        &mut renderers,
+        &[],
+        &scene,
+        "background",
+        Tolerance::EXACT,
+        Tolerance::SOFTWARE,
+    );
+}
+
+#[test]
+fn canvas_size() {
+    let mut renderers = harness::Renderers::new(size(8, 6));
+    renderers.set_canvas_size(size(12, 9));
+    let scene = Scene {
+        rectangles: vec![Rectangle {
+            position: [0, 0],
+            dimensions: [12, 9],
+            color: RED,
+            blend: BlendMode::Alpha,
+        }],
+        pixels: vec![PrimitiveVertex {
+            position: [11.0, 8.0],
+            color: WHITE,
+        }],
+        ..Default::default()
+    };
+    harness::check(
+        &mut renderers,
+        &[],
+        &scene,
+        "canvas_size",
+        Tolerance::EXACT,
+        Tolerance::SOFTWARE,
+    );
+}
+
+#[test]
+fn canvas_size_upscaling() {
+    // A 12x9 canvas fills a 24x18 output at twice its size, with every pixel doubled.
+    let Some(mut renderer) = upscaling_renderer(size(24, 18)) else {
+        return;
+    };
+    assert!(renderer.set_canvas_size(size(0, 9)).is_err());
+    renderer.set_canvas_size(size(12, 9)).unwrap();
+    let canvas_size = renderer.canvas_size();
+    assert_eq!((canvas_size.width, canvas_size.height), (12, 9));
+
+    let mut scene = Scene::default();
+    for (i, y) in (0..9).enumerate() {
+        scene.pixels.push(PrimitiveVertex {
+            position: [(i % 12) as f32, y as f32],
+            color: WHITE,
+        });
+    }
+    renderer.render(&[], &scene).unwrap();
+    let canvas = renderer.read_canvas().unwrap();
+    let output = renderer.read_output().unwrap();
+    for y in 0..18 {
+        for x in 0..24 {
+            let scaled = (y / 2 * 12 + x / 2) * 4;
+            let upscaled = (y * 24 + x) * 4;
+            assert_eq!(
+                canvas[scaled..scaled + 4],
+                output[upscaled..upscaled + 4],
+                "output pixel ({x}, {y})"
+            );
+        }
+    }
+}
+
+#[test]
+fn render_targets() {
+    let mut renderers = harness::Renderers::new(size(8, 6));
+    // A translucent 4x3 target with its bottom left and top right corners marked,
+    // so the image shows whether it's drawn upright.
+    let mut target = renderers.create_render_target("minimap", size(4, 3));
+    let corners = Scene {
+        background: Color::premultiplied(0.0, 0.0, 0.5, 0.5),
+        pixels: vec![
+            PrimitiveVertex {
+                position: [0.0, 0.0],
+                color: RED,
+            },
+            PrimitiveVertex {
+                position: [3.0, 2.0],
+                color: WHITE,
+            },
+        ],
+        ..Default::default()
+    };
+    renderers.render_to_target(&mut target, &[], &corners);
+
+    let scene = Scene {
+        background: Color::srgb8(40, 90, 60, 255),
+        sprites: vec![
+            SpriteInstance {
+                position: [0, 0],
+                sprite: target.sprite(),
+                blend: BlendMode::Alpha,
+            },
+            SpriteInstance {
+                position: [3, 2],
+                sprite: target.sprite(),
+                blend: BlendMode::Additive,
+            },
+        ],
+        ..Default::default()
+    };
+    harness::check(
+        &mut renderers,
+        &[target.sheet()],
+        &scene,
+        "render_targets",
+        Tolerance::EXACT,
+        Tolerance::SOFTWARE,
+    );
+}
+
+#[test]
+fn letterbox() {
+    // Letterboxes only exist on the GPU, and one image stacks every kind.
+    let output = size(21, 10);
+    let Some(mut renderer) = upscaling_renderer(output) else {
+        return;
+    };
+    let scene = gradient_scene();
+
+    let border = (0..4 * 2)
+        .flat_map(|i| [(i * 32) as u8, 64, (255 - i * 32) as u8, 255])
+        .collect();
+    let letterboxes = [
+        Letterbox::Color(Color::srgb8(90, 30, 60, 255)),
+        Letterbox::Image(SpriteData::new((4, 2), (0, 0), border).unwrap()),
+        Letterbox::BlurredCanvas { brightness: 0.5 },
+    ];
+    let mut stacked = Vec::new();
+    let count = letterboxes.len() as u32;
+    for letterbox in letterboxes {
+        renderer.set_letterbox(letterbox).unwrap();
+        renderer.render(&[], &scene).unwrap();
+        stacked.extend(renderer.read_output().unwrap());
+    }
+    // Images larger than a texture can be are rejected, keeping the blurred canvas.
+    let wide = SpriteData::new((9000, 1), (0, 0), vec![255; 9000 * 4]).unwrap();
+    assert!(renderer.set_letterbox(Letterbox::Image(wide)).is_err());
+    renderer.render(&[], &scene).unwrap();
+    assert_eq!(
+        renderer.read_output().unwrap(),
+        stacked[stacked.len() / count as usize * (count as usize - 1)..]
+    );
+
+    harness::assert_golden(
+        "letterbox",
+        size(output.width, output.height * count),
+        &stacked,
+        // Bilinear filtering isn't equally precise on every GPU.
+        Tolerance {
+            channel: 2,
+            pixels: 0,
+        },
+    );
+}
+
+#[test]
+fn post_effects() {
+    let output = size(24, 18);
+    let Some(mut renderer) = upscaling_renderer(output) else {
+        return;
+    };
+    let scene = gradient_scene();
+    let invert = renderer
+        .create_post_shader(
+            "invert",
+            "@fragment
+            fn post_f(in: PostInter) -> @location(0) vec4<f32> {
+                let color = textureSample(source, source_sampler, in.uv);
+                return vec4<f32>(mix(color.rgb, 1.0 - color.rgb, post.params.x), color.a);
+            }",
+        )
+        .unwrap();
+    assert!(renderer
+        .create_post_shader("broken", "fn post_f() -> f32 { return; }")
+        .is_err());
+
+    let chains = [
+        vec![
+            PostEffect::Scanlines { intensity: 0.5 },
+            PostEffect::ShadowMask { intensity: 0.5 },
+        ],
+        vec![
+            PostEffect::Curvature { amount: 0.2 },
+            PostEffect::Vignette { intensity: 0.8 },
+        ],
+        vec![
+            PostEffect::ChromaticAberration { offset: 2.0 },
+            PostEffect::Bloom {
+                threshold: 0.5,
+                intensity: 1.0,
+                radius: 3.0,
+            },
+        ],
+        vec![PostEffect::Custom {
+            shader: invert,
+            params: [1.0, 0.0, 0.0, 0.0],
+        }],
+    ];
+    let mut stacked = Vec::new();
+    for chain in &chains {
+        *renderer.post_effects_mut() = chain.clone();
+        renderer.render(&[], &scene).unwrap();
+        stacked.extend(renderer.read_output().unwrap());
+    }
+    harness::assert_golden(
+        "post_effects",
+        size(output.width, output.height * chains.len() as u32),
+        &stacked,
+        // Filtering and transcendental functions aren't equally precise on every GPU.
+        Tolerance {
+            channel: 2,
+            pixels: 0,
+        },
+    );
+}
+
+/// A headless renderer with an 8x6 canvas, upscaled to `output`.
+fn upscaling_renderer(output: Size) -> Option<Renderer<'static>> {
+    harness::headless(size(8, 6), Some(output))
+}
+
+/// Fills an 8x6 canvas with pixels of distinct colors.
+fn gradient_scene() -> Scene<'static> {
+    let mut scene = Scene::default();
+    for y in 0..6 {
+        for x in 0..8 {
+            let color = Color::linear(x as f32 / 7.0, y as f32 / 5.0, ((x + y) % 2) as f32, 1.0);
+            scene.pixels.push(PrimitiveVertex {
+                position: [x as f32, y as f32],
+                color,
+            });
+        }
+    }
+    scene
+}
+
+#[test]
+fn materials() {
+    let canvas = size(24, 12);
+    let Some(mut renderer) = harness::headless(canvas, None) else {
+        return;
+    };
+    let mut builder = renderer.create_sprite_sheet_builder("materials");
+    let data = (0..16)
+        .flat_map(|i| [(i * 16) as u8, (255 - i * 16) as u8, 128, 255])
+        .collect();
+    let sprite = builder.add(SpriteData::new((4, 4), (0, 0), data).unwrap());
+    let sheet = builder.build();
+    let sprites = |x| {
+        vec![SpriteInstance {
+            position: [x, 1],
+            sprite,
+            blend: BlendMode::Alpha,
+        }]
+    };
+
+    // A material that only calls `sprite_color` draws sprites as if it weren't there.
+    let plain = renderer
+        .create_material(MaterialDescriptor {
+            label: "plain",
+            source: "@fragment
+            fn material_f(in: MaterialInter) -> @location(0) vec4<f32> {
+                return sprite_color(in);
+            }",
+            uniforms: &[],
+        })
+        .unwrap();
+    let scene = Scene {
+        sprites: sprites(1),
+        ..Default::default()
+    };
+    renderer.render(&[&sheet], &scene).unwrap();
+    let expected = renderer.read_canvas().unwrap();
+    assert!(matches!(renderer.read_output(), Err(RenderError::NoOutput)));
+    let mut scene = Scene::default();
+    scene.material_draws.push(MaterialDraw {
+        material: &plain,
+        sprites: sprites(1),
+        rectangles: Vec::new(),
+    });
+    renderer.render(&[&sheet], &scene).unwrap();
+    assert_eq!(renderer.read_canvas().unwrap(), expected);
+
+    // Draws past the instance buffers are dropped, rather than overflowing them.
+    let many_sprites: Vec<_> = sprites(1).into_iter().cycle().take(5000).collect();
+    scene.material_draws[0].sprites = many_sprites.clone();
+    scene.material_draws.push(MaterialDraw {
+        material: &plain,
+        sprites: many_sprites,
+        rectangles: Vec::new(),
+    });
+    renderer.render(&[&sheet], &scene).unwrap();
+    assert_eq!(renderer.read_canvas().unwrap(), expected);
+
+    let flash = renderer
+        .create_material(MaterialDescriptor {
+            label: "hit flash",
+            source: "struct MaterialUniforms { amount: f32 }
+            @fragment
+            fn material_f(in: MaterialInter) -> @location(0) vec4<f32> {
+                let color = sprite_color(in);
+                return vec4<f32>(mix(color.rgb, vec3<f32>(color.a), material.amount), color.a);
+            }",
+            uniforms: bytemuck::bytes_of(&0.0f32),
+        })
+        .unwrap();
+    flash.set_uniforms(bytemuck::bytes_of(&0.5f32));
+    let gradient = renderer
+        .create_material(MaterialDescriptor {
+            label: "gradient",
+            source: "@fragment
+            fn material_f(in: MaterialInter) -> @location(0) vec4<f32> {
+                let uv = in.local / vec2<f32>(in.size);
+                return vec4<f32>(uv, 0.0, 1.0) * in.color.a;
+            }",
+            uniforms: &[],
+        })
+        .unwrap();
+    let shear = renderer
+        .create_material(MaterialDescriptor {
+            label: "shear",
+            source: "fn material_vertex(v: MaterialVertex) -> MaterialInter {
+                var out = default_material_vertex(v);
+                out.position.y += v.corner.x * 4.0 / f32(canvas_info.dimensions.y);
+                return out;
+            }
+            @fragment
+            fn material_f(in: MaterialInter) -> @location(0) vec4<f32> {
+                return in.color;
+            }",
+            uniforms: &[],
+        })
+        .unwrap();
+    assert!(renderer
+        .create_material(MaterialDescriptor {
+            label: "broken",
+            source: "fn material_f() -> f32 { return; }",
+            uniforms: &[],
+        })
+        .is_err());
+
+    let scene = Scene {
+        material_draws: vec![
+            MaterialDraw {
+                material: &flash,
+                sprites: sprites(1),
+                rectangles: Vec::new(),
+            },
+            MaterialDraw {
+                material: &gradient,
+                sprites: Vec::new(),
+                rectangles: vec![Rectangle {
+                    position: [7, 1],
+                    dimensions: [8, 8],
+                    color: WHITE,
+                    blend: BlendMode::Alpha,
+                }],
+            },
+            MaterialDraw {
+                material: &shear,
+                sprites: Vec::new(),
+                rectangles: vec![Rectangle {
+                    position: [17, 1],
+                    dimensions: [6, 6],
+                    color: RED,
+                    blend: BlendMode::Alpha,
+                }],
+            },
+        ],
+        ..Default::default()
+    };
+    renderer.render(&[&sheet], &scene).unwrap();
+    harness::assert_golden(
+        "materials",
+        canvas,
+        &renderer.read_canvas().unwrap(),
+        Tolerance::SOFTWARE,
+    );
+}
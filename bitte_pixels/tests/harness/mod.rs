@@ -0,0 +1,243 @@
+//! Golden-image testing: scenes are rendered headlessly and compared with checked-in PNGs.
+//!
+//! Golden images live in `tests/golden/<name>.png`.
+//! Images are only written when the `UPDATE_GOLDEN` environment variable is set,
+//! and tests fail if their image is missing otherwise.
+//! On a mismatch, the actual image and a diff image are written to `target/golden/`,
+//! with differing pixels in red over a faded copy of the expected image.
+//!
+//! Every scene is also drawn by the software renderer and compared with the same golden image,
+//! which never updates it.
+//! The GPU half of a test is skipped with a warning if no adapter, not even a software one, is available.
+
+// Every test binary uses a different part of the harness.
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+use graphics::{
+    software,
+    sprite::{SpriteSheet, SpriteSheetBuilder},
+    tilemap::Tilemap,
+    RenderError, RenderTarget, Renderer, Scene, Size,
+};
+
+/// How far an image may deviate from its golden image.
+#[derive(Clone, Copy, Debug)]
+pub struct Tolerance {
+    /// Largest difference allowed in any channel of a pixel.
+    pub channel: u8,
+    /// Number of pixels allowed to differ by more than `channel`.
+    pub pixels: usize,
+}
+
+impl Tolerance {
+    pub const EXACT: Tolerance = Tolerance {
+        channel: 0,
+        pixels: 0,
+    };
+
+    /// The software renderer rounds to sRGB exactly, which GPUs may not.
+    pub const SOFTWARE: Tolerance = Tolerance {
+        channel: 1,
+        pixels: 0,
+    };
+}
+
+/// The headless GPU renderer, if the system has an adapter, and the software renderer.
+pub struct Renderers {
+    pub gpu: Option<Renderer<'static>>,
+    pub software: software::Renderer,
+}
+
+/// A headless GPU renderer, or `None` with a warning if the system has no adapter,
+/// in which case the test should be skipped.
+/// Panics if the renderer can't be created for any other reason.
+pub fn headless(canvas: Size, output: Option<Size>) -> Option<Renderer<'static>> {
+    match pollster::block_on(Renderer::new_headless(canvas, output)) {
+        Ok(renderer) => Some(renderer),
+        Err(RenderError::AcquireAdapter) => {
+            eprintln!("warning: no adapter available, skipping the GPU renderer");
+            None
+        }
+        Err(err) => panic!("couldn't create headless renderer: {err}"),
+    }
+}
+
+impl Renderers {
+    pub fn new(size: Size) -> Self {
+        Self {
+            gpu: headless(size, None),
+            software: software::Renderer::new(size),
+        }
+    }
+
+    /// Start a sheet that both renderers can draw.
+    /// The software renderer reads sprites from memory, so GPU sheets work for both.
+    pub fn create_sprite_sheet_builder<'a>(&'a mut self, name: &'a str) -> SpriteSheetBuilder<'a> {
+        match &mut self.gpu {
+            Some(gpu) => gpu.create_sprite_sheet_builder(name),
+            None => self.software.create_sprite_sheet_builder(name),
+        }
+    }
+
+    /// Create a tilemap that both renderers can draw.
+    pub fn create_tilemap(
+        &self,
+        size: (u32, u32),
+        tile_size: (u32, u32),
+        layers: usize,
+    ) -> Tilemap {
+        match &self.gpu {
+            Some(gpu) => gpu.create_tilemap(size, tile_size, layers),
+            None => self.software.create_tilemap(size, tile_size, layers),
+        }
+    }
+
+    /// Create a render target that both renderers can draw to and draw.
+    pub fn create_render_target(&self, name: &str, size: Size) -> RenderTarget {
+        match &self.gpu {
+            Some(gpu) => gpu.create_render_target(name, size),
+            None => self.software.create_render_target(name, size),
+        }
+        .unwrap()
+    }
+
+    /// Render a scene to a target with both renderers,
+    /// which update its texture and its copy in memory respectively.
+    pub fn render_to_target(
+        &mut self,
+        target: &mut RenderTarget,
+        sprite_sheets: &[&SpriteSheet],
+        scene: &Scene,
+    ) {
+        if let Some(gpu) = &mut self.gpu {
+            gpu.render_to_target(target, sprite_sheets, scene).unwrap();
+        }
+        self.software
+            .render_to_target(target, sprite_sheets, scene)
+            .unwrap();
+    }
+
+    /// Change the canvas size of both renderers.
+    pub fn set_canvas_size(&mut self, size: Size) {
+        if let Some(gpu) = &mut self.gpu {
+            gpu.set_canvas_size(size).unwrap();
+        }
+        self.software.set_canvas_size(size).unwrap();
+    }
+}
+
+/// Render a scene with both renderers and compare their canvases with the golden image `name`.
+/// Only the GPU renderer writes golden images.
+pub fn check(
+    renderers: &mut Renderers,
+    sprite_sheets: &[&SpriteSheet],
+    scene: &Scene,
+    name: &str,
+    tolerance: Tolerance,
+    software_tolerance: Tolerance,
+) {
+    let size = renderers.software.canvas_size();
+    if let Some(gpu) = &mut renderers.gpu {
+        gpu.render(sprite_sheets, scene).unwrap();
+        assert_golden(name, size, &gpu.read_canvas().unwrap(), tolerance);
+    }
+
+    let software = &mut renderers.software;
+    software.render(sprite_sheets, scene).unwrap();
+    let golden = golden_path(name);
+    assert!(golden.exists(), "{name}: golden image is missing");
+    compare(
+        &format!("{name}.software"),
+        &golden,
+        size,
+        software.canvas(),
+        software_tolerance,
+    );
+}
+
+/// Compare RGBA pixels, top-to-bottom, with the golden image `name`.
+pub fn assert_golden(name: &str, size: Size, actual: &[u8], tolerance: Tolerance) {
+    let golden = golden_path(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        write_png(&golden, size, actual);
+        eprintln!("wrote golden image {golden:?}");
+        return;
+    }
+    assert!(golden.exists(), "{name}: golden image is missing");
+    compare(name, &golden, size, actual, tolerance);
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name)
+        .with_extension("png")
+}
+
+fn compare(name: &str, golden: &PathBuf, size: Size, actual: &[u8], tolerance: Tolerance) {
+    let (expected_size, expected) = read_png(golden);
+    assert_eq!(
+        (expected_size.width, expected_size.height),
+        (size.width, size.height),
+        "{name}: size differs from golden image"
+    );
+
+    let mut differing = 0;
+    let mut diff = Vec::with_capacity(actual.len());
+    for (actual, expected) in actual.chunks_exact(4).zip(expected.chunks_exact(4)) {
+        let distance = actual
+            .iter()
+            .zip(expected)
+            .map(|(a, e)| a.abs_diff(*e))
+            .max()
+            .unwrap();
+        if distance > tolerance.channel {
+            differing += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            diff.extend(expected[..3].iter().map(|c| c / 4));
+            diff.push(255);
+        }
+    }
+
+    if differing > tolerance.pixels {
+        let out = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/golden");
+        std::fs::create_dir_all(&out).unwrap();
+        let actual_path = out.join(format!("{name}.actual.png"));
+        let diff_path = out.join(format!("{name}.diff.png"));
+        write_png(&actual_path, size, actual);
+        write_png(&diff_path, size, &diff);
+        panic!(
+            "{name}: {differing} pixels differ from golden image (tolerance {tolerance:?}), \
+             see {actual_path:?} and {diff_path:?}"
+        );
+    }
+}
+
+fn read_png(path: &PathBuf) -> (Size, Vec<u8>) {
+    let decoder = png::Decoder::new(std::fs::File::open(path).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).unwrap();
+    assert_eq!(info.color_type, png::ColorType::Rgba, "{path:?} isn't RGBA");
+    data.truncate(info.buffer_size());
+    let size = Size {
+        width: info.width,
+        height: info.height,
+    };
+    (size, data)
+}
+
+fn write_png(path: &Path, size: Size, data: &[u8]) {
+    let file = std::fs::File::create(path).unwrap();
+    let mut encoder = png::Encoder::new(file, size.width, size.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .unwrap()
+        .write_image_data(data)
+        .unwrap();
+}
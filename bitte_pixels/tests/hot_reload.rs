@@ -0,0 +1,95 @@
+//! Materials loaded from files, edited while the renderer runs.
+#![cfg(feature = "hot-reload")]
+
+mod harness;
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use graphics::{material::MaterialDraw, BlendMode, Color, Rectangle, Renderer, Scene, Size};
+
+/// Longer than the renderer waits between checking files.
+const POLL_WAIT: Duration = Duration::from_millis(400);
+
+fn fill_shader(color: &str) -> String {
+    format!(
+        "@fragment
+        fn material_f(in: MaterialInter) -> @location(0) vec4<f32> {{
+            return vec4<f32>({color}, 1.0);
+        }}"
+    )
+}
+
+/// A temporary directory, removed when dropped so that failing tests don't leave it behind.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("{name}_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Overwrite a file, with a modification time that is `edit` seconds after the epoch.
+/// Edits get distinct times even on file systems with coarse timestamps.
+fn edit(path: &Path, contents: &str, edit: u64) {
+    std::fs::write(path, contents).unwrap();
+    let file = std::fs::File::options().write(true).open(path).unwrap();
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(edit);
+    file.set_modified(modified).unwrap();
+}
+
+#[test]
+fn materials_reload_when_their_file_changes() {
+    let size = Size {
+        width: 4,
+        height: 4,
+    };
+    let Some(mut renderer) = harness::headless(size, None) else {
+        return;
+    };
+    let dir = TempDir::new("bitte_hot_reload");
+    let path = dir.0.join("fill.wgsl");
+    edit(&path, &fill_shader("1.0, 0.0, 0.0"), 1);
+
+    let material = renderer.load_material(&path, &[]).unwrap();
+    assert!(renderer
+        .load_material(dir.0.join("missing.wgsl"), &[])
+        .is_err());
+    let scene = Scene {
+        material_draws: vec![MaterialDraw {
+            material: &material,
+            sprites: Vec::new(),
+            rectangles: vec![Rectangle {
+                position: [0, 0],
+                dimensions: [4, 4],
+                color: Color::WHITE,
+                blend: BlendMode::Alpha,
+            }],
+        }],
+        ..Default::default()
+    };
+    let render = |renderer: &mut Renderer| {
+        renderer.render(&[], &scene).unwrap();
+        renderer.read_canvas().unwrap()[..4].to_vec()
+    };
+    assert_eq!(render(&mut renderer), [255, 0, 0, 255]);
+
+    std::thread::sleep(POLL_WAIT);
+    edit(&path, &fill_shader("0.0, 1.0, 0.0"), 2);
+    assert_eq!(render(&mut renderer), [0, 255, 0, 255]);
+
+    // A broken edit keeps the last version that compiled.
+    std::thread::sleep(POLL_WAIT);
+    edit(&path, "fn material_f() -> f32 { return; }", 3);
+    assert_eq!(render(&mut renderer), [0, 255, 0, 255]);
+}
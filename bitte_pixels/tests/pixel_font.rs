@@ -0,0 +1,122 @@
+//! Bitmap fonts loaded from the BDF and PCF fixtures in `tests/fixtures/fonts`,
+//! which hold the same glyphs.
+
+use std::path::PathBuf;
+
+use graphics::{
+    pixel_font::{FontFileError, PixelFont},
+    software, Size,
+};
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/fonts")
+        .join(name)
+}
+
+/// Glyph bitmaps as rows of `#` and `.`, from the top.
+fn rows(font: &PixelFont, c: char) -> Vec<String> {
+    let glyph = font.glyphs.iter().find(|glyph| glyph.char == c).unwrap();
+    let width = glyph.dimensions.0 as usize;
+    glyph
+        .bits
+        .chunks(width)
+        .map(|row| row.iter().map(|&set| if set { '#' } else { '.' }).collect())
+        .collect()
+}
+
+fn check_tiny(font: &PixelFont) {
+    assert_eq!((font.ascent, font.descent), (4, 1));
+    assert_eq!(font.default_char, Some('A'));
+    // Glyphs without an encoding, or without metrics, are left out.
+    let chars: Vec<_> = font.glyphs.iter().map(|glyph| glyph.char).collect();
+    assert_eq!(chars, ['A', 'C']);
+
+    let a = &font.glyphs[0];
+    assert_eq!((a.dimensions, a.offset, a.advance), ((3, 4), (0, 0), 4));
+    assert_eq!(rows(font, 'A'), [".#.", "#.#", "###", "#.#"]);
+    let hook = &font.glyphs[1];
+    assert_eq!(
+        (hook.dimensions, hook.offset, hook.advance),
+        ((2, 3), (0, -1), 3)
+    );
+    assert_eq!(rows(font, 'C'), ["##", "##", ".#"]);
+}
+
+#[test]
+fn bdf_glyphs_and_metrics() {
+    check_tiny(&PixelFont::load(fixture("tiny.bdf")).unwrap());
+}
+
+#[test]
+fn pcf_glyphs_and_metrics() {
+    check_tiny(&PixelFont::load(fixture("tiny.pcf")).unwrap());
+}
+
+#[test]
+fn fonts_measure_text_with_their_default_char() {
+    let font = PixelFont::load(fixture("tiny.pcf")).unwrap();
+    let renderer = software::Renderer::new(Size {
+        width: 1,
+        height: 1,
+    });
+    let mut builder = renderer.create_sprite_sheet_builder("tiny");
+    let font = font.to_font(&mut builder);
+    assert_eq!(font.line_height(), 5);
+    assert_eq!(font.glyph('A').unwrap().advance, 4);
+    assert_eq!(font.measure("AxC", None), (11, 5));
+}
+
+#[test]
+fn malformed_bdf_is_rejected() {
+    let source = std::fs::read_to_string(fixture("tiny.bdf")).unwrap();
+    let parse = |source: &str| PixelFont::parse_bdf(source).err();
+    assert!(matches!(
+        parse("STARTCHAR A\n"),
+        Some(FontFileError::Format)
+    ));
+    let corrupt = |from: &str, to: &str| match parse(&source.replacen(from, to, 1)) {
+        Some(FontFileError::Corrupt(err)) => err,
+        err => panic!("replacing {from:?} gave {err:?}"),
+    };
+    assert_eq!(corrupt("BBX 3 4 0 0", "BBX 3 x 0 0"), "bad number");
+    assert_eq!(corrupt("E0\n", "EZ\n"), "bad bitmap row");
+    assert_eq!(corrupt("BBX 3 4 0 0", "BBX 3 5 0 0"), "bad bitmap row");
+    assert_eq!(
+        corrupt("BBX 3 4 0 0", "BBX -3 4 0 0"),
+        "bitmap doesn't match bounding box"
+    );
+    let truncated = &source[..source.find("E0\n").unwrap()];
+    assert!(matches!(
+        parse(truncated),
+        Some(FontFileError::Corrupt("bitmap ended early"))
+    ));
+}
+
+#[test]
+fn malformed_pcf_is_rejected() {
+    let data = std::fs::read(fixture("tiny.pcf")).unwrap();
+    assert!(matches!(
+        PixelFont::parse_pcf(&data[1..]),
+        Err(FontFileError::Format)
+    ));
+    // Cutting the file anywhere fails cleanly.
+    for len in 0..data.len() {
+        assert!(PixelFont::parse_pcf(&data[..len]).is_err(), "{len} bytes");
+    }
+
+    let mut missing = data.clone();
+    // Only the first three tables, leaving out the encodings.
+    missing[4] = 3;
+    assert!(matches!(
+        PixelFont::parse_pcf(&missing),
+        Err(FontFileError::Corrupt("missing table"))
+    ));
+    let mut outside = data.clone();
+    // The offset of the first table.
+    outside[20..24].copy_from_slice(&0x1000u32.to_le_bytes());
+    assert!(matches!(
+        PixelFont::parse_pcf(&outside),
+        Err(FontFileError::Corrupt("table outside of file"))
+    ));
+}
@@ -0,0 +1,250 @@
+use graphics::{
+    software,
+    sprite::{
+        HandleError, InvalidSpritePolicy, PackedSpriteSheet, SheetFileError, SpriteData,
+        SpriteHandle, SpriteInstance, SpriteSheet,
+    },
+    BlendMode, RenderError, Scene, Size,
+};
+
+/// A sprite filled with a single opaque color.
+fn solid(width: u32, height: u32, color: [u8; 3]) -> SpriteData {
+    let [r, g, b] = color;
+    let data = [r, g, b, 255].repeat((width * height) as usize);
+    SpriteData::new((width, height), (0, 0), data).unwrap()
+}
+
+fn renderer() -> software::Renderer {
+    software::Renderer::new(Size {
+        width: 4,
+        height: 4,
+    })
+}
+
+fn packed(renderer: &software::Renderer) -> PackedSpriteSheet {
+    let mut builder = renderer.create_sprite_sheet_builder("cached");
+    builder.add_named("square", solid(2, 2, [255, 0, 0]));
+    builder.add_named_animation(
+        "blink",
+        vec![solid(1, 3, [0, 255, 0]), solid(3, 1, [0, 0, 255])],
+    );
+    builder.pack()
+}
+
+fn written(packed: &PackedSpriteSheet) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    packed.write(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn sheets_round_trip_through_files() {
+    let mut renderer = renderer();
+    let bytes = written(&packed(&renderer));
+    let read = PackedSpriteSheet::read(bytes.as_slice()).unwrap();
+    assert_eq!(written(&read).len(), bytes.len());
+
+    let sheet = renderer.load_sprite_sheet("cached", read).unwrap();
+    let square = sheet.sprite("square").unwrap();
+    assert_eq!(sheet.dimensions(square), Ok((2, 2)));
+    let blink = sheet.animation("blink").unwrap();
+    assert_eq!(sheet.dimensions(blink.get_frame(0)), Ok((1, 3)));
+    assert_eq!(sheet.dimensions(blink.get_frame(1)), Ok((3, 1)));
+
+    let scene = Scene {
+        sprites: vec![SpriteInstance {
+            position: [1, 1],
+            sprite: square,
+            blend: BlendMode::Alpha,
+        }],
+        ..Default::default()
+    };
+    renderer.render(&[&sheet], &scene).unwrap();
+    let red: Vec<_> = renderer
+        .canvas()
+        .chunks_exact(4)
+        .map(|pixel| pixel == [255, 0, 0, 255])
+        .collect();
+    #[rustfmt::skip]
+    assert_eq!(red, [
+        false, false, false, false,
+        false, true, true, false,
+        false, true, true, false,
+        false, false, false, false,
+    ]);
+}
+
+#[test]
+fn bad_magic_is_rejected() {
+    let mut bytes = written(&packed(&renderer()));
+    bytes[..4].copy_from_slice(b"PNG\0");
+    let err = PackedSpriteSheet::read(bytes.as_slice()).err().unwrap();
+    assert!(matches!(err, SheetFileError::Format), "{err}");
+}
+
+#[test]
+fn other_versions_are_rejected() {
+    let mut bytes = written(&packed(&renderer()));
+    bytes[4..8].copy_from_slice(&99u32.to_le_bytes());
+    let err = PackedSpriteSheet::read(bytes.as_slice()).err().unwrap();
+    assert!(matches!(err, SheetFileError::Version(99)), "{err}");
+}
+
+#[test]
+fn truncated_files_are_rejected() {
+    let bytes = written(&packed(&renderer()));
+    for len in [0, 3, 10, 30, bytes.len() / 2, bytes.len() - 1] {
+        let err = PackedSpriteSheet::read(&bytes[..len]).err().unwrap();
+        assert!(matches!(err, SheetFileError::Io(_)), "{len} bytes: {err}");
+    }
+}
+
+#[test]
+fn corrupt_lengths_fail_without_allocating_them() {
+    // A name or a page whose length claims gigabytes of data that isn't there.
+    let header = |name_count: u32, page_count: u32| {
+        let mut bytes = b"BTSS".to_vec();
+        for value in [2, page_count, 0, name_count] {
+            bytes.extend_from_slice(&u32::to_le_bytes(value));
+        }
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes
+    };
+    for bytes in [header(1, 0), header(0, 1)] {
+        let err = PackedSpriteSheet::read(bytes.as_slice()).err().unwrap();
+        assert!(matches!(err, SheetFileError::Io(_)), "{err}");
+    }
+}
+
+/// Pixels of the 4x4 canvas after drawing a sprite at the origin, from the top left.
+fn drawn(
+    renderer: &mut software::Renderer,
+    sheet: &SpriteSheet,
+    sprite: SpriteHandle,
+) -> Vec<[u8; 4]> {
+    let scene = Scene {
+        sprites: vec![SpriteInstance {
+            position: [0, 0],
+            sprite,
+            blend: BlendMode::Alpha,
+        }],
+        ..Default::default()
+    };
+    renderer.render(&[sheet], &scene).unwrap();
+    renderer
+        .canvas()
+        .chunks_exact(4)
+        .map(|pixel| pixel.try_into().unwrap())
+        .collect()
+}
+
+/// A sheet with three 4-pixel sprites, packed into a page with no room left.
+fn full_sheet(renderer: &software::Renderer) -> (SpriteSheet, [SpriteHandle; 3]) {
+    let mut builder = renderer.create_sprite_sheet_builder("runtime");
+    let sprites = [
+        builder.add(solid(2, 2, [255, 0, 0])),
+        builder.add(solid(2, 2, [0, 255, 0])),
+        builder.add(solid(4, 1, [0, 0, 255])),
+    ];
+    (builder.build(), sprites)
+}
+
+#[test]
+fn removed_pixels_are_reused() {
+    let renderer = renderer();
+    let (mut sheet, [red, green, _]) = full_sheet(&renderer);
+    assert_eq!(sheet.page_count(), 1);
+
+    sheet.remove(red).unwrap();
+    let yellow = sheet.insert(solid(1, 4, [255, 255, 0]));
+    assert_eq!(sheet.page_count(), 1);
+    assert_eq!(sheet.dimensions(yellow), Ok((1, 4)));
+
+    // The space of two adjacent sprites fits a sprite of their combined size.
+    sheet.remove(yellow).unwrap();
+    sheet.remove(green).unwrap();
+    let wide = sheet.insert(solid(4, 2, [255, 255, 255]));
+    assert_eq!(sheet.page_count(), 1);
+    assert_eq!(sheet.dimensions(wide), Ok((4, 2)));
+
+    sheet.insert(solid(1, 1, [0, 0, 0]));
+    assert_eq!(sheet.page_count(), 2);
+}
+
+#[test]
+fn stale_handles_are_detected() {
+    let mut renderer = renderer();
+    let (mut sheet, [red, ..]) = full_sheet(&renderer);
+    sheet.remove(red).unwrap();
+    assert!(!sheet.contains(red));
+    assert_eq!(sheet.dimensions(red), Err(HandleError::Removed));
+    assert_eq!(sheet.remove(red), Err(HandleError::Removed));
+
+    // The slot is reused, but the old handle stays invalid.
+    let green = sheet.insert(solid(2, 2, [0, 255, 0]));
+    assert!(sheet.contains(green));
+    assert_eq!(sheet.dimensions(red), Err(HandleError::Removed));
+    assert_eq!(
+        sheet.replace(red, solid(1, 1, [0, 0, 0])),
+        Err(HandleError::Removed)
+    );
+
+    let scene = Scene {
+        sprites: vec![SpriteInstance {
+            position: [0, 0],
+            sprite: red,
+            blend: BlendMode::Alpha,
+        }],
+        ..Default::default()
+    };
+    renderer.set_invalid_sprite_policy(InvalidSpritePolicy::Error);
+    let err = renderer.render(&[&sheet], &scene).err().unwrap();
+    assert!(matches!(
+        err,
+        RenderError::InvalidSprite(HandleError::Removed)
+    ));
+
+    let other = renderer.create_sprite_sheet_builder("other").build();
+    assert_eq!(other.dimensions(green), Err(HandleError::WrongSheet));
+}
+
+#[test]
+fn handles_stay_valid_after_compacting() {
+    let mut renderer = renderer();
+    let (mut sheet, [red, green, blue]) = full_sheet(&renderer);
+    let before = [red, blue].map(|sprite| drawn(&mut renderer, &sheet, sprite));
+
+    sheet.remove(green).unwrap();
+    sheet.compact();
+    assert!(sheet.contains(red) && sheet.contains(blue));
+    let after = [red, blue].map(|sprite| drawn(&mut renderer, &sheet, sprite));
+    assert_eq!(before, after);
+
+    // The freed pixels are at the end of the page, so an 8-pixel sprite still fits.
+    sheet.remove(red).unwrap();
+    sheet.compact();
+    let wide = sheet.insert(solid(4, 2, [255, 255, 255]));
+    assert_eq!(sheet.page_count(), 1);
+    assert_eq!(drawn(&mut renderer, &sheet, blue), after[1]);
+    assert_eq!(sheet.dimensions(wide), Ok((4, 2)));
+}
+
+#[test]
+fn removing_a_frame_forgets_the_animation_name() {
+    let renderer = renderer();
+    let mut sheet = renderer
+        .load_sprite_sheet("cached", packed(&renderer))
+        .unwrap();
+    let blink = sheet.animation("blink").unwrap();
+    sheet.remove(blink.get_frame(0)).unwrap();
+    assert!(sheet.animation("blink").is_none());
+    assert!(sheet.contains(blink.get_frame(1)));
+    assert!(sheet.sprite("square").is_some());
+}
+
+#[test]
+#[should_panic(expected = "animation has no frames")]
+fn empty_animations_are_rejected() {
+    let mut sheet = renderer().create_sprite_sheet_builder("empty").build();
+    sheet.insert_animation(Vec::new());
+}
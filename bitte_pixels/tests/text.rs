@@ -0,0 +1,101 @@
+//! Measuring and wrapping text with a bitmap font.
+
+use graphics::text::{Font, Glyph, TextLine};
+
+/// A font without sprites, where `a` is 2 pixels wide, `b` is 3, and space is 1.
+fn font() -> Font {
+    let mut font = Font::new(5);
+    for (c, advance) in [('a', 2), ('b', 3), (' ', 1), ('?', 4)] {
+        font.add_glyph(
+            c,
+            Glyph {
+                sprite: None,
+                advance,
+            },
+        );
+    }
+    font
+}
+
+/// The text and width of every line.
+fn lines<'a>(font: &Font, text: &'a str, max_width: Option<u32>) -> Vec<(&'a str, u32)> {
+    let lines = font.lines(text, max_width);
+    lines
+        .into_iter()
+        .map(|TextLine { range, width }| (&text[range], width))
+        .collect()
+}
+
+#[test]
+fn widths_add_advances_and_kerning() {
+    let mut font = font();
+    assert_eq!(font.width(""), 0);
+    assert_eq!(font.width("ab a"), 8);
+    font.set_kerning('a', 'b', -1);
+    assert_eq!(font.kerning('a', 'b'), -1);
+    assert_eq!(font.kerning('b', 'a'), 0);
+    assert_eq!(font.width("ab a"), 7);
+    assert_eq!(font.width("abab"), 8);
+    // Kerning can't make text narrower than nothing.
+    font.set_kerning('b', 'b', -10);
+    assert_eq!(font.width("bb"), 0);
+}
+
+#[test]
+fn missing_characters_use_the_fallback() {
+    let mut font = font();
+    assert_eq!(font.width("axa"), 4);
+    font.set_fallback(Some('?'));
+    assert_eq!(font.width("axa"), 8);
+    assert_eq!(font.measure("x", None), (4, 5));
+}
+
+#[test]
+fn lines_break_at_newlines() {
+    let font = font();
+    assert_eq!(
+        lines(&font, "ab\n\nba a  \n", None),
+        [("ab", 5), ("", 0), ("ba a  ", 8), ("", 0)]
+    );
+    assert_eq!(font.measure("ab\n\nba a  \n", None), (8, 20));
+    assert_eq!(font.measure("", None), (0, 5));
+}
+
+#[test]
+fn lines_wrap_between_words() {
+    let font = font();
+    assert_eq!(lines(&font, "aa aa aa", Some(9)), [("aa aa", 9), ("aa", 4)]);
+    assert_eq!(
+        lines(&font, "aa aa aa", Some(8)),
+        [("aa", 4), ("aa", 4), ("aa", 4)]
+    );
+    // Newlines still break lines, and wrapping starts over after them.
+    assert_eq!(
+        lines(&font, "aa\naa aa b", Some(9)),
+        [("aa", 4), ("aa aa", 9), ("b", 3)]
+    );
+    assert_eq!(font.measure("aa aa aa", Some(8)), (4, 15));
+}
+
+#[test]
+fn long_words_are_split_between_characters() {
+    let font = font();
+    assert_eq!(
+        lines(&font, "aaaaaaa", Some(5)),
+        [("aa", 4), ("aa", 4), ("aa", 4), ("a", 2)]
+    );
+    assert_eq!(
+        lines(&font, "b aaaaa b", Some(6)),
+        [("b", 3), ("aaa", 6), ("aa", 4), ("b", 3)]
+    );
+    // Characters wider than the line still take one line each.
+    assert_eq!(lines(&font, "bb", Some(2)), [("b", 3), ("b", 3)]);
+}
+
+#[test]
+fn wrapping_accounts_for_kerning() {
+    let mut font = font();
+    assert_eq!(lines(&font, "ab ab", Some(10)), [("ab", 5), ("ab", 5)]);
+    font.set_kerning('a', 'b', -1);
+    assert_eq!(lines(&font, "ab ab", Some(9)), [("ab ab", 9)]);
+}
@@ -0,0 +1,176 @@
+//! Maps in both formats, loaded from the fixtures in `tests/fixtures/tiled`.
+#![cfg(feature = "tiled")]
+
+use std::path::PathBuf;
+
+use graphics::{
+    software,
+    sprite::{SpriteHandle, SpriteInstance, SpriteSheet},
+    tiled::{self, ObjectShape, PropertyValue, TiledMap},
+    BlendMode, Color, Scene, Size,
+};
+
+const R: [u8; 4] = [255, 0, 0, 255];
+const G: [u8; 4] = [0, 255, 0, 255];
+const B: [u8; 4] = [0, 0, 255, 255];
+const W: [u8; 4] = [255, 255, 255, 255];
+const K: [u8; 4] = [0, 0, 0, 255];
+const Y: [u8; 4] = [255, 255, 0, 255];
+/// Magenta is the tileset's transparent color.
+const CLEAR: [u8; 4] = [0; 4];
+/// Half-transparent white, premultiplied in linear space.
+const HALF: [u8; 4] = [188, 188, 188, 128];
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/tiled")
+        .join(name)
+}
+
+fn load(name: &str) -> (software::Renderer, TiledMap, SpriteSheet) {
+    let renderer = software::Renderer::new(Size {
+        width: 2,
+        height: 2,
+    });
+    let mut builder = renderer.create_sprite_sheet_builder(name);
+    let map = tiled::load_map(fixture(name), &mut builder).unwrap();
+    let sheet = builder.build();
+    (renderer, map, sheet)
+}
+
+/// Pixels of a 2x2 tile from the top left, or `None` for an empty cell.
+fn tile_pixels(
+    renderer: &mut software::Renderer,
+    sheet: &SpriteSheet,
+    tile: Option<SpriteHandle>,
+) -> Option<Vec<[u8; 4]>> {
+    let scene = Scene {
+        background: Color::TRANSPARENT,
+        sprites: vec![SpriteInstance {
+            position: [0, 0],
+            sprite: tile?,
+            blend: BlendMode::Alpha,
+        }],
+        ..Default::default()
+    };
+    renderer.render(&[sheet], &scene).unwrap();
+    let pixels = renderer.canvas().chunks_exact(4);
+    Some(pixels.map(|pixel| pixel.try_into().unwrap()).collect())
+}
+
+/// Every layer of the fixtures holds the same tiles, in a different encoding.
+fn check_tile_layers(name: &str, layers: [&str; 2]) {
+    let (mut renderer, map, sheet) = load(name);
+    assert_eq!((map.size, map.tile_size), ((3, 2), (2, 2)));
+    // Rows are stored from the bottom, and the flipped tiles are on the bottom row.
+    let expected = [
+        Some(vec![G, R, W, B]),
+        Some(vec![B, W, R, G]),
+        Some(vec![R, B, G, W]),
+        Some(vec![R, G, B, W]),
+        Some(vec![CLEAR, HALF, K, Y]),
+        None,
+    ];
+    for layer in layers {
+        let layer = map.tile_layer(layer).unwrap();
+        let tiles: Vec<_> = layer
+            .tiles
+            .iter()
+            .map(|&tile| tile_pixels(&mut renderer, &sheet, tile))
+            .collect();
+        assert_eq!(tiles, expected, "layer {:?}", layer.name);
+    }
+    assert_eq!(
+        map.properties["music"],
+        PropertyValue::String("cave.ogg".into())
+    );
+}
+
+/// Object positions are flipped to the bottom-left corner, with the Y-axis pointing up.
+fn check_objects(name: &str) {
+    let (_, map, _) = load(name);
+    let objects = &map.object_layer("objects").unwrap().objects;
+    assert_eq!(objects.len(), 4);
+
+    let door = &objects[0];
+    assert_eq!(
+        (door.name.as_str(), door.class.as_str()),
+        ("door", "trigger")
+    );
+    assert_eq!((door.position, door.size), ([1.0, 2.5], [2.0, 1.0]));
+    assert!(matches!(door.shape, ObjectShape::Rectangle));
+    assert_eq!(door.properties["target"], PropertyValue::Int(4));
+
+    assert_eq!(objects[1].position, [3.0, 0.0]);
+    assert!(matches!(objects[1].shape, ObjectShape::Point));
+
+    assert_eq!(objects[2].position, [0.0, 2.0]);
+    let ObjectShape::Polygon(points) = &objects[2].shape else {
+        panic!("not a polygon");
+    };
+    assert_eq!(points, &[[0.0, 0.0], [2.0, 0.0], [2.0, -1.0]]);
+
+    // Tile objects are placed by their bottom-left corner in Tiled too.
+    assert_eq!(objects[3].position, [4.0, 0.0]);
+    assert!(matches!(objects[3].shape, ObjectShape::Tile(_)));
+}
+
+#[test]
+fn xml_maps_decode_csv_and_zlib_layers() {
+    check_tile_layers("map.tmx", ["csv", "zlib"]);
+}
+
+#[test]
+fn json_maps_decode_array_and_base64_layers() {
+    check_tile_layers("map.tmj", ["array", "base64"]);
+}
+
+#[test]
+fn xml_objects_are_flipped() {
+    check_objects("map.tmx");
+}
+
+#[test]
+fn json_objects_are_flipped() {
+    check_objects("map.tmj");
+}
+
+#[test]
+fn missing_files_are_reported() {
+    let renderer = software::Renderer::new(Size {
+        width: 1,
+        height: 1,
+    });
+    let mut builder = renderer.create_sprite_sheet_builder("missing");
+    let err = tiled::load_map(fixture("missing.tmx"), &mut builder)
+        .err()
+        .unwrap();
+    assert!(matches!(err, tiled::TiledError::Io(..)), "{err}");
+}
+
+fn load_err(name: &str) -> String {
+    let renderer = software::Renderer::new(Size {
+        width: 1,
+        height: 1,
+    });
+    let mut builder = renderer.create_sprite_sheet_builder(name);
+    match tiled::load_map(fixture(name), &mut builder) {
+        Err(tiled::TiledError::Invalid(err)) => err,
+        Err(err) => panic!("{name}: unexpected error {err}"),
+        Ok(_) => panic!("{name} loaded"),
+    }
+}
+
+#[test]
+fn empty_maps_are_rejected() {
+    assert_eq!(load_err("empty.tmj"), "map and tile sizes must not be zero");
+}
+
+#[test]
+fn tiles_past_the_image_are_rejected() {
+    // The tile's position overflows, as the tileset spaces its tiles far apart.
+    assert_eq!(
+        load_err("far_tile.tmx"),
+        "tile 1000000 is outside of its image"
+    );
+}
@@ -0,0 +1,119 @@
+//! TrueType fonts rasterized from the tiny fixtures in `tests/fixtures/fonts`.
+//! At 10 pixels per em, a font unit is a hundredth of a pixel.
+#![cfg(feature = "truetype")]
+
+use std::path::PathBuf;
+
+use graphics::{
+    software,
+    sprite::{SpriteInstance, SpriteSheet},
+    truetype::TrueTypeFont,
+    BlendMode, Color, Scene, Size,
+};
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/fonts")
+        .join(name)
+}
+
+fn setup() -> (software::Renderer, SpriteSheet, TrueTypeFont) {
+    let renderer = software::Renderer::new(Size {
+        width: 8,
+        height: 10,
+    });
+    let sheet = renderer.create_sprite_sheet_builder("glyphs").build();
+    let font = TrueTypeFont::load(fixture("tiny.ttf"), 10).unwrap();
+    (renderer, sheet, font)
+}
+
+/// The glyph's sprite as rows of `#` and `.`, from the top.
+/// Glyphs are drawn on the bottom of a line as tall as the canvas,
+/// so glyphs that reach the ascender start on the top row.
+fn glyph_rows(
+    renderer: &mut software::Renderer,
+    sheet: &SpriteSheet,
+    font: &TrueTypeFont,
+    c: char,
+) -> Vec<String> {
+    let sprite = font.font().glyph(c).unwrap().sprite.unwrap();
+    let (width, height) = sheet.dimensions(sprite).unwrap();
+    let scene = Scene {
+        background: Color::TRANSPARENT,
+        sprites: vec![SpriteInstance {
+            position: [0, 0],
+            sprite,
+            blend: BlendMode::Alpha,
+        }],
+        ..Default::default()
+    };
+    renderer.render(&[sheet], &scene).unwrap();
+    let canvas = renderer.canvas();
+    (0..height as usize)
+        .map(|y| {
+            (0..width as usize)
+                .map(|x| match canvas[(y * 8 + x) * 4 + 3] {
+                    0 => '.',
+                    _ => '#',
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn prepared_glyphs_are_rasterized_into_the_sheet() {
+    let (mut renderer, mut sheet, mut font) = setup();
+    assert!(font.font().glyph('A').is_none());
+    font.prepare(&mut sheet, "A");
+    let glyph = *font.font().glyph('A').unwrap();
+    assert_eq!(glyph.advance, 5);
+    assert_eq!(font.font().line_height(), 10);
+    // 4.3 pixels wide, so the last column is only 30% covered.
+    let rows = glyph_rows(&mut renderer, &sheet, &font, 'A');
+    assert_eq!(rows, vec!["####.".to_owned(); 8]);
+}
+
+#[test]
+fn threshold_sets_the_coverage_cut_off() {
+    let (mut renderer, mut sheet, mut font) = setup();
+    font.set_threshold(0.2);
+    font.prepare(&mut sheet, "A");
+    let rows = glyph_rows(&mut renderer, &sheet, &font, 'A');
+    assert_eq!(rows, vec!["#####".to_owned(); 8]);
+}
+
+#[test]
+fn fallbacks_supply_missing_chars() {
+    let (mut renderer, mut sheet, mut font) = setup();
+    font.prepare(&mut sheet, "AB");
+    assert!(font.font().glyph('B').is_none());
+
+    let fallback = std::fs::read(fixture("tiny_fallback.ttf")).unwrap();
+    font.add_fallback(fallback).unwrap();
+    font.prepare(&mut sheet, "AB");
+    let rows = glyph_rows(&mut renderer, &sheet, &font, 'B');
+    assert_eq!(rows, vec!["####".to_owned(); 8]);
+}
+
+#[test]
+fn pairs_of_the_same_face_are_kerned() {
+    let (_, mut sheet, mut font) = setup();
+    font.prepare(&mut sheet, "AVA");
+    assert_eq!(font.font().kerning('A', 'V'), -1);
+    assert_eq!(font.font().kerning('V', 'A'), 0);
+    assert_eq!(font.font().width("AV"), 9);
+}
+
+#[test]
+fn pairs_are_kerned_once_a_fallback_provides_them() {
+    let (_, mut sheet, mut font) = setup();
+    font.prepare(&mut sheet, "BC");
+    let fallback = std::fs::read(fixture("tiny_fallback.ttf")).unwrap();
+    font.add_fallback(fallback).unwrap();
+    font.prepare(&mut sheet, "BC");
+    assert_eq!(font.font().kerning('B', 'C'), -2);
+    // Glyphs of different faces aren't kerned.
+    font.prepare(&mut sheet, "AB");
+    assert_eq!(font.font().kerning('A', 'B'), 0);
+}